@@ -1,9 +1,21 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 
-use ratatui::style::{Color, Style};
+use ratatui::{
+    layout::Rect,
+    style::{Color, Modifier, Style},
+};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub const DEFAULT_FG_RGB: (u8, u8, u8) = (229, 229, 229);
+pub const DEFAULT_BG_RGB: (u8, u8, u8) = (0, 0, 0);
+/// The largest canvas side accepted from `--size`, the size prompt and loaded files.
+pub const MAX_CANVAS_DIM: u16 = 2000;
+/// A layer's opacity, in percent, when it hides nothing of itself.
+pub const FULL_OPACITY: u8 = 100;
+/// 4x4 ordered-dither thresholds, 0 to 15, shared by the dither fill and layer opacity.
+pub const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PaintColor {
     Default,
     Black,
@@ -14,6 +26,7 @@ pub enum PaintColor {
     Magenta,
     Cyan,
     White,
+    Rgb { r: u8, g: u8, b: u8 },
 }
 
 impl PaintColor {
@@ -28,20 +41,121 @@ impl PaintColor {
             PaintColor::Magenta => Color::Magenta,
             PaintColor::Cyan => Color::Cyan,
             PaintColor::White => Color::White,
+            PaintColor::Rgb { r, g, b } => Color::Rgb(r, g, b),
         }
     }
 
-    pub fn name(self) -> &'static str {
+    pub fn to_rgb(self) -> Option<(u8, u8, u8)> {
         match self {
-            PaintColor::Default => "Default",
-            PaintColor::Black => "Black",
-            PaintColor::Red => "Red",
-            PaintColor::Green => "Green",
-            PaintColor::Yellow => "Yellow",
-            PaintColor::Blue => "Blue",
-            PaintColor::Magenta => "Magenta",
-            PaintColor::Cyan => "Cyan",
-            PaintColor::White => "White",
+            PaintColor::Default => None,
+            PaintColor::Black => Some((0, 0, 0)),
+            PaintColor::Red => Some((205, 0, 0)),
+            PaintColor::Green => Some((0, 205, 0)),
+            PaintColor::Yellow => Some((205, 205, 0)),
+            PaintColor::Blue => Some((0, 0, 238)),
+            PaintColor::Magenta => Some((205, 0, 205)),
+            PaintColor::Cyan => Some((0, 205, 205)),
+            PaintColor::White => Some((229, 229, 229)),
+            PaintColor::Rgb { r, g, b } => Some((r, g, b)),
+        }
+    }
+
+    pub fn luminance(self) -> u8 {
+        let (r, g, b) = self.to_rgb().unwrap_or(DEFAULT_FG_RGB);
+        ((299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000) as u8
+    }
+
+    pub fn luminance_band(self, bands: usize) -> usize {
+        let bands = bands.max(1);
+        (self.luminance() as usize * bands / 256).min(bands - 1)
+    }
+
+    // Accepts a quick-palette digit (0 = default), a color name, or #RGB/#RRGGBB hex.
+    pub fn parse(input: &str) -> Option<PaintColor> {
+        let input = input.trim();
+        if let Some(index) = input
+            .chars()
+            .next()
+            .filter(|_| input.len() == 1)
+            .and_then(|c| c.to_digit(10))
+        {
+            let index = index as u8;
+            return if index == 0 {
+                Some(PaintColor::Default)
+            } else {
+                Self::from_quick_index(index)
+            };
+        }
+
+        let named = std::iter::once(PaintColor::Default).chain(Self::quick_palette());
+        for color in named {
+            if color.name().eq_ignore_ascii_case(input) {
+                return Some(color);
+            }
+        }
+
+        Self::parse_hex(input)
+    }
+
+    pub fn parse_hex(input: &str) -> Option<PaintColor> {
+        let hex = input.trim();
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let channel = |s: &str| u8::from_str_radix(s, 16).ok();
+        match hex.len() {
+            3 => {
+                let r = channel(&hex[0..1])?;
+                let g = channel(&hex[1..2])?;
+                let b = channel(&hex[2..3])?;
+                Some(PaintColor::Rgb {
+                    r: r * 17,
+                    g: g * 17,
+                    b: b * 17,
+                })
+            }
+            6 => Some(PaintColor::Rgb {
+                r: channel(&hex[0..2])?,
+                g: channel(&hex[2..4])?,
+                b: channel(&hex[4..6])?,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn lighten(self, step_percent: u8) -> PaintColor {
+        let step = step_percent.min(100) as u16;
+        self.map_rgb(|c| c + ((255 - c) * step + 50) / 100)
+    }
+
+    pub fn darken(self, step_percent: u8) -> PaintColor {
+        let step = step_percent.min(100) as u16;
+        self.map_rgb(|c| c - (c * step + 50) / 100)
+    }
+
+    fn map_rgb(self, f: impl Fn(u16) -> u16) -> PaintColor {
+        let (r, g, b) = self.to_rgb().unwrap_or(DEFAULT_FG_RGB);
+        PaintColor::Rgb {
+            r: f(r as u16).min(255) as u8,
+            g: f(g as u16).min(255) as u8,
+            b: f(b as u16).min(255) as u8,
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            PaintColor::Default => "Default".to_string(),
+            PaintColor::Black => "Black".to_string(),
+            PaintColor::Red => "Red".to_string(),
+            PaintColor::Green => "Green".to_string(),
+            PaintColor::Yellow => "Yellow".to_string(),
+            PaintColor::Blue => "Blue".to_string(),
+            PaintColor::Magenta => "Magenta".to_string(),
+            PaintColor::Cyan => "Cyan".to_string(),
+            PaintColor::White => "White".to_string(),
+            PaintColor::Rgb { r, g, b } => format!("#{:02X}{:02X}{:02X}", r, g, b),
         }
     }
 
@@ -58,6 +172,35 @@ impl PaintColor {
         ]
     }
 
+    /// Entry `index` of the xterm 256-color palette: the eight named colors, their bright
+    /// variants, a 6x6x6 color cube and a 24-step gray ramp.
+    pub fn xterm(index: u8) -> PaintColor {
+        const BRIGHT: [(u8, u8, u8); 8] = [
+            (127, 127, 127),
+            (255, 0, 0),
+            (0, 255, 0),
+            (255, 255, 0),
+            (92, 92, 255),
+            (255, 0, 255),
+            (0, 255, 255),
+            (255, 255, 255),
+        ];
+        let level = |step: u8| if step == 0 { 0 } else { 55 + 40 * step };
+        let (r, g, b) = match index {
+            0..=7 => return Self::quick_palette()[index as usize],
+            8..=15 => BRIGHT[index as usize - 8],
+            16..=231 => {
+                let cube = index - 16;
+                (level(cube / 36), level(cube / 6 % 6), level(cube % 6))
+            }
+            _ => {
+                let gray = 8 + 10 * (index - 232);
+                (gray, gray, gray)
+            }
+        };
+        PaintColor::Rgb { r, g, b }
+    }
+
     pub fn from_quick_index(index: u8) -> Option<PaintColor> {
         let palette = Self::quick_palette();
         let idx = index.checked_sub(1)? as usize;
@@ -65,11 +208,78 @@ impl PaintColor {
     }
 }
 
+/// Text attributes of a cell as bit flags. Saved as a plain number, and left out of JSON
+/// entirely when no attribute is set, so older files load unchanged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CellAttrs(u8);
+
+impl CellAttrs {
+    pub const NONE: Self = Self(0);
+    pub const BOLD: Self = Self(1);
+    pub const ITALIC: Self = Self(2);
+    pub const UNDERLINE: Self = Self(4);
+    pub const REVERSED: Self = Self(8);
+
+    /// Every attribute with the letter `.tpaint` files use for it and a display name.
+    pub const ALL: [(Self, char, &'static str); 4] = [
+        (Self::BOLD, 'b', "bold"),
+        (Self::ITALIC, 'i', "italic"),
+        (Self::UNDERLINE, 'u', "underline"),
+        (Self::REVERSED, 'r', "reversed"),
+    ];
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn toggled(self, other: Self) -> Self {
+        Self(self.0 ^ other.0)
+    }
+
+    /// The attributes as `.tpaint` letters, e.g. `"bu"`.
+    pub fn letters(self) -> String {
+        Self::ALL
+            .iter()
+            .filter(|(attr, _, _)| self.contains(*attr))
+            .map(|(_, letter, _)| *letter)
+            .collect()
+    }
+
+    pub fn from_letters(letters: &str) -> Option<Self> {
+        letters.chars().try_fold(Self::NONE, |attrs, letter| {
+            let (attr, _, _) = Self::ALL.iter().find(|(_, l, _)| *l == letter)?;
+            Some(Self(attrs.0 | attr.0))
+        })
+    }
+
+    pub fn modifier(self) -> Modifier {
+        let mut modifier = Modifier::empty();
+        for (attr, flag) in [
+            (Self::BOLD, Modifier::BOLD),
+            (Self::ITALIC, Modifier::ITALIC),
+            (Self::UNDERLINE, Modifier::UNDERLINED),
+            (Self::REVERSED, Modifier::REVERSED),
+        ] {
+            if self.contains(attr) {
+                modifier |= flag;
+            }
+        }
+        modifier
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PaintCell {
     pub ch: char,
     pub fg: PaintColor,
     pub bg: Option<PaintColor>,
+    #[serde(default, skip_serializing_if = "CellAttrs::is_empty")]
+    pub attrs: CellAttrs,
 }
 
 impl Default for PaintCell {
@@ -84,15 +294,49 @@ impl PaintCell {
             ch: ' ',
             fg: PaintColor::Default,
             bg: None,
+            attrs: CellAttrs::NONE,
         }
     }
 
     pub fn new(ch: char, fg: PaintColor) -> Self {
-        Self { ch, fg, bg: None }
+        Self {
+            ch,
+            fg,
+            bg: None,
+            attrs: CellAttrs::NONE,
+        }
+    }
+
+    /// The cell a brush stroke with `ch` leaves, on `bg` if there is one. A space has no glyph to
+    /// show `color` with, so unless `literal_space` it paints a block of background instead: `bg`
+    /// when set, otherwise `color`. A space with nothing to show comes out blank; callers refuse
+    /// such strokes, as only the Eraser clears cells.
+    pub fn brush(ch: char, color: PaintColor, bg: Option<PaintColor>, literal_space: bool) -> Self {
+        let block = (ch == ' ' && !literal_space && color != PaintColor::Default).then_some(color);
+        Self::new(ch, color).with_bg(bg.or(block))
+    }
+
+    pub fn with_attrs(self, attrs: CellAttrs) -> Self {
+        Self { attrs, ..self }
+    }
+
+    /// Replaces the background, treating the default color as no background at all.
+    pub fn with_bg(self, bg: Option<PaintColor>) -> Self {
+        Self {
+            bg: bg.filter(|color| *color != PaintColor::Default),
+            ..self
+        }
+    }
+
+    /// Underlined or reversed spaces still show, so only attribute-free spaces are blank.
+    pub fn is_blank(self) -> bool {
+        self.ch == ' ' && self.bg.is_none() && self.attrs.is_empty()
     }
 
     pub fn style(self) -> Style {
-        let mut style = Style::default().fg(self.fg.to_ratatui());
+        let mut style = Style::default()
+            .fg(self.fg.to_ratatui())
+            .add_modifier(self.attrs.modifier());
         if let Some(bg) = self.bg {
             style = style.bg(bg.to_ratatui());
         }
@@ -100,13 +344,108 @@ impl PaintCell {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Canvas {
     width: u16,
     height: u16,
     cells: Vec<PaintCell>,
 }
 
+/// One sheet of a drawing. Layers share one size and are shown bottom to top; a hidden layer
+/// keeps its cells but is left out of the view and of flattened exports.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layer {
+    pub canvas: Canvas,
+    #[serde(default = "default_visible")]
+    pub visible: bool,
+    /// Shown in the status bar; unnamed layers go by their number alone.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    /// How much of the layer shows, in percent. Cells have no alpha, so a partly opaque layer
+    /// shows that share of its cells in an ordered-dither pattern.
+    #[serde(default = "full_opacity", skip_serializing_if = "is_full_opacity")]
+    pub opacity: u8,
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+fn full_opacity() -> u8 {
+    FULL_OPACITY
+}
+
+fn is_full_opacity(opacity: &u8) -> bool {
+    *opacity >= FULL_OPACITY
+}
+
+impl Layer {
+    pub fn new(canvas: Canvas) -> Self {
+        Self {
+            canvas,
+            visible: true,
+            name: String::new(),
+            opacity: FULL_OPACITY,
+        }
+    }
+}
+
+/// Stacks canvases bottom to top, where any non-blank cell hides the ones below. Each canvas
+/// comes with its opacity in percent, and only the cells `dither_shows` picks for it are drawn.
+/// The result is `width` x `height`; each canvas is read where it overlaps that.
+pub fn flatten<'a>(
+    width: u16,
+    height: u16,
+    canvases: impl IntoIterator<Item = (&'a Canvas, u8)>,
+) -> Canvas {
+    let mut flat = Canvas::new(width, height);
+    for (canvas, opacity) in canvases {
+        for y in 0..height.min(canvas.height()) {
+            for x in 0..width.min(canvas.width()) {
+                let cell = canvas.get(x, y);
+                if !cell.is_blank() && dither_shows(x, y, opacity) {
+                    flat.set(x, y, cell);
+                }
+            }
+        }
+    }
+    flat
+}
+
+/// Whether a layer at `opacity` percent shows its cell at (x, y): a 50% layer shows every other
+/// cell, 25% one in four, evenly spread by the Bayer matrix.
+pub fn dither_shows(x: u16, y: u16, opacity: u8) -> bool {
+    let threshold = BAYER_4X4[y as usize % 4][x as usize % 4];
+    u32::from(threshold) * 100 < u32::from(opacity.min(FULL_OPACITY)) * 16
+}
+
+/// A rectangular block of cells lifted off the canvas, stored row by row.
+#[derive(Debug, Clone)]
+pub struct CellBlock {
+    pub width: u16,
+    pub height: u16,
+    pub cells: Vec<PaintCell>,
+}
+
+impl CellBlock {
+    pub fn get(&self, x: u16, y: u16) -> PaintCell {
+        self.cells[y as usize * self.width as usize + x as usize]
+    }
+}
+
+/// What a canvas holds and roughly how much memory it takes.
+#[derive(Debug, Clone, Copy)]
+pub struct CanvasStats {
+    pub width: u16,
+    pub height: u16,
+    pub cells: usize,
+    /// Cells that aren't blank.
+    pub painted: usize,
+    /// Distinct glyph and background colors among the painted cells.
+    pub colors: usize,
+    pub bytes: usize,
+}
+
 impl Canvas {
     pub fn new(width: u16, height: u16) -> Self {
         let width = width.max(1);
@@ -156,6 +495,135 @@ impl Canvas {
         self.cells[idx] = cell;
     }
 
+    pub fn copy_region(&self, rect: Rect) -> CellBlock {
+        let mut cells = Vec::with_capacity(rect.width as usize * rect.height as usize);
+        for y in rect.y..rect.y.saturating_add(rect.height) {
+            for x in rect.x..rect.x.saturating_add(rect.width) {
+                cells.push(self.get(x, y));
+            }
+        }
+        CellBlock {
+            width: rect.width,
+            height: rect.height,
+            cells,
+        }
+    }
+
+    /// Mirrors the grid left to right.
+    pub fn flip_horizontal(&mut self) {
+        let width = self.width as usize;
+        for row in self.cells.chunks_mut(width) {
+            row.reverse();
+        }
+    }
+
+    /// Mirrors the grid top to bottom.
+    pub fn flip_vertical(&mut self) {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        for y in 0..height / 2 {
+            let (top, bottom) = self.cells.split_at_mut((height - 1 - y) * width);
+            top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+        }
+    }
+
+    /// Turns the grid a quarter turn clockwise, swapping width and height.
+    pub fn rotate_cw(&mut self) {
+        let (width, height) = (self.width, self.height);
+        let mut rotated = Canvas::new(height, width);
+        for y in 0..height {
+            for x in 0..width {
+                rotated.set(height - 1 - y, x, self.get(x, y));
+            }
+        }
+        *self = rotated;
+    }
+
+    /// Turns the grid a quarter turn counter-clockwise, swapping width and height.
+    pub fn rotate_ccw(&mut self) {
+        let (width, height) = (self.width, self.height);
+        let mut rotated = Canvas::new(height, width);
+        for y in 0..height {
+            for x in 0..width {
+                rotated.set(y, width - 1 - x, self.get(x, y));
+            }
+        }
+        *self = rotated;
+    }
+
+    /// The tightest rectangle holding every non-blank cell, or `None` for a blank canvas.
+    pub fn content_bounds(&self) -> Option<Rect> {
+        let mut bounds: Option<(u16, u16, u16, u16)> = None;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.get(x, y).is_blank() {
+                    continue;
+                }
+                bounds = Some(match bounds {
+                    Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                    None => (x, y, x, y),
+                });
+            }
+        }
+        bounds.map(|(x0, y0, x1, y1)| Rect::new(x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+    }
+
+    /// Replaces the canvas with the cells inside `rect`.
+    pub fn crop(&mut self, rect: Rect) {
+        let block = self.copy_region(rect);
+        self.width = block.width.max(1);
+        self.height = block.height.max(1);
+        self.cells = block.cells;
+        self.cells.resize(
+            self.width as usize * self.height as usize,
+            PaintCell::blank(),
+        );
+    }
+
+    /// How many cells are stored; only a deserialized canvas can disagree with `width * height`.
+    pub fn cell_count(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn stats(&self) -> CanvasStats {
+        let mut colors = HashSet::new();
+        let mut painted = 0;
+        for cell in self.cells.iter().filter(|cell| !cell.is_blank()) {
+            painted += 1;
+            if cell.ch != ' ' {
+                colors.insert(cell.fg);
+            }
+            colors.extend(cell.bg);
+        }
+        CanvasStats {
+            width: self.width,
+            height: self.height,
+            cells: self.cells.len(),
+            painted,
+            colors: colors.len(),
+            bytes: self.memory_usage(),
+        }
+    }
+
+    /// Approximate heap and inline size in bytes.
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>() + self.cells.capacity() * std::mem::size_of::<PaintCell>()
+    }
+
+    pub fn content_extent(&self) -> (u16, u16) {
+        let mut extent_w = 0;
+        let mut extent_h = 0;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if !self.get(x, y).is_blank() {
+                    extent_w = extent_w.max(x + 1);
+                    extent_h = extent_h.max(y + 1);
+                }
+            }
+        }
+        (extent_w, extent_h)
+    }
+
     pub fn resize_preserve(&mut self, new_width: u16, new_height: u16) {
         let new_width = new_width.max(1);
         let new_height = new_height.max(1);
@@ -190,25 +658,156 @@ pub struct CellChange {
     pub after: PaintCell,
 }
 
+/// The whole layer stack before and after a change that reshapes it, such as a resize or
+/// adding a layer.
+#[derive(Debug, Clone)]
+pub struct CanvasSnapshot {
+    pub before: Vec<Layer>,
+    pub after: Vec<Layer>,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Operation {
     pub changes: Vec<CellChange>,
+    pub snapshot: Option<Box<CanvasSnapshot>>,
+    /// The layer `changes` belong to.
+    pub layer: usize,
 }
 
 impl Operation {
+    pub fn replace_layers(before: Vec<Layer>, after: Vec<Layer>) -> Self {
+        Self {
+            changes: Vec::new(),
+            snapshot: Some(Box::new(CanvasSnapshot { before, after })),
+            layer: 0,
+        }
+    }
+
+    /// The same cell changes, recorded against `layer`.
+    pub fn on_layer(self, layer: usize) -> Self {
+        Self { layer, ..self }
+    }
+
+    /// Records the cells that differ between two same-sized canvases.
+    pub fn diff(before: &Canvas, after: &Canvas) -> Self {
+        let mut changes = Vec::new();
+        for y in 0..after.height() {
+            for x in 0..after.width() {
+                let (old, new) = (before.get(x, y), after.get(x, y));
+                if old != new {
+                    changes.push(CellChange {
+                        x,
+                        y,
+                        before: old,
+                        after: new,
+                    });
+                }
+            }
+        }
+        Self {
+            changes,
+            snapshot: None,
+            layer: 0,
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.changes.is_empty()
+        self.changes.is_empty() && self.snapshot.is_none()
+    }
+
+    /// Approximate size in bytes, counting both canvases of a snapshot.
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.changes.capacity() * std::mem::size_of::<CellChange>()
+            + self.snapshot.as_ref().map_or(0, |snapshot| {
+                snapshot
+                    .before
+                    .iter()
+                    .chain(&snapshot.after)
+                    .map(|layer| layer.canvas.memory_usage())
+                    .sum()
+            })
+    }
+
+    /// True when undoing this swaps the whole layer stack rather than individual cells.
+    pub fn is_whole_canvas(&self) -> bool {
+        self.snapshot.is_some()
     }
 
-    pub fn apply_before(&self, canvas: &mut Canvas) {
+    /// The rectangle enclosing every changed cell; the full canvas for snapshots.
+    pub fn bounds(&self) -> Option<Rect> {
+        if let Some(snapshot) = &self.snapshot {
+            let canvas = &snapshot.after.first()?.canvas;
+            return Some(Rect::new(0, 0, canvas.width(), canvas.height()));
+        }
+        let first = self.changes.first()?;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (first.x, first.y, first.x, first.y);
         for change in &self.changes {
-            canvas.set(change.x, change.y, change.before);
+            min_x = min_x.min(change.x);
+            min_y = min_y.min(change.y);
+            max_x = max_x.max(change.x);
+            max_y = max_y.max(change.y);
         }
+        Some(Rect::new(
+            min_x,
+            min_y,
+            max_x - min_x + 1,
+            max_y - min_y + 1,
+        ))
     }
 
-    pub fn apply_after(&self, canvas: &mut Canvas) {
-        for change in &self.changes {
-            canvas.set(change.x, change.y, change.after);
+    /// Folds a later cell-by-cell operation into this one: a cell both touch keeps this
+    /// operation's `before` and the later one's `after`, and cells that end up unchanged are
+    /// dropped. Operations with a snapshot or on different layers can't be merged and come back
+    /// as `Err`.
+    pub fn merge(self, later: Operation) -> Result<Operation, (Operation, Operation)> {
+        if self.snapshot.is_some() || later.snapshot.is_some() || self.layer != later.layer {
+            return Err((self, later));
+        }
+
+        let mut merged: HashMap<(u16, u16), CellChange> = self
+            .changes
+            .into_iter()
+            .map(|change| ((change.x, change.y), change))
+            .collect();
+        for change in later.changes {
+            merged
+                .entry((change.x, change.y))
+                .and_modify(|earlier| earlier.after = change.after)
+                .or_insert(change);
+        }
+
+        let mut changes: Vec<_> = merged
+            .into_values()
+            .filter(|change| change.before != change.after)
+            .collect();
+        changes.sort_by_key(|c| (c.y, c.x));
+        Ok(Operation {
+            changes,
+            snapshot: None,
+            layer: self.layer,
+        })
+    }
+
+    pub fn apply_before(&self, layers: &mut Vec<Layer>) {
+        if let Some(snapshot) = &self.snapshot {
+            *layers = snapshot.before.clone();
+        }
+        if let Some(layer) = layers.get_mut(self.layer) {
+            for change in &self.changes {
+                layer.canvas.set(change.x, change.y, change.before);
+            }
+        }
+    }
+
+    pub fn apply_after(&self, layers: &mut Vec<Layer>) {
+        if let Some(snapshot) = &self.snapshot {
+            *layers = snapshot.after.clone();
+        }
+        if let Some(layer) = layers.get_mut(self.layer) {
+            for change in &self.changes {
+                layer.canvas.set(change.x, change.y, change.after);
+            }
         }
     }
 }
@@ -255,10 +854,22 @@ impl OperationBuilder {
         canvas.set(ux, uy, new_cell);
     }
 
+    /// Puts back every cell this builder has touched and forgets the changes.
+    pub fn revert(&mut self, canvas: &mut Canvas) {
+        for change in self.changes.values() {
+            canvas.set(change.x, change.y, change.before);
+        }
+        self.changes.clear();
+    }
+
     pub fn into_operation(self) -> Operation {
         let mut changes: Vec<_> = self.changes.into_values().collect();
         changes.sort_by_key(|c| (c.y, c.x));
-        Operation { changes }
+        Operation {
+            changes,
+            snapshot: None,
+            layer: 0,
+        }
     }
 }
 
@@ -278,6 +889,37 @@ impl History {
         }
     }
 
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn undo_len(&self) -> usize {
+        self.undo_stack.len()
+    }
+
+    pub fn redo_len(&self) -> usize {
+        self.redo_stack.len()
+    }
+
+    /// Undoable operations, the one the next undo reverts first.
+    pub fn undo_ops(&self) -> impl Iterator<Item = &Operation> {
+        self.undo_stack.iter().rev()
+    }
+
+    /// Redoable operations, the one the next redo reapplies first.
+    pub fn redo_ops(&self) -> impl Iterator<Item = &Operation> {
+        self.redo_stack.iter().rev()
+    }
+
+    /// Approximate size in bytes of every undo and redo step.
+    pub fn memory_usage(&self) -> usize {
+        self.undo_ops()
+            .chain(self.redo_ops())
+            .map(Operation::memory_usage)
+            .sum()
+    }
+
+    /// Once the stack is full, pushing drops the oldest operation.
     pub fn push(&mut self, op: Operation) {
         if op.is_empty() {
             return;
@@ -291,28 +933,177 @@ impl History {
         }
     }
 
-    pub fn undo(&mut self, canvas: &mut Canvas) -> bool {
+    /// Like `push`, but with `merge` set the operation joins the newest undo step instead of
+    /// becoming its own, so a burst of small edits undoes at once. Nothing merges across an
+    /// undo (while there is something to redo) or with whole-canvas operations.
+    pub fn push_merged(&mut self, op: Operation, merge: bool) {
+        if !merge || !self.redo_stack.is_empty() {
+            self.push(op);
+            return;
+        }
+        let Some(last) = self.undo_stack.pop_back() else {
+            self.push(op);
+            return;
+        };
+
+        match last.merge(op) {
+            Ok(merged) if merged.is_empty() => {}
+            Ok(merged) => self.undo_stack.push_back(merged),
+            Err((last, op)) => {
+                self.undo_stack.push_back(last);
+                self.push(op);
+            }
+        }
+    }
+
+    pub fn undo(&mut self, layers: &mut Vec<Layer>) -> bool {
         let Some(op) = self.undo_stack.pop_back() else {
             return false;
         };
 
-        op.apply_before(canvas);
+        op.apply_before(layers);
         self.redo_stack.push(op);
         true
     }
 
-    pub fn redo(&mut self, canvas: &mut Canvas) -> bool {
+    pub fn redo(&mut self, layers: &mut Vec<Layer>) -> bool {
         let Some(op) = self.redo_stack.pop() else {
             return false;
         };
 
-        op.apply_after(canvas);
+        op.apply_after(layers);
         self.undo_stack.push_back(op);
         true
     }
+}
 
-    pub fn clear(&mut self) {
-        self.undo_stack.clear();
-        self.redo_stack.clear();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x2 canvas where every cell has a different glyph, so any misplaced cell shows.
+    fn numbered_canvas() -> Canvas {
+        let mut canvas = Canvas::new(3, 2);
+        for (i, ch) in "abcdef".chars().enumerate() {
+            canvas.set(
+                i as u16 % 3,
+                i as u16 / 3,
+                PaintCell::new(ch, PaintColor::Default),
+            );
+        }
+        canvas
+    }
+
+    #[test]
+    fn four_clockwise_turns_restore_the_canvas() {
+        let mut canvas = numbered_canvas();
+        canvas.rotate_cw();
+        assert_eq!((canvas.width(), canvas.height()), (2, 3));
+        assert_eq!(canvas.get(1, 0).ch, 'a');
+        assert_eq!(canvas.get(0, 0).ch, 'd');
+        for _ in 0..3 {
+            canvas.rotate_cw();
+        }
+        assert_eq!(canvas, numbered_canvas());
+    }
+
+    #[test]
+    fn counter_clockwise_undoes_clockwise() {
+        let mut canvas = numbered_canvas();
+        canvas.rotate_cw();
+        canvas.rotate_ccw();
+        assert_eq!(canvas, numbered_canvas());
+        canvas.rotate_ccw();
+        canvas.rotate_cw();
+        assert_eq!(canvas, numbered_canvas());
+    }
+
+    #[test]
+    fn gray_gradient_splits_into_even_luminance_bands() {
+        let gray = |v| PaintColor::Rgb { r: v, g: v, b: v };
+        let bands: Vec<usize> = [0, 63, 64, 127, 128, 191, 192, 255]
+            .into_iter()
+            .map(|v| gray(v).luminance_band(4))
+            .collect();
+        assert_eq!(bands, [0, 0, 1, 1, 2, 2, 3, 3]);
+        assert_eq!(PaintColor::Default.luminance_band(4), 3);
+        assert_eq!(PaintColor::Blue.luminance_band(4), 0);
+        assert_eq!(gray(255).luminance_band(1), 0);
+    }
+
+    #[test]
+    fn layer_opacity_shows_that_share_of_cells() {
+        let mut canvas = Canvas::new(4, 4);
+        for y in 0..4 {
+            for x in 0..4 {
+                canvas.set(x, y, PaintCell::new('#', PaintColor::Default));
+            }
+        }
+        for (opacity, shown) in [(0, 0), (25, 4), (50, 8), (75, 12), (100, 16)] {
+            let flat = flatten(4, 4, [(&canvas, opacity)]);
+            assert_eq!(flat.stats().painted, shown, "{opacity}%");
+        }
+    }
+
+    #[test]
+    fn layers_without_opacity_load_fully_opaque() {
+        let layer: Layer =
+            serde_json::from_value(serde_json::json!({ "canvas": Canvas::new(1, 1) })).unwrap();
+        assert_eq!(layer.opacity, FULL_OPACITY);
+
+        let mut faded = Layer::new(Canvas::new(1, 1));
+        faded.opacity = 50;
+        let text = serde_json::to_string(&faded).unwrap();
+        assert_eq!(serde_json::from_str::<Layer>(&text).unwrap(), faded);
+        assert!(!serde_json::to_string(&layer).unwrap().contains("opacity"));
+    }
+
+    #[test]
+    fn merged_operations_keep_the_earliest_before_and_latest_after() {
+        let mut canvas = Canvas::new(3, 1);
+        let (a, b, c) = (
+            PaintCell::new('a', PaintColor::Red),
+            PaintCell::new('b', PaintColor::Green),
+            PaintCell::new('c', PaintColor::Blue),
+        );
+        let mut first = OperationBuilder::new();
+        first.apply(&mut canvas, 0, 0, a);
+        first.apply(&mut canvas, 1, 0, a);
+        let mut second = OperationBuilder::new();
+        second.apply(&mut canvas, 0, 0, b);
+        second.apply(&mut canvas, 1, 0, PaintCell::blank());
+        second.apply(&mut canvas, 2, 0, c);
+
+        let merged = first
+            .into_operation()
+            .merge(second.into_operation())
+            .unwrap();
+        let changes: Vec<_> = merged
+            .changes
+            .iter()
+            .map(|change| (change.x, change.before, change.after))
+            .collect();
+        // Cell 1 was painted and erased again, so the merged step doesn't touch it.
+        assert_eq!(
+            changes,
+            [(0, PaintCell::blank(), b), (2, PaintCell::blank(), c)]
+        );
+
+        let mut layers = vec![Layer::new(canvas.clone())];
+        merged.apply_before(&mut layers);
+        assert_eq!(layers[0].canvas, Canvas::new(3, 1));
+        merged.apply_after(&mut layers);
+        assert_eq!(layers[0].canvas, canvas);
+    }
+
+    #[test]
+    fn operations_on_different_layers_do_not_merge() {
+        let mut canvas = Canvas::new(1, 1);
+        let mut first = OperationBuilder::new();
+        first.apply(&mut canvas, 0, 0, PaintCell::new('a', PaintColor::Red));
+        let mut second = OperationBuilder::new();
+        second.apply(&mut canvas, 0, 0, PaintCell::new('b', PaintColor::Red));
+        let later = second.into_operation().on_layer(1);
+        assert!(first.into_operation().merge(later).is_err());
     }
 }
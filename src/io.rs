@@ -1,24 +1,352 @@
 use std::{
     fs,
+    io::{Read, Write},
     path::{Path, PathBuf},
 };
 
 use anyhow::{Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use ratatui::layout::Rect;
+use serde::Serialize;
 
-use crate::canvas::{Canvas, PaintCell, PaintColor};
+use crate::{
+    canvas::{
+        flatten, Canvas, CellAttrs, Layer, PaintCell, PaintColor, DEFAULT_BG_RGB, DEFAULT_FG_RGB,
+    },
+    keymap::KeyMap,
+};
+
+pub const MAX_EXPORT_CELL_PX: u32 = 64;
+/// Raster exports above this many pixels are refused rather than allocated.
+const MAX_EXPORT_PIXELS: u64 = 100_000_000;
+const ASCII_HEADER_PREFIX: &str = "# termipaint ";
+const TPAINT_HEADER_PREFIX: &str = "tpaint 1 ";
+/// Canvas width, in cells, that images are scaled to when no other width is given.
+pub const DEFAULT_IMAGE_WIDTH: u16 = 80;
+/// Characters an imported image's pixels map to, from darkest to brightest.
+#[cfg(feature = "png")]
+const IMAGE_RAMP: &[char] = &[' ', '.', ':', '-', '=', '+', '*', '#', '%', '@'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub cell_width: u32,
+    pub cell_height: u32,
+    pub ascii_header: bool,
+    /// Text color SVG exports use for cells drawn in the default color.
+    pub svg_default_fill: (u8, u8, u8),
+    /// Colors PPM and PNG exports use for the default foreground and for cells without a
+    /// background.
+    pub default_fg: (u8, u8, u8),
+    pub default_bg: (u8, u8, u8),
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        // Terminal cells are roughly twice as tall as they are wide.
+        Self {
+            cell_width: 8,
+            cell_height: 16,
+            ascii_header: false,
+            svg_default_fill: DEFAULT_FG_RGB,
+            default_fg: DEFAULT_FG_RGB,
+            default_bg: DEFAULT_BG_RGB,
+        }
+    }
+}
+
+impl ExportOptions {
+    pub fn parse_cell_size(input: &str) -> Option<(u32, u32)> {
+        let (w, h) = input.trim().split_once(['x', 'X'])?;
+        let w = w.trim().parse::<u32>().ok()?;
+        let h = h.trim().parse::<u32>().ok()?;
+        let valid = 1..=MAX_EXPORT_CELL_PX;
+        if !valid.contains(&w) || !valid.contains(&h) {
+            return None;
+        }
+        Some((w, h))
+    }
+}
 
-pub fn save_canvas(path: &Path, canvas: &Canvas) -> Result<()> {
+/// JSON saves keep every layer; the other formats get the visible layers flattened into one.
+pub fn save_layers(path: &Path, layers: &[Layer], options: &ExportOptions) -> Result<()> {
+    if is_gzip_json(path) {
+        return save_json_gz(path, layers);
+    }
+    if extension_lower(path).as_deref() == Some("json") {
+        return save_json(path, layers);
+    }
+    save_canvas(path, &flatten_visible(layers), options)
+}
+
+/// Like `save_layers`, but writes only the cells inside `region` of every layer.
+pub fn save_region(
+    path: &Path,
+    layers: &[Layer],
+    region: Rect,
+    options: &ExportOptions,
+) -> Result<()> {
+    let cropped: Vec<Layer> = layers
+        .iter()
+        .map(|layer| {
+            let mut layer = layer.clone();
+            layer.canvas.crop(region);
+            layer
+        })
+        .collect();
+    save_layers(path, &cropped, options)
+}
+
+fn save_canvas(path: &Path, canvas: &Canvas, options: &ExportOptions) -> Result<()> {
     match extension_lower(path).as_deref() {
-        Some("json") => save_json(path, canvas),
-        _ => save_ascii(path, canvas),
+        Some("ppm") => save_ppm(path, canvas, options),
+        Some("png") => save_png(path, canvas, options),
+        Some("svg") => save_svg(path, canvas, options),
+        Some("html" | "htm") => save_html(path, canvas),
+        Some("tpaint") => save_tpaint(path, canvas),
+        _ => save_ascii(path, canvas, options.ascii_header),
     }
 }
 
-pub fn load_canvas(path: &Path) -> Result<Canvas> {
+pub fn is_image_export(path: &Path) -> bool {
+    matches!(
+        extension_lower(path).as_deref(),
+        Some("ppm" | "png" | "svg" | "html" | "htm")
+    )
+}
+
+/// Loads a drawing as its layer stack; every format but JSON gives a single layer. Drawings
+/// wider or taller than `max_dim` cells (usually `MAX_CANVAS_DIM`) are refused.
+pub fn load_layers(path: &Path, max_dim: u16) -> Result<Vec<Layer>> {
+    if is_gzip_json(path) {
+        return load_json_gz(path, max_dim);
+    }
+    if extension_lower(path).as_deref() == Some("json") {
+        return load_json(path, max_dim);
+    }
+    load_canvas(path, max_dim).map(|canvas| vec![Layer::new(canvas)])
+}
+
+/// Loads a drawing as one canvas, with a layered save's visible layers flattened.
+pub fn load_canvas(path: &Path, max_dim: u16) -> Result<Canvas> {
+    if is_gzip_json(path) {
+        return load_json_gz(path, max_dim).map(|layers| flatten_visible(&layers));
+    }
     match extension_lower(path).as_deref() {
-        Some("json") => load_json(path),
-        _ => load_ascii(path),
+        Some("json") => load_json(path, max_dim).map(|layers| flatten_visible(&layers)),
+        Some("tpaint") => load_tpaint(path, max_dim),
+        Some("png" | "jpg" | "jpeg") => load_image(path, DEFAULT_IMAGE_WIDTH, max_dim),
+        _ => load_ascii(path, max_dim),
+    }
+}
+
+/// Whether `load_canvas` reads this path as a picture to turn into ASCII art.
+pub fn is_image_file(path: &Path) -> bool {
+    matches!(
+        extension_lower(path).as_deref(),
+        Some("png" | "jpg" | "jpeg")
+    )
+}
+
+/// Turns a PNG or JPEG into ASCII art `width` cells wide. Each cell takes the average color of
+/// the pixels it covers as its foreground and a character from `IMAGE_RAMP` by brightness;
+/// mostly transparent pixels stay blank. Rows are halved because cells are about twice as tall
+/// as they are wide. Neither side goes past `max_dim` cells.
+#[cfg(feature = "png")]
+pub fn load_image(path: &Path, width: u16, max_dim: u16) -> Result<Canvas> {
+    let image = image::ImageReader::open(path)
+        .with_context(|| format!("failed to read {}", path.display()))?
+        .with_guessed_format()
+        .with_context(|| format!("failed to read {}", path.display()))?
+        .decode()
+        .with_context(|| format!("failed to decode image {}", path.display()))?;
+
+    let max_dim = max_dim.max(1);
+    let width = u32::from(width.clamp(1, max_dim));
+    let height = (u64::from(image.height()) * u64::from(width)
+        / (u64::from(image.width().max(1)) * 2))
+        .clamp(1, u64::from(max_dim)) as u32;
+    let pixels = image
+        .resize_exact(width, height, image::imageops::FilterType::Triangle)
+        .to_rgba8();
+
+    let mut canvas = Canvas::new(width as u16, height as u16);
+    for (x, y, pixel) in pixels.enumerate_pixels() {
+        let [r, g, b, a] = pixel.0;
+        if a < 128 {
+            continue;
+        }
+        let luma = (299 * u32::from(r) + 587 * u32::from(g) + 114 * u32::from(b)) / 1000;
+        let ch = IMAGE_RAMP[luma as usize * (IMAGE_RAMP.len() - 1) / 255];
+        if ch != ' ' {
+            canvas.set(
+                x as u16,
+                y as u16,
+                PaintCell::new(ch, PaintColor::Rgb { r, g, b }),
+            );
+        }
+    }
+    Ok(canvas)
+}
+
+#[cfg(not(feature = "png"))]
+pub fn load_image(path: &Path, _width: u16, _max_dim: u16) -> Result<Canvas> {
+    anyhow::bail!(
+        "cannot read {}: built without the `png` feature",
+        path.display()
+    )
+}
+
+/// Where crash-recovery autosaves go: `$XDG_CACHE_HOME/termipaint` or `~/.cache/termipaint`.
+pub fn autosave_dir() -> Option<PathBuf> {
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_dir.join("termipaint"))
+}
+
+/// This instance's autosave, named after its process ID so instances running side by side never
+/// overwrite or remove each other's recovery file.
+pub fn autosave_path() -> Option<PathBuf> {
+    Some(autosave_dir()?.join(format!("autosave-{}.json", std::process::id())))
+}
+
+/// Takes an exclusive lock on the autosave's `.lock` file and holds it until the returned file is
+/// dropped, which tells other instances this autosave still belongs to a running instance.
+pub fn lock_autosave(path: &Path) -> Option<fs::File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).ok()?;
     }
+    let lock = fs::File::create(path.with_extension("lock")).ok()?;
+    lock.try_lock().ok()?;
+    Some(lock)
+}
+
+/// Removes an autosave along with its lock file.
+pub fn remove_autosave(path: &Path) {
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(path.with_extension("lock"));
+}
+
+/// The newest autosave in `dir` left behind by an instance that is no longer running.
+pub fn orphaned_autosave(dir: &Path) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name();
+            let pid: u32 = name
+                .to_str()?
+                .strip_prefix("autosave-")?
+                .strip_suffix(".json")?
+                .parse()
+                .ok()?;
+            if pid == std::process::id() || !is_left_behind(&entry.path()) {
+                return None;
+            }
+            Some((entry.metadata().ok()?.modified().ok()?, entry.path()))
+        })
+        .max()
+        .map(|(_, path)| path)
+}
+
+/// Whether the instance that wrote this autosave has exited: its lock file is there and nobody
+/// holds the lock. Without a lock file there is no telling, so the autosave is left alone.
+fn is_left_behind(path: &Path) -> bool {
+    fs::OpenOptions::new()
+        .write(true)
+        .open(path.with_extension("lock"))
+        .is_ok_and(|lock| lock.try_lock().is_ok())
+}
+
+/// Writes an autosave through a temporary file so a crash mid-write never leaves a torn recovery file.
+pub fn write_autosave(path: &Path, layers: &[Layer]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let temp = path.with_extension("tmp.json");
+    save_json(&temp, layers)?;
+    fs::rename(&temp, path).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// The settings directory: `$XDG_CONFIG_HOME/termipaint` or `~/.config/termipaint`.
+fn config_dir() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("termipaint"))
+}
+
+/// Where the recent-files list lives, in the settings directory.
+pub fn recent_files_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("recent.json"))
+}
+
+/// Where the user palette lives, in the settings directory.
+pub fn palette_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("palette.json"))
+}
+
+/// Where custom key bindings live, in the settings directory.
+pub fn keybindings_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("keybindings.toml"))
+}
+
+/// Reads custom key bindings; a missing file means the default keys.
+pub fn load_keymap(path: &Path) -> Result<KeyMap> {
+    if !path.exists() {
+        return Ok(KeyMap::default());
+    }
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    KeyMap::from_toml(&text).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// Reads the user palette, a JSON list of color names or `#RRGGBB` strings; a missing file is
+/// just an empty palette.
+pub fn load_palette(path: &Path) -> Result<Vec<PaintColor>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let names: Vec<String> = serde_json::from_str(&text)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    names
+        .iter()
+        .map(|name| {
+            PaintColor::parse(name)
+                .with_context(|| format!("{}: unknown color '{name}'", path.display()))
+        })
+        .collect()
+}
+
+pub fn save_palette(path: &Path, colors: &[PaintColor]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let names: Vec<String> = colors.iter().map(|color| color.name()).collect();
+    let text = serde_json::to_string_pretty(&names).context("failed to serialize palette")?;
+    fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Reads the recent-files list; a missing file is just an empty list.
+pub fn load_recent_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+pub fn save_recent_files(path: &Path, files: &[PathBuf]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let text = serde_json::to_string_pretty(files).context("failed to serialize recent files")?;
+    fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
 }
 
 pub fn parse_path(input: &str, fallback: &str) -> PathBuf {
@@ -29,22 +357,179 @@ pub fn parse_path(input: &str, fallback: &str) -> PathBuf {
     PathBuf::from(trimmed)
 }
 
-fn save_json(path: &Path, canvas: &Canvas) -> Result<()> {
-    let text =
-        serde_json::to_string_pretty(canvas).context("failed to serialize canvas to JSON")?;
+/// The JSON format version saves write. Version 1 is the bare canvas object older builds wrote
+/// and version 2 wraps that one canvas; both still load as a single layer, with defaults for any
+/// fields added since. Version 3 holds the layer stack.
+const JSON_FORMAT_VERSION: u64 = 3;
+
+/// A JSON save: the layer stack, bottom first, with the format version it was written in.
+#[derive(Serialize)]
+struct JsonFile<'a> {
+    version: u64,
+    layers: &'a [Layer],
+}
+
+impl<'a> JsonFile<'a> {
+    fn latest(layers: &'a [Layer]) -> Self {
+        Self {
+            version: JSON_FORMAT_VERSION,
+            layers,
+        }
+    }
+}
+
+/// Reads either a versioned save or a legacy bare canvas, refusing versions newer than this build.
+fn layers_from_json(text: &str, max_dim: u16) -> Result<Vec<Layer>> {
+    let mut value: serde_json::Value = serde_json::from_str(text)?;
+    let Some(version) = value.get("version") else {
+        let canvas = validate_canvas(serde_json::from_value(value)?, max_dim)?;
+        return Ok(vec![Layer::new(canvas)]);
+    };
+    let version = version
+        .as_u64()
+        .context("the format version is not a number")?;
+    if version > JSON_FORMAT_VERSION {
+        anyhow::bail!(
+            "saved in format version {version}, but this build reads up to {JSON_FORMAT_VERSION}; \
+             update TermiPaint to open it"
+        );
+    }
+    if version < 3 {
+        let canvas = value
+            .get_mut("canvas")
+            .map(serde_json::Value::take)
+            .context("missing \"canvas\"")?;
+        let canvas = validate_canvas(serde_json::from_value(canvas)?, max_dim)?;
+        return Ok(vec![Layer::new(canvas)]);
+    }
+    let layers = value
+        .get_mut("layers")
+        .map(serde_json::Value::take)
+        .context("missing \"layers\"")?;
+    validate_layers(serde_json::from_value(layers)?, max_dim)
+}
+
+/// Every layer must be a valid canvas, and all of them the same size.
+fn validate_layers(layers: Vec<Layer>, max_dim: u16) -> Result<Vec<Layer>> {
+    let Some(first) = layers.first() else {
+        anyhow::bail!("the file has no layers");
+    };
+    let size = (first.canvas.width(), first.canvas.height());
+    layers
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut layer)| {
+            layer.canvas = validate_canvas(layer.canvas, max_dim)
+                .with_context(|| format!("layer {}", index + 1))?;
+            if (layer.canvas.width(), layer.canvas.height()) != size {
+                anyhow::bail!(
+                    "layer {} is {}x{}, but the first layer is {}x{}",
+                    index + 1,
+                    layer.canvas.width(),
+                    layer.canvas.height(),
+                    size.0,
+                    size.1
+                );
+            }
+            Ok(layer)
+        })
+        .collect()
+}
+
+/// The visible layers stacked into one canvas, for formats that only hold one.
+pub fn flatten_visible(layers: &[Layer]) -> Canvas {
+    let (width, height) = layers.first().map_or((1, 1), |layer| {
+        (layer.canvas.width(), layer.canvas.height())
+    });
+    let visible = layers.iter().filter(|layer| layer.visible);
+    flatten(
+        width,
+        height,
+        visible.map(|layer| (&layer.canvas, layer.opacity)),
+    )
+}
+
+/// Rejects sizes outside 1..=`max_dim` per side before anything that large is allocated.
+fn check_canvas_size(width: usize, height: usize, max_dim: u16) -> Result<()> {
+    let valid = 1..=max_dim as usize;
+    if !valid.contains(&width) || !valid.contains(&height) {
+        anyhow::bail!("canvas size {width}x{height} is outside 1x1 to {max_dim}x{max_dim}");
+    }
+    Ok(())
+}
+
+/// Checks what deserializing can't: a size within `max_dim` per side and exactly one cell per
+/// position, so a malformed file is an error instead of a panic later.
+fn validate_canvas(canvas: Canvas, max_dim: u16) -> Result<Canvas> {
+    let (width, height) = (canvas.width() as usize, canvas.height() as usize);
+    check_canvas_size(width, height, max_dim)?;
+    if canvas.cell_count() != width * height {
+        anyhow::bail!(
+            "a {width}x{height} canvas needs {} cells, but the file has {}",
+            width * height,
+            canvas.cell_count()
+        );
+    }
+    Ok(canvas)
+}
+
+fn save_json(path: &Path, layers: &[Layer]) -> Result<()> {
+    let text = serde_json::to_string_pretty(&JsonFile::latest(layers))
+        .context("failed to serialize canvas to JSON")?;
     fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
 }
 
-fn load_json(path: &Path) -> Result<Canvas> {
+fn load_json(path: &Path, max_dim: u16) -> Result<Vec<Layer>> {
     let text = fs::read_to_string(path)
         .with_context(|| format!("failed to read JSON file {}", path.display()))?;
-    let canvas = serde_json::from_str::<Canvas>(&text)
-        .with_context(|| format!("failed to parse JSON file {}", path.display()))?;
-    Ok(canvas)
+    layers_from_json(&text, max_dim)
+        .with_context(|| format!("failed to parse JSON file {}", path.display()))
+}
+
+/// `.json.gz` and `.tpz` hold the same JSON as `.json`, gzip-compressed.
+fn is_gzip_json(path: &Path) -> bool {
+    match extension_lower(path).as_deref() {
+        Some("tpz") => true,
+        Some("gz") => path
+            .file_stem()
+            .is_some_and(|stem| extension_lower(Path::new(stem)).as_deref() == Some("json")),
+        _ => false,
+    }
+}
+
+fn save_json_gz(path: &Path, layers: &[Layer]) -> Result<()> {
+    let file =
+        fs::File::create(path).with_context(|| format!("failed to write {}", path.display()))?;
+    let mut encoder = GzEncoder::new(std::io::BufWriter::new(file), Compression::default());
+    serde_json::to_writer(&mut encoder, &JsonFile::latest(layers))
+        .context("failed to serialize canvas to JSON")?;
+    encoder
+        .finish()
+        .and_then(|mut writer| writer.flush())
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn load_json_gz(path: &Path, max_dim: u16) -> Result<Vec<Layer>> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("failed to read compressed file {}", path.display()))?;
+    let mut text = String::new();
+    GzDecoder::new(std::io::BufReader::new(file))
+        .read_to_string(&mut text)
+        .with_context(|| format!("failed to decompress {}", path.display()))?;
+    layers_from_json(&text, max_dim)
+        .with_context(|| format!("failed to parse JSON in {}", path.display()))
 }
 
-fn save_ascii(path: &Path, canvas: &Canvas) -> Result<()> {
+fn save_ascii(path: &Path, canvas: &Canvas, header: bool) -> Result<()> {
     let mut out = String::new();
+    if header {
+        out.push_str(&format!(
+            "{}{}x{}\n",
+            ASCII_HEADER_PREFIX,
+            canvas.width(),
+            canvas.height()
+        ));
+    }
     for y in 0..canvas.height() {
         for x in 0..canvas.width() {
             out.push(canvas.get(x, y).ch);
@@ -56,45 +541,755 @@ fn save_ascii(path: &Path, canvas: &Canvas) -> Result<()> {
     fs::write(path, out).with_context(|| format!("failed to write {}", path.display()))
 }
 
-fn load_ascii(path: &Path) -> Result<Canvas> {
+fn load_ascii(path: &Path, max_dim: u16) -> Result<Canvas> {
     let text = fs::read_to_string(path)
         .with_context(|| format!("failed to read ASCII file {}", path.display()))?;
 
-    let lines: Vec<&str> = if text.is_empty() {
-        vec![""]
-    } else {
-        text.lines().collect()
-    };
+    let mut lines = ascii_rows(&text);
 
-    let height = lines.len().max(1) as u16;
-    let width = lines
-        .iter()
-        .map(|line| line.chars().count() as u16)
-        .max()
-        .unwrap_or(1)
-        .max(1);
+    let header = lines.first().and_then(|line| parse_ascii_header(line));
+    let (width, height) = match header {
+        Some((width, height)) => {
+            check_canvas_size(width.into(), height.into(), max_dim)
+                .with_context(|| format!("{} is too large to load", path.display()))?;
+            lines.remove(0);
+            (width, height)
+        }
+        None => {
+            let height = lines.len().max(1);
+            let width = lines
+                .iter()
+                .map(|line| line.chars().count())
+                .max()
+                .unwrap_or(1)
+                .max(1);
+            check_canvas_size(width, height, max_dim)
+                .with_context(|| format!("{} is too large to load", path.display()))?;
+            (width as u16, height as u16)
+        }
+    };
 
     let mut canvas = Canvas::new(width, height);
 
     for (y, line) in lines.iter().enumerate() {
         for (x, ch) in line.chars().enumerate() {
-            canvas.set(
-                x as u16,
-                y as u16,
-                PaintCell {
-                    ch,
-                    fg: PaintColor::Default,
-                    bg: None,
-                },
-            );
+            canvas.set(x as u16, y as u16, PaintCell::new(ch, PaintColor::Default));
+        }
+    }
+
+    Ok(canvas)
+}
+
+/// Splits plain text into canvas rows. Both `\n` and `\r\n` end a row, even mixed in one file,
+/// and a single newline at the very end is treated as a terminator rather than an extra empty row.
+/// Everything else is kept, so trailing spaces and a last row of only spaces survive a round trip.
+fn ascii_rows(text: &str) -> Vec<&str> {
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    text.split('\n')
+        .map(|row| row.strip_suffix('\r').unwrap_or(row))
+        .collect()
+}
+
+fn parse_ascii_header(line: &str) -> Option<(u16, u16)> {
+    let size = line.trim_end().strip_prefix(ASCII_HEADER_PREFIX)?;
+    let (w, h) = size.split_once('x')?;
+    let w = w.parse::<u16>().ok().filter(|w| *w > 0)?;
+    let h = h.parse::<u16>().ok().filter(|h| *h > 0)?;
+    Some((w, h))
+}
+
+/// Writes the line-based `.tpaint` format: a `tpaint 1 WxH` header, then one line per row.
+/// Each row starts in the default style and `{FG}` or `{FG/BG}` switches the style for the cells
+/// after it (colors by name or `#RRGGBB`), optionally followed by `+` and attribute letters
+/// (`b`old, `i`talic, `u`nderline, `r`eversed). A literal `{` or `\` in the art is written as
+/// `\{` or `\\`.
+fn save_tpaint(path: &Path, canvas: &Canvas) -> Result<()> {
+    let mut out = format!(
+        "{}{}x{}\n",
+        TPAINT_HEADER_PREFIX,
+        canvas.width(),
+        canvas.height()
+    );
+    for y in 0..canvas.height() {
+        let mut style = (PaintColor::Default, None, CellAttrs::NONE);
+        for x in 0..canvas.width() {
+            let cell = canvas.get(x, y);
+            if (cell.fg, cell.bg, cell.attrs) != style {
+                style = (cell.fg, cell.bg, cell.attrs);
+                out.push('{');
+                out.push_str(&cell.fg.name());
+                if let Some(bg) = cell.bg {
+                    out.push('/');
+                    out.push_str(&bg.name());
+                }
+                if !cell.attrs.is_empty() {
+                    out.push('+');
+                    out.push_str(&cell.attrs.letters());
+                }
+                out.push('}');
+            }
+            if matches!(cell.ch, '{' | '\\') {
+                out.push('\\');
+            }
+            out.push(cell.ch);
+        }
+        out.push('\n');
+    }
+    fs::write(path, out).with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn load_tpaint(path: &Path, max_dim: u16) -> Result<Canvas> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("failed to read tpaint file {}", path.display()))?;
+    let mut lines = text.lines();
+    let (width, height) = lines
+        .next()
+        .and_then(|line| line.strip_prefix(TPAINT_HEADER_PREFIX))
+        .and_then(|size| size.trim().split_once('x'))
+        .and_then(|(w, h)| Some((w.parse::<u16>().ok()?, h.parse::<u16>().ok()?)))
+        .filter(|(w, h)| *w > 0 && *h > 0)
+        .with_context(|| format!("{} has no 'tpaint 1 WxH' header", path.display()))?;
+    check_canvas_size(width.into(), height.into(), max_dim)
+        .with_context(|| format!("{} is too large to load", path.display()))?;
+
+    let mut canvas = Canvas::new(width, height);
+    for (y, line) in lines.take(height as usize).enumerate() {
+        let line_no = y + 2;
+        let mut style = PaintCell::blank();
+        let mut x = 0u16;
+        let mut chars = line.chars();
+        while let Some(ch) = chars.next() {
+            let ch = match ch {
+                '{' => {
+                    let token: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    let (colors, attrs) = match token.split_once('+') {
+                        Some((colors, letters)) => (
+                            colors,
+                            CellAttrs::from_letters(letters).with_context(|| {
+                                format!("line {}: unknown attributes '{}'", line_no, letters)
+                            })?,
+                        ),
+                        None => (token.as_str(), CellAttrs::NONE),
+                    };
+                    style.attrs = attrs;
+                    let (fg, bg) = match colors.split_once('/') {
+                        Some((fg, bg)) => (fg, Some(bg)),
+                        None => (colors, None),
+                    };
+                    let parse = |name: &str| {
+                        PaintColor::parse(name)
+                            .with_context(|| format!("line {}: unknown color '{}'", line_no, name))
+                    };
+                    style.fg = parse(fg)?;
+                    style = style.with_bg(bg.map(parse).transpose()?);
+                    continue;
+                }
+                '\\' => chars
+                    .next()
+                    .with_context(|| format!("line {}: dangling '\\'", line_no))?,
+                ch => ch,
+            };
+            if x < width {
+                canvas.set(x, y as u16, PaintCell { ch, ..style });
+            }
+            x = x.saturating_add(1);
         }
     }
 
     Ok(canvas)
 }
 
+/// The pixel size of a raster export, or an error when it would exceed `MAX_EXPORT_PIXELS`.
+fn raster_size(canvas: &Canvas, cell_w: u32, cell_h: u32) -> Result<(u32, u32)> {
+    let img_w = u32::from(canvas.width()).checked_mul(cell_w);
+    let img_h = u32::from(canvas.height()).checked_mul(cell_h);
+    let pixels = img_w
+        .zip(img_h)
+        .and_then(|(w, h)| u64::from(w).checked_mul(u64::from(h)));
+    match (img_w, img_h, pixels) {
+        (Some(w), Some(h), Some(pixels)) if pixels <= MAX_EXPORT_PIXELS => Ok((w, h)),
+        _ => anyhow::bail!(
+            "a {}x{} canvas at {cell_w}x{cell_h} px per cell is larger than the \
+             {MAX_EXPORT_PIXELS}-pixel export limit; use a smaller cell size",
+            canvas.width(),
+            canvas.height()
+        ),
+    }
+}
+
+fn save_ppm(path: &Path, canvas: &Canvas, options: &ExportOptions) -> Result<()> {
+    let cell_w = options.cell_width.max(1);
+    let cell_h = options.cell_height.max(1);
+    let (img_w, img_h) = raster_size(canvas, cell_w, cell_h)?;
+
+    let mut out = format!("P6\n{} {}\n255\n", img_w, img_h).into_bytes();
+    out.reserve(img_w as usize * img_h as usize * 3);
+
+    for py in 0..img_h {
+        for px in 0..img_w {
+            let cell = canvas.get((px / cell_w) as u16, (py / cell_h) as u16);
+            let cell = unreversed(cell, options.default_fg, options.default_bg);
+            let (r, g, b) = cell_pixel_rgb(cell, options);
+            out.extend_from_slice(&[r, g, b]);
+        }
+    }
+
+    fs::write(path, out).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// One `<rect>` per cell background and one `<text>` per glyph, on the same grid as the raster
+/// exports. Each glyph is stretched to exactly one cell so any monospace font lines up.
+fn save_svg(path: &Path, canvas: &Canvas, options: &ExportOptions) -> Result<()> {
+    use std::fmt::Write as _;
+
+    let cell_w = options.cell_width.max(1);
+    let cell_h = options.cell_height.max(1);
+    let img_w = canvas.width() as u32 * cell_w;
+    let img_h = canvas.height() as u32 * cell_h;
+    let hex = |(r, g, b): (u8, u8, u8)| format!("#{:02x}{:02x}{:02x}", r, g, b);
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{img_w}" height="{img_h}" viewBox="0 0 {img_w} {img_h}">"#
+    );
+    let _ = writeln!(
+        out,
+        r#"<rect width="100%" height="100%" fill="{}"/>"#,
+        hex(DEFAULT_BG_RGB)
+    );
+    let _ = writeln!(
+        out,
+        r#"<g font-family="monospace" font-size="{}" text-anchor="start">"#,
+        cell_h * 5 / 6
+    );
+
+    // Backgrounds go first so a glyph that overhangs its cell is never covered by a neighbour's.
+    for y in 0..canvas.height() {
+        for x in 0..canvas.width() {
+            let cell = unreversed(canvas.get(x, y), options.svg_default_fill, DEFAULT_BG_RGB);
+            if let Some(bg) = cell.bg.and_then(|bg| bg.to_rgb()) {
+                let (px, py) = (x as u32 * cell_w, y as u32 * cell_h);
+                let _ = writeln!(
+                    out,
+                    r#"<rect x="{px}" y="{py}" width="{cell_w}" height="{cell_h}" fill="{}"/>"#,
+                    hex(bg)
+                );
+            }
+        }
+    }
+
+    for y in 0..canvas.height() {
+        for x in 0..canvas.width() {
+            let cell = unreversed(canvas.get(x, y), options.svg_default_fill, DEFAULT_BG_RGB);
+            let (px, py) = (x as u32 * cell_w, y as u32 * cell_h);
+            if cell.ch != ' ' {
+                let fill = cell.fg.to_rgb().unwrap_or(options.svg_default_fill);
+                let mut attrs = String::new();
+                if cell.attrs.contains(CellAttrs::BOLD) {
+                    attrs.push_str(r#" font-weight="bold""#);
+                }
+                if cell.attrs.contains(CellAttrs::ITALIC) {
+                    attrs.push_str(r#" font-style="italic""#);
+                }
+                if cell.attrs.contains(CellAttrs::UNDERLINE) {
+                    attrs.push_str(r#" text-decoration="underline""#);
+                }
+                let _ = writeln!(
+                    out,
+                    r#"<text x="{px}" y="{}" fill="{}"{attrs} textLength="{cell_w}" lengthAdjust="spacingAndGlyphs">{}</text>"#,
+                    py + cell_h * 4 / 5,
+                    hex(fill),
+                    xml_escape(cell.ch)
+                );
+            }
+        }
+    }
+
+    out.push_str("</g>\n</svg>\n");
+    fs::write(path, out).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// A `<pre>` block where each run of identically styled cells shares one `<span>`.
+fn save_html(path: &Path, canvas: &Canvas) -> Result<()> {
+    let css = |(r, g, b): (u8, u8, u8)| format!("#{:02x}{:02x}{:02x}", r, g, b);
+    // A space shows no foreground (unless underlined), so ignore it there to let runs merge
+    // across gaps; likewise bold and italic.
+    let style_of = |cell: PaintCell| {
+        let cell = unreversed(cell, DEFAULT_FG_RGB, DEFAULT_BG_RGB);
+        let attrs = if cell.ch != ' ' {
+            cell.attrs
+        } else if cell.attrs.contains(CellAttrs::UNDERLINE) {
+            CellAttrs::UNDERLINE
+        } else {
+            CellAttrs::NONE
+        };
+        let fg = if attrs.is_empty() && cell.ch == ' ' {
+            None
+        } else {
+            cell.fg.to_rgb()
+        };
+        (fg, cell.bg.and_then(|bg| bg.to_rgb()), attrs)
+    };
+
+    let mut out =
+        String::from("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"></head>\n<body>\n");
+    out.push_str(&format!(
+        "<pre style=\"background:{};color:{};font-family:monospace;line-height:1\">",
+        css(DEFAULT_BG_RGB),
+        css(DEFAULT_FG_RGB)
+    ));
+
+    for y in 0..canvas.height() {
+        let mut x = 0;
+        while x < canvas.width() {
+            let style = style_of(canvas.get(x, y));
+            let mut text = String::new();
+            while x < canvas.width() && style_of(canvas.get(x, y)) == style {
+                text.push_str(&xml_escape(canvas.get(x, y).ch));
+                x += 1;
+            }
+
+            let mut declarations = Vec::new();
+            if let Some(fg) = style.0 {
+                declarations.push(format!("color:{}", css(fg)));
+            }
+            if let Some(bg) = style.1 {
+                declarations.push(format!("background:{}", css(bg)));
+            }
+            if style.2.contains(CellAttrs::BOLD) {
+                declarations.push("font-weight:bold".to_string());
+            }
+            if style.2.contains(CellAttrs::ITALIC) {
+                declarations.push("font-style:italic".to_string());
+            }
+            if style.2.contains(CellAttrs::UNDERLINE) {
+                declarations.push("text-decoration:underline".to_string());
+            }
+            if declarations.is_empty() {
+                out.push_str(&text);
+            } else {
+                out.push_str(&format!(
+                    "<span style=\"{}\">{}</span>",
+                    declarations.join(";"),
+                    text
+                ));
+            }
+        }
+        out.push('\n');
+    }
+
+    out.push_str("</pre>\n</body>\n</html>\n");
+    fs::write(path, out).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Escapes a glyph for XML or HTML text.
+fn xml_escape(ch: char) -> String {
+    match ch {
+        '<' => "&lt;".to_string(),
+        '>' => "&gt;".to_string(),
+        '&' => "&amp;".to_string(),
+        '"' => "&quot;".to_string(),
+        '\'' => "&apos;".to_string(),
+        _ => ch.to_string(),
+    }
+}
+
+#[cfg(feature = "png")]
+fn save_png(path: &Path, canvas: &Canvas, options: &ExportOptions) -> Result<()> {
+    let cell_w = options.cell_width.max(1);
+    let cell_h = options.cell_height.max(1);
+    let (img_w, img_h) = raster_size(canvas, cell_w, cell_h)?;
+
+    let image = image::RgbImage::from_fn(img_w, img_h, |px, py| {
+        let cell = canvas.get((px / cell_w) as u16, (py / cell_h) as u16);
+        // The 8x8 glyph is stretched over the whole cell, nearest neighbour.
+        let gx = (px % cell_w) * 8 / cell_w;
+        let gy = (py % cell_h) * 8 / cell_h;
+        let cell = unreversed(cell, options.default_fg, options.default_bg);
+        image::Rgb(glyph_pixel_rgb(cell, gx, gy, options).into())
+    });
+
+    image
+        .save_with_format(path, image::ImageFormat::Png)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(not(feature = "png"))]
+fn save_png(path: &Path, _canvas: &Canvas, _options: &ExportOptions) -> Result<()> {
+    anyhow::bail!(
+        "cannot write {}: built without the `png` feature",
+        path.display()
+    )
+}
+
+#[cfg(feature = "png")]
+fn glyph_pixel_rgb(cell: PaintCell, gx: u32, gy: u32, options: &ExportOptions) -> (u8, u8, u8) {
+    use font8x8::UnicodeFonts;
+
+    let bg = cell
+        .bg
+        .and_then(|bg| bg.to_rgb())
+        .unwrap_or(options.default_bg);
+    let fg = cell.fg.to_rgb().unwrap_or(options.default_fg);
+    if gy == 7 && cell.attrs.contains(CellAttrs::UNDERLINE) {
+        return fg;
+    }
+    if cell.ch == ' ' {
+        return bg;
+    }
+
+    let glyph = font8x8::BASIC_FONTS
+        .get(cell.ch)
+        .or_else(|| font8x8::LATIN_FONTS.get(cell.ch))
+        .or_else(|| font8x8::BOX_FONTS.get(cell.ch))
+        .or_else(|| font8x8::BLOCK_FONTS.get(cell.ch))
+        .or_else(|| font8x8::MISC_FONTS.get(cell.ch));
+    let Some(rows) = glyph else {
+        return cell_pixel_rgb(cell, options);
+    };
+
+    // Bit 0 of each glyph row is its leftmost pixel.
+    if rows[gy as usize] & (1 << gx) != 0 {
+        fg
+    } else {
+        bg
+    }
+}
+
+// Without a font, a painted cell is drawn as a solid block of its foreground color.
+fn cell_pixel_rgb(cell: PaintCell, options: &ExportOptions) -> (u8, u8, u8) {
+    if cell.ch != ' ' {
+        return cell.fg.to_rgb().unwrap_or(options.default_fg);
+    }
+    cell.bg
+        .and_then(|bg| bg.to_rgb())
+        .unwrap_or(options.default_bg)
+}
+
+/// Bakes the reversed attribute into swapped colors, for formats that have no such notion.
+/// Default colors swap as the format's `default_fg` and `default_bg`.
+fn unreversed(cell: PaintCell, default_fg: (u8, u8, u8), default_bg: (u8, u8, u8)) -> PaintCell {
+    if !cell.attrs.contains(CellAttrs::REVERSED) {
+        return cell;
+    }
+    let rgb = |(r, g, b): (u8, u8, u8)| PaintColor::Rgb { r, g, b };
+    PaintCell {
+        fg: rgb(cell.bg.and_then(|bg| bg.to_rgb()).unwrap_or(default_bg)),
+        bg: Some(rgb(cell.fg.to_rgb().unwrap_or(default_fg))),
+        attrs: cell.attrs.toggled(CellAttrs::REVERSED),
+        ..cell
+    }
+}
+
 fn extension_lower(path: &Path) -> Option<String> {
     path.extension()
         .and_then(|s| s.to_str())
         .map(|s| s.to_ascii_lowercase())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::MAX_CANVAS_DIM;
+
+    /// A path in the temp directory unique to this test run; `name` keeps tests apart.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("termipaint-{}-{name}", std::process::id()))
+    }
+
+    fn load_text(name: &str, text: &str) -> Result<Canvas> {
+        let path = temp_path(name);
+        fs::write(&path, text).unwrap();
+        let canvas = load_canvas(&path, MAX_CANVAS_DIM);
+        fs::remove_file(&path).unwrap();
+        canvas
+    }
+
+    #[test]
+    fn oversized_raster_export_is_refused() {
+        let canvas = Canvas::new(MAX_CANVAS_DIM, MAX_CANVAS_DIM);
+        let options = ExportOptions {
+            cell_width: MAX_EXPORT_CELL_PX,
+            cell_height: MAX_EXPORT_CELL_PX,
+            ..ExportOptions::default()
+        };
+        let path = temp_path("huge.ppm");
+        let err = save_layers(&path, &[Layer::new(canvas)], &options).unwrap_err();
+        assert!(err.to_string().contains("export limit"), "{err:#}");
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn raster_export_uses_the_configured_default_colors() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.set(0, 0, PaintCell::new('#', PaintColor::Default));
+        let options = ExportOptions {
+            cell_width: 1,
+            cell_height: 1,
+            default_fg: (1, 2, 3),
+            default_bg: (4, 5, 6),
+            ..ExportOptions::default()
+        };
+        let path = temp_path("defaults.ppm");
+        save_layers(&path, &[Layer::new(canvas)], &options).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(bytes.ends_with(&[1, 2, 3, 4, 5, 6]), "{bytes:?}");
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn loaded_images_stay_within_the_size_limit() {
+        let options = ExportOptions {
+            cell_width: 1,
+            cell_height: 1,
+            ..ExportOptions::default()
+        };
+        let path = temp_path("tall.png");
+        save_layers(&path, &[Layer::new(Canvas::new(4, 200))], &options).unwrap();
+        let canvas = load_image(&path, 500, 10);
+        fs::remove_file(&path).unwrap();
+        let canvas = canvas.unwrap();
+        assert_eq!((canvas.width(), canvas.height()), (10, 10));
+    }
+
+    #[test]
+    fn only_autosaves_of_finished_instances_are_recovered() {
+        let dir = temp_path("autosaves");
+        let own = dir.join(autosave_path().unwrap().file_name().unwrap());
+        let running = dir.join(format!("autosave-{}.json", u32::MAX));
+        let finished = dir.join(format!("autosave-{}.json", u32::MAX - 1));
+        let unknown = dir.join(format!("autosave-{}.json", u32::MAX - 2));
+        let _own_lock = lock_autosave(&own).unwrap();
+        let running_lock = lock_autosave(&running).unwrap();
+        drop(lock_autosave(&finished).unwrap());
+        for path in [
+            &own,
+            &running,
+            &finished,
+            &unknown,
+            &dir.join(format!("autosave-{}.tmp.json", u32::MAX)),
+        ] {
+            fs::write(path, "{}").unwrap();
+        }
+        assert_eq!(orphaned_autosave(&dir), Some(finished.clone()));
+
+        remove_autosave(&finished);
+        assert!(!finished.with_extension("lock").exists());
+        assert_eq!(orphaned_autosave(&dir), None);
+        drop(running_lock);
+        assert_eq!(orphaned_autosave(&dir), Some(running));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn json_with_wrong_cell_count_is_rejected() {
+        let mut value = serde_json::to_value(Canvas::new(3, 2)).unwrap();
+        value["cells"].as_array_mut().unwrap().pop();
+        let err = layers_from_json(&value.to_string(), MAX_CANVAS_DIM).unwrap_err();
+        assert!(err.to_string().contains("needs 6 cells"), "{err:#}");
+    }
+
+    #[test]
+    fn json_above_a_lower_limit_is_rejected() {
+        let text = serde_json::to_string(&Canvas::new(40, 10)).unwrap();
+        assert!(layers_from_json(&text, 40).is_ok());
+        let err = layers_from_json(&text, 32).unwrap_err();
+        assert!(err.to_string().contains("1x1 to 32x32"), "{err:#}");
+    }
+
+    #[test]
+    fn json_with_huge_width_is_rejected() {
+        let text = r#"{"width": 65535, "height": 1, "cells": []}"#;
+        let err = layers_from_json(text, MAX_CANVAS_DIM).unwrap_err();
+        assert!(err.to_string().contains("65535x1"), "{err:#}");
+    }
+
+    #[test]
+    fn ascii_with_huge_size_header_is_rejected() {
+        let text = format!("{ASCII_HEADER_PREFIX}65535x65535\nart");
+        assert!(load_text("huge.txt", &text).is_err());
+    }
+
+    #[test]
+    fn tpaint_with_huge_header_is_rejected() {
+        let text = format!("{TPAINT_HEADER_PREFIX}65535x2\n{{Red}}x\n");
+        assert!(load_text("huge.tpaint", &text).is_err());
+    }
+
+    fn sample_canvas() -> Canvas {
+        let mut canvas = Canvas::new(3, 2);
+        canvas.set(0, 0, PaintCell::new('#', PaintColor::Red));
+        canvas.set(
+            2,
+            1,
+            PaintCell::new('o', PaintColor::Default).with_bg(Some(PaintColor::Blue)),
+        );
+        canvas
+    }
+
+    #[test]
+    fn legacy_bare_canvas_loads_as_one_layer() {
+        let text = serde_json::to_string(&sample_canvas()).unwrap();
+        let layers = layers_from_json(&text, MAX_CANVAS_DIM).unwrap();
+        assert_eq!(layers, vec![Layer::new(sample_canvas())]);
+    }
+
+    #[test]
+    fn version_2_canvas_loads_as_one_layer() {
+        let text = serde_json::json!({ "version": 2, "canvas": sample_canvas() }).to_string();
+        let layers = layers_from_json(&text, MAX_CANVAS_DIM).unwrap();
+        assert_eq!(layers, vec![Layer::new(sample_canvas())]);
+    }
+
+    #[test]
+    fn version_3_keeps_every_layer() {
+        let mut hidden = Layer::new(Canvas::new(3, 2));
+        hidden.visible = false;
+        let layers = vec![Layer::new(sample_canvas()), hidden];
+        let text = serde_json::to_string(&JsonFile::latest(&layers)).unwrap();
+        assert_eq!(layers_from_json(&text, MAX_CANVAS_DIM).unwrap(), layers);
+    }
+
+    #[test]
+    fn newer_format_version_is_rejected() {
+        let text = serde_json::json!({
+            "version": JSON_FORMAT_VERSION + 1,
+            "layers": [Layer::new(sample_canvas())],
+        })
+        .to_string();
+        let err = layers_from_json(&text, MAX_CANVAS_DIM).unwrap_err();
+        assert!(err.to_string().contains("update TermiPaint"), "{err:#}");
+    }
+
+    #[test]
+    fn tpaint_round_trip_keeps_every_cell() {
+        let mut canvas = Canvas::new(6, 3);
+        canvas.set(0, 0, PaintCell::new('{', PaintColor::Red));
+        canvas.set(
+            1,
+            0,
+            PaintCell::new('\\', PaintColor::Rgb { r: 1, g: 2, b: 3 }),
+        );
+        canvas.set(2, 0, PaintCell::new('}', PaintColor::Default));
+        canvas.set(
+            3,
+            1,
+            PaintCell::new('x', PaintColor::Cyan).with_bg(Some(PaintColor::Rgb {
+                r: 200,
+                g: 100,
+                b: 0,
+            })),
+        );
+        canvas.set(
+            4,
+            1,
+            PaintCell::new('y', PaintColor::Cyan)
+                .with_bg(Some(PaintColor::Black))
+                .with_attrs(CellAttrs::BOLD.toggled(CellAttrs::UNDERLINE)),
+        );
+        canvas.set(
+            5,
+            2,
+            PaintCell::new(' ', PaintColor::Default).with_attrs(CellAttrs::REVERSED),
+        );
+
+        let path = temp_path("round-trip.tpaint");
+        save_tpaint(&path, &canvas).unwrap();
+        let loaded = load_tpaint(&path, MAX_CANVAS_DIM);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), canvas);
+    }
+
+    #[test]
+    fn gzip_json_round_trip_keeps_every_layer() {
+        let mut hidden = Layer::new(Canvas::new(3, 2));
+        hidden.visible = false;
+        let layers = vec![Layer::new(sample_canvas()), hidden];
+        for name in ["round-trip.tpz", "round-trip.json.gz"] {
+            let path = temp_path(name);
+            save_layers(&path, &layers, &ExportOptions::default()).unwrap();
+            let mut magic = [0; 2];
+            fs::File::open(&path)
+                .and_then(|mut file| file.read_exact(&mut magic))
+                .unwrap();
+            let loaded = load_layers(&path, MAX_CANVAS_DIM);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(magic, [0x1f, 0x8b], "{name} is not gzip");
+            assert_eq!(loaded.unwrap(), layers);
+        }
+    }
+
+    #[test]
+    fn gzip_json_is_detected_by_extension() {
+        for name in ["art.tpz", "art.TPZ", "art.json.gz", "art.JSON.GZ"] {
+            assert!(is_gzip_json(Path::new(name)), "{name}");
+        }
+        for name in ["art.json", "art.gz", "art.txt.gz", "json.gz.txt", "tpz"] {
+            assert!(!is_gzip_json(Path::new(name)), "{name}");
+        }
+    }
+
+    #[test]
+    fn ascii_round_trip_keeps_spaces_and_mixed_line_endings() {
+        let loaded = load_text("mixed.txt", "ab \r\ncd  \n   \r\n").unwrap();
+        assert_eq!((loaded.width(), loaded.height()), (4, 3));
+        assert_eq!(loaded.get(1, 0).ch, 'b');
+        assert_eq!(loaded.get(1, 1).ch, 'd');
+
+        for header in [false, true] {
+            let path = temp_path(&format!("round-trip-{header}.txt"));
+            save_ascii(&path, &loaded, header).unwrap();
+            let reloaded = load_ascii(&path, MAX_CANVAS_DIM);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(reloaded.unwrap(), loaded, "header: {header}");
+        }
+    }
+
+    #[test]
+    fn region_export_matches_the_source_cells() {
+        let mut top = Layer::new(Canvas::new(3, 2));
+        top.canvas.set(1, 1, PaintCell::new('@', PaintColor::Green));
+        top.visible = false;
+        let layers = vec![Layer::new(sample_canvas()), top];
+        let region = Rect::new(1, 1, 2, 1);
+
+        let path = temp_path("region.json");
+        save_region(&path, &layers, region, &ExportOptions::default()).unwrap();
+        let loaded = load_layers(&path, MAX_CANVAS_DIM);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded.len(), layers.len());
+        for (cropped, source) in loaded.iter().zip(&layers) {
+            assert_eq!((cropped.canvas.width(), cropped.canvas.height()), (2, 1));
+            assert_eq!(cropped.visible, source.visible);
+            for x in 0..region.width {
+                assert_eq!(
+                    cropped.canvas.get(x, 0),
+                    source.canvas.get(region.x + x, region.y)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ascii_size_header_pads_short_content() {
+        let text = format!("{ASCII_HEADER_PREFIX}5x3\nab\n");
+        let canvas = load_text("padded.txt", &text).unwrap();
+        assert_eq!((canvas.width(), canvas.height()), (5, 3));
+        assert_eq!(canvas.get(1, 0).ch, 'b');
+        assert!(canvas.get(4, 2).is_blank());
+    }
+
+    #[test]
+    fn malformed_ascii_header_is_art() {
+        let text = format!("{ASCII_HEADER_PREFIX}5by3\nab");
+        let canvas = load_text("malformed.txt", &text).unwrap();
+        assert_eq!(canvas.height(), 2);
+        assert_eq!(canvas.width() as usize, text.lines().next().unwrap().len());
+        assert_eq!(canvas.get(0, 0).ch, '#');
+        assert_eq!(canvas.get(0, 1).ch, 'a');
+    }
+}
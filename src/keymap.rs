@@ -0,0 +1,497 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{Context, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+
+/// Everything a key can be bound to. The names in `keybindings.toml` are the snake_case forms,
+/// e.g. `pencil = "b"` or `undo = ["u", "Ctrl+z"]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quit,
+    Undo,
+    Redo,
+    Save,
+    ExportView,
+    Load,
+    Import,
+    Reference,
+    RecentFiles,
+    ExportCellSize,
+    SvgFill,
+    ImageColors,
+    LuminanceBands,
+    Copy,
+    Cut,
+    Paste,
+    Clear,
+    Trim,
+    CanvasSize,
+    FillWarnThreshold,
+    BrushSize,
+    GridSpacing,
+    ScrollLeft,
+    ScrollRight,
+    ScrollUp,
+    ScrollDown,
+    CursorLeft,
+    CursorRight,
+    CursorUp,
+    CursorDown,
+    Press,
+    Pencil,
+    Eraser,
+    Line,
+    Rectangle,
+    Circle,
+    Fill,
+    Spray,
+    Text,
+    Select,
+    BoxLine,
+    Gradient,
+    ReplaceAll,
+    Arrow,
+    Polygon,
+    Measure,
+    NextTool,
+    PrevTool,
+    AsciiHeader,
+    MessageLog,
+    History,
+    ColorPicker,
+    Backdrop,
+    Stats,
+    Minimap,
+    ZoomIn,
+    ZoomOut,
+    ResetView,
+    ShadedLines,
+    CornerRadius,
+    FromCenter,
+    NewLayer,
+    DeleteLayer,
+    LayerUp,
+    LayerDown,
+    ToggleLayer,
+    FlattenLayers,
+    MoveLayerUp,
+    MoveLayerDown,
+    RenameLayer,
+    LayerOpacityDown,
+    LayerOpacityUp,
+    Symmetry,
+    MirrorAxis,
+    LineStyle,
+    Help,
+    FillPattern,
+    Grid,
+    ConfirmDestructive,
+    FlipHorizontal,
+    FlipVertical,
+    MultiFill,
+    GradientEnd,
+    FillConnectivity,
+    FillMatch,
+    SizeDown,
+    SizeUp,
+    SpacingDown,
+    SpacingUp,
+    PatternBrush,
+    FilledShapes,
+    Lighten,
+    Darken,
+    HalfBlock,
+    BlankGlyph,
+    LiteralSpaces,
+    BackgroundMode,
+    Rainbow,
+    Crosshair,
+    BrushShape,
+    NextBrushChar,
+    BrushChar,
+    RotateRight,
+    RotateLeft,
+    ColorPrompt,
+    DefaultColor,
+    SwapColors,
+    PaletteAdd,
+    PaletteRemove,
+}
+
+impl Action {
+    /// The action's name as written in `keybindings.toml`.
+    pub fn name(self) -> String {
+        let mut name = String::new();
+        for ch in format!("{self:?}").chars() {
+            if ch.is_ascii_uppercase() && !name.is_empty() {
+                name.push('_');
+            }
+            name.push(ch.to_ascii_lowercase());
+        }
+        name
+    }
+}
+
+/// The built-in bindings, in the same notation as `keybindings.toml`.
+const DEFAULT_BINDINGS: &[(Action, &[&str])] = &[
+    (Action::Quit, &["q"]),
+    (Action::Undo, &["u", "Ctrl+z"]),
+    (Action::Redo, &["y", "Ctrl+y", "Ctrl+Shift+z"]),
+    (Action::Save, &["Ctrl+s"]),
+    (Action::ExportView, &["Ctrl+Shift+s", "F2"]),
+    (Action::Load, &["Ctrl+o"]),
+    (Action::Import, &["Ctrl+u"]),
+    (Action::Reference, &["Shift+j"]),
+    (Action::RecentFiles, &["Ctrl+p"]),
+    (Action::ExportCellSize, &["Ctrl+e"]),
+    (Action::SvgFill, &["Ctrl+f"]),
+    (Action::ImageColors, &["F5"]),
+    (Action::LuminanceBands, &["Ctrl+l"]),
+    (Action::Copy, &["Ctrl+c"]),
+    (Action::Cut, &["Ctrl+x"]),
+    (Action::Paste, &["Ctrl+v"]),
+    (Action::Clear, &["Ctrl+n"]),
+    (Action::Trim, &["Ctrl+t"]),
+    (Action::CanvasSize, &["Ctrl+r"]),
+    (Action::FillWarnThreshold, &["Ctrl+w"]),
+    (Action::BrushSize, &["Ctrl+b"]),
+    (Action::GridSpacing, &["Ctrl+g"]),
+    (Action::ScrollLeft, &["Ctrl+Left"]),
+    (Action::ScrollRight, &["Ctrl+Right"]),
+    (Action::ScrollUp, &["Ctrl+Up"]),
+    (Action::ScrollDown, &["Ctrl+Down"]),
+    (Action::CursorLeft, &["Left"]),
+    (Action::CursorRight, &["Right"]),
+    (Action::CursorUp, &["Up"]),
+    (Action::CursorDown, &["Down"]),
+    (Action::Press, &["Space"]),
+    (Action::Pencil, &["p"]),
+    (Action::Eraser, &["e"]),
+    (Action::Line, &["l"]),
+    (Action::Rectangle, &["r"]),
+    (Action::Circle, &["c"]),
+    (Action::Fill, &["f"]),
+    (Action::Spray, &["a"]),
+    (Action::Text, &["i"]),
+    (Action::Select, &["s"]),
+    (Action::BoxLine, &["j"]),
+    (Action::Gradient, &["z"]),
+    (Action::ReplaceAll, &["v"]),
+    (Action::Arrow, &["Shift+w"]),
+    (Action::Polygon, &["Shift+o"]),
+    (Action::Measure, &["Shift+y"]),
+    (Action::NextTool, &["Tab"]),
+    (Action::PrevTool, &["Shift+Tab"]),
+    (Action::AsciiHeader, &["Shift+h"]),
+    (Action::MessageLog, &["Shift+l"]),
+    (Action::History, &["Shift+u"]),
+    (Action::ColorPicker, &["Shift+k"]),
+    (Action::Backdrop, &["Shift+b"]),
+    (Action::Stats, &["Shift+i"]),
+    (Action::Minimap, &["Shift+n"]),
+    (Action::ZoomIn, &["+", "="]),
+    (Action::ZoomOut, &["-"]),
+    (Action::ResetView, &["Home"]),
+    (Action::ShadedLines, &["Shift+t"]),
+    (Action::CornerRadius, &["Shift+r"]),
+    (Action::FromCenter, &["Shift+e"]),
+    (Action::NewLayer, &["Ctrl+a"]),
+    (Action::DeleteLayer, &["Ctrl+d"]),
+    (Action::LayerUp, &["PageUp"]),
+    (Action::LayerDown, &["PageDown"]),
+    (Action::ToggleLayer, &["Ctrl+k"]),
+    (Action::FlattenLayers, &["F4"]),
+    (Action::MoveLayerUp, &["Ctrl+PageUp"]),
+    (Action::MoveLayerDown, &["Ctrl+PageDown"]),
+    (Action::RenameLayer, &["F3"]),
+    (Action::LayerOpacityDown, &[","]),
+    (Action::LayerOpacityUp, &["."]),
+    (Action::Symmetry, &["Shift+s"]),
+    (Action::MirrorAxis, &["Shift+a"]),
+    (Action::LineStyle, &["Shift+d"]),
+    (Action::Help, &["?", "F1"]),
+    (Action::FillPattern, &["Shift+p"]),
+    (Action::Grid, &["Shift+g"]),
+    (Action::ConfirmDestructive, &["Shift+c"]),
+    (Action::FlipHorizontal, &["Shift+m"]),
+    (Action::FlipVertical, &["Shift+v"]),
+    (Action::MultiFill, &["Shift+f"]),
+    (Action::GradientEnd, &["$"]),
+    (Action::FillConnectivity, &["n"]),
+    (Action::FillMatch, &["m"]),
+    (Action::SizeDown, &["["]),
+    (Action::SizeUp, &["]"]),
+    (Action::SpacingDown, &["{"]),
+    (Action::SpacingUp, &["}"]),
+    (Action::PatternBrush, &["Shift+q"]),
+    (Action::FilledShapes, &["t"]),
+    (Action::Lighten, &[">"]),
+    (Action::Darken, &["<"]),
+    (Action::HalfBlock, &["h"]),
+    (Action::BlankGlyph, &["`"]),
+    (Action::LiteralSpaces, &["_"]),
+    (Action::BackgroundMode, &["g"]),
+    (Action::Rainbow, &["w"]),
+    (Action::Crosshair, &["k"]),
+    (Action::BrushShape, &["o"]),
+    (Action::NextBrushChar, &["b"]),
+    (Action::BrushChar, &["'"]),
+    (Action::RotateRight, &[")"]),
+    (Action::RotateLeft, &["("]),
+    (Action::ColorPrompt, &["#"]),
+    (Action::DefaultColor, &["0", "d"]),
+    (Action::SwapColors, &["x"]),
+    (Action::PaletteAdd, &[";"]),
+    (Action::PaletteRemove, &[":"]),
+];
+
+/// One key as the key map sees it. Plain character keys carry Shift in the character's case;
+/// `shift` only distinguishes Ctrl/Cmd shortcuts such as `Ctrl+Shift+z`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct KeyBinding {
+    code: KeyCode,
+    shortcut: bool,
+    shift: bool,
+}
+
+impl KeyBinding {
+    /// Parses `x`, `Shift+x`, `Ctrl+x`, `Cmd+Shift+x`, `Space`, `F1`, `Left`... Ctrl and Cmd are
+    /// the same modifier here, like everywhere else in the app.
+    fn parse(spec: &str) -> Option<Self> {
+        let (mods, key) = match spec.rsplit_once('+') {
+            // A lone `+`, or a spec ending in `++`, means the plus key itself.
+            Some((mods, "")) => (mods.strip_suffix('+').unwrap_or(mods), "+"),
+            Some(split) => split,
+            None => ("", spec),
+        };
+        let mut shortcut = false;
+        let mut shift = false;
+        for modifier in mods.split('+').filter(|m| !m.is_empty()) {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" | "cmd" | "super" => shortcut = true,
+                "shift" => shift = true,
+                _ => return None,
+            }
+        }
+
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(ch), None) => KeyCode::Char(ch),
+            _ => match key.to_ascii_lowercase().as_str() {
+                "space" => KeyCode::Char(' '),
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                name => KeyCode::F(name.strip_prefix('f')?.parse().ok()?),
+            },
+        };
+        Some(Self::new(code, shortcut, shift))
+    }
+
+    /// How the help overlay writes the key, e.g. `u`, `Shift+M`, `Ctrl+Shift+Z` or `PgUp`.
+    fn label(self) -> String {
+        let key = match self.code {
+            KeyCode::Char(' ') => "Space".to_string(),
+            KeyCode::Char(ch) if self.shortcut => ch.to_ascii_uppercase().to_string(),
+            KeyCode::Char(ch) if ch.is_ascii_uppercase() => format!("Shift+{ch}"),
+            KeyCode::Char(ch) => ch.to_string(),
+            KeyCode::BackTab => "Shift+Tab".to_string(),
+            KeyCode::PageUp => "PgUp".to_string(),
+            KeyCode::PageDown => "PgDn".to_string(),
+            KeyCode::F(n) => format!("F{n}"),
+            code => format!("{code:?}"),
+        };
+        match (self.shortcut, self.shift) {
+            (true, true) => format!("Ctrl+Shift+{key}"),
+            (true, false) => format!("Ctrl+{key}"),
+            _ => key,
+        }
+    }
+
+    fn from_event(key: KeyEvent) -> Self {
+        Self::new(
+            key.code,
+            has_shortcut_modifier(key.modifiers),
+            key.modifiers.contains(KeyModifiers::SHIFT),
+        )
+    }
+
+    fn new(code: KeyCode, shortcut: bool, shift: bool) -> Self {
+        match code {
+            KeyCode::Char(ch) if shortcut => Self {
+                code: KeyCode::Char(ch.to_ascii_lowercase()),
+                shortcut,
+                shift: shift || ch.is_ascii_uppercase(),
+            },
+            KeyCode::Char(ch) if shift => Self {
+                code: KeyCode::Char(ch.to_ascii_uppercase()),
+                shortcut,
+                shift: false,
+            },
+            // Terminals report Shift+Tab as its own key.
+            KeyCode::Tab if shift && !shortcut => Self {
+                code: KeyCode::BackTab,
+                shortcut,
+                shift: false,
+            },
+            code => Self {
+                code,
+                shortcut,
+                shift: shortcut && shift,
+            },
+        }
+    }
+}
+
+/// `keybindings.toml` values: one key or a list of keys.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Which action each key runs. Keys that aren't in the map (Enter, Esc, the palette digits and
+/// Alt attribute toggles) keep their fixed meaning in `handle_key`.
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    bindings: HashMap<KeyBinding, Action>,
+    /// Every key in the order it was listed, defaults first, so `keys_for` is stable.
+    listed: Vec<KeyBinding>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self::with_overrides(BTreeMap::new()).expect("built-in key bindings are valid")
+    }
+}
+
+impl KeyMap {
+    /// Reads `keybindings.toml` text. Every action named in it gets exactly the keys listed (an
+    /// empty list unbinds it); all other actions keep their default keys unless one of those
+    /// keys was taken.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        // TOML errors quote the offending source over several lines; the status bar has one.
+        let table: BTreeMap<Action, Keys> = toml::from_str(text).map_err(|err| {
+            let line = err
+                .span()
+                .map_or(1, |span| text[..span.start].matches('\n').count() + 1);
+            anyhow::anyhow!("line {line}: {}", err.message())
+        })?;
+        let overrides = table
+            .into_iter()
+            .map(|(action, keys)| {
+                let keys = match keys {
+                    Keys::One(key) => vec![key],
+                    Keys::Many(keys) => keys,
+                };
+                (action, keys)
+            })
+            .collect();
+        Self::with_overrides(overrides)
+    }
+
+    fn with_overrides(overrides: BTreeMap<Action, Vec<String>>) -> Result<Self> {
+        let mut bindings = HashMap::new();
+        let mut listed = Vec::new();
+        for &(action, keys) in DEFAULT_BINDINGS {
+            if overrides.contains_key(&action) {
+                continue;
+            }
+            for key in keys {
+                let binding = KeyBinding::parse(key).context("invalid built-in key")?;
+                bindings.insert(binding, action);
+                listed.push(binding);
+            }
+        }
+
+        let mut claimed: HashMap<KeyBinding, Action> = HashMap::new();
+        for (&action, keys) in &overrides {
+            for key in keys {
+                let binding =
+                    KeyBinding::parse(key).with_context(|| format!("unknown key '{key}'"))?;
+                if let Some(other) = claimed.insert(binding, action) {
+                    if other != action {
+                        anyhow::bail!(
+                            "'{key}' is bound to both {} and {}",
+                            other.name(),
+                            action.name()
+                        );
+                    }
+                }
+                bindings.insert(binding, action);
+                listed.push(binding);
+            }
+        }
+        Ok(Self { bindings, listed })
+    }
+
+    /// Every key that runs `action`, written the way the help overlay shows keys. Empty when
+    /// the action is unbound.
+    pub fn keys_for(&self, action: Action) -> Vec<String> {
+        let mut keys: Vec<String> = Vec::new();
+        for binding in &self.listed {
+            if self.bindings.get(binding) == Some(&action) {
+                let label = binding.label();
+                if !keys.contains(&label) {
+                    keys.push(label);
+                }
+            }
+        }
+        keys
+    }
+
+    /// The action for a key press. A Ctrl/Cmd shortcut that isn't bound falls back to the same
+    /// key without Shift and then without the modifier, and an unbound capital letter to its
+    /// lowercase, so the map only needs to list the keys that differ.
+    pub fn action(&self, key: KeyEvent) -> Option<Action> {
+        let binding = KeyBinding::from_event(key);
+        let mut candidates = vec![binding];
+        if binding.shortcut {
+            candidates.push(KeyBinding {
+                shift: false,
+                ..binding
+            });
+            let code = match binding.code {
+                KeyCode::Char(ch) if binding.shift => KeyCode::Char(ch.to_ascii_uppercase()),
+                code => code,
+            };
+            candidates.push(KeyBinding::new(code, false, false));
+        }
+        if let Some(KeyBinding {
+            code: KeyCode::Char(ch),
+            ..
+        }) = candidates.last().copied()
+        {
+            candidates.push(KeyBinding::new(
+                KeyCode::Char(ch.to_ascii_lowercase()),
+                false,
+                false,
+            ));
+        }
+        candidates
+            .into_iter()
+            .find_map(|candidate| self.bindings.get(&candidate).copied())
+    }
+}
+
+/// Every action, in the order of the built-in bindings.
+#[cfg(test)]
+pub fn all_actions() -> impl Iterator<Item = Action> {
+    DEFAULT_BINDINGS.iter().map(|(action, _)| *action)
+}
+
+pub fn has_shortcut_modifier(modifiers: KeyModifiers) -> bool {
+    modifiers.contains(KeyModifiers::CONTROL) || modifiers.contains(KeyModifiers::SUPER)
+}
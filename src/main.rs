@@ -1,11 +1,18 @@
 mod canvas;
 mod io;
+mod keymap;
 mod tools;
 mod ui;
 
-use std::{collections::HashSet, io as stdio, path::PathBuf, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    io as stdio,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use crossterm::{
     event::{
         self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
@@ -17,21 +24,165 @@ use crossterm::{
 use ratatui::{backend::CrosstermBackend, layout::Rect, Terminal};
 
 use crate::{
-    canvas::{Canvas, History, OperationBuilder, PaintCell, PaintColor},
+    canvas::{
+        flatten, Canvas, CellAttrs, CellBlock, History, Layer, Operation, OperationBuilder,
+        PaintCell, PaintColor, FULL_OPACITY, MAX_CANVAS_DIM,
+    },
+    io::ExportOptions,
+    keymap::{has_shortcut_modifier, Action, KeyMap},
     tools::{
-        bresenham_line, brush_points, ellipse_points, flood_fill_points, rectangle_points, Point,
-        Tool,
+        arrowhead_cells, arrowhead_points, box_glyph, box_line_points, box_side_towards, box_sides,
+        bresenham_line, brush_points_iter, coverage_glyph, ellipse_points, filled_ellipse_points,
+        flood_fill_points, gradient_points_with_colors, matching_points, polyline_points,
+        rectangle_points, rounded_rectangle_points, shaded_line_cells, spray_points, square_end,
+        BrushShape, Connectivity, FillMatch, FillPattern, LineStyle, Point, Symmetry, Tool,
     },
     ui::{PreviewStyle, ToolbarAction, UiState},
 };
 
-const UNDO_LIMIT: usize = 100;
+const DEFAULT_UNDO_LIMIT: usize = 100;
+const MAX_UNDO_LIMIT: usize = 10_000;
+/// The highest `--max-size`. It keeps a crafted file, the size prompt or a resize drag from
+/// allocating billions of cells; a 4000x4000 canvas is 16 million.
+const MAX_SIZE_CEILING: u16 = 4000;
+const MAX_BRUSH_SPACING: u8 = 16;
+const MAX_BRUSH_SIZE: u8 = 16;
+const BRIGHTNESS_STEP_PERCENT: u8 = 10;
+const HALF_BLOCK_PIXEL: char = '█';
+const DEFAULT_FILL_WARN_THRESHOLD: usize = 2000;
+const DEFAULT_GRID_SPACING: u16 = 10;
+const SPRAY_DENSITY: u8 = 3;
+const SCROLL_STEP: u16 = 4;
+const SPRAY_INTERVAL: Duration = Duration::from_millis(50);
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_RECENT_FILES: usize = 10;
+const MAX_LOG_MESSAGES: usize = 200;
+/// Every layer is a full canvas, so the stack is capped to keep memory in check.
+const MAX_LAYERS: usize = 16;
+/// How far one key press changes a layer's opacity, in percent.
+const OPACITY_STEP: u8 = 25;
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+/// Edits with the same tool this close together share one undo step.
+const MERGE_WINDOW: Duration = Duration::from_millis(300);
+const MAX_CORNER_RADIUS: u8 = 4;
+const MAX_ZOOM: u16 = 4;
+const TEXT_TAB_WIDTH: i32 = 4;
+const READY_STATUS: &str = "Ready - press ? for help";
+/// How long a status message stays before the status line goes back to `READY_STATUS`.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(4);
+const ERROR_STATUS_TIMEOUT: Duration = Duration::from_secs(10);
 
 fn main() -> Result<()> {
-    run()
+    let args = Args::parse(std::env::args().skip(1))?;
+    run(args)
+}
+
+struct Args {
+    undo_limit: usize,
+    size: Option<(u16, u16)>,
+    /// Largest canvas side loading a file or resizing allows.
+    max_size: u16,
+    image_width: Option<u16>,
+    file: Option<PathBuf>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut size = None;
+        let mut image_width = None;
+        let mut parsed = Args {
+            undo_limit: DEFAULT_UNDO_LIMIT,
+            size: None,
+            max_size: MAX_CANVAS_DIM,
+            image_width: None,
+            file: None,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--undo-limit" => {
+                    let value = args.next().context("--undo-limit needs a number")?;
+                    parsed.undo_limit = value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|n| (1..=MAX_UNDO_LIMIT).contains(n))
+                        .with_context(|| {
+                            format!(
+                                "--undo-limit must be between 1 and {}, got '{}'",
+                                MAX_UNDO_LIMIT, value
+                            )
+                        })?;
+                }
+                "--size" => size = Some(args.next().context("--size needs a WxH value")?),
+                "--max-size" => {
+                    let value = args.next().context("--max-size needs a number")?;
+                    parsed.max_size = value
+                        .parse::<u16>()
+                        .ok()
+                        .filter(|n| (1..=MAX_SIZE_CEILING).contains(n))
+                        .with_context(|| {
+                            format!(
+                                "--max-size must be between 1 and {}, got '{}'",
+                                MAX_SIZE_CEILING, value
+                            )
+                        })?;
+                }
+                "--image-width" => {
+                    image_width = Some(args.next().context("--image-width needs a number")?)
+                }
+                _ if arg.starts_with("--") => bail!("unknown argument '{}'", arg),
+                _ if parsed.file.is_some() => bail!("only one file can be opened, got '{}'", arg),
+                _ => parsed.file = Some(PathBuf::from(arg)),
+            }
+        }
+
+        // Checked last, as --max-size may come after them.
+        if let Some(value) = size {
+            let size = parse_canvas_size(&value, parsed.max_size).with_context(|| {
+                format!(
+                    "--size must be WxH with sides from 1 to {}, got '{}'",
+                    parsed.max_size, value
+                )
+            })?;
+            parsed.size = Some(size);
+        }
+        if let Some(value) = image_width {
+            let width = value
+                .parse::<u16>()
+                .ok()
+                .filter(|n| (1..=parsed.max_size).contains(n))
+                .with_context(|| {
+                    format!(
+                        "--image-width must be between 1 and {}, got '{}'",
+                        parsed.max_size, value
+                    )
+                })?;
+            parsed.image_width = Some(width);
+        }
+
+        Ok(parsed)
+    }
 }
 
-fn run() -> Result<()> {
+fn run(args: Args) -> Result<()> {
+    // Load before touching the terminal so a bad file is reported on a normal screen.
+    let loaded = match &args.file {
+        Some(path) if io::is_image_file(path) => {
+            let width = args
+                .image_width
+                .or(args.size.map(|(width, _)| width))
+                .unwrap_or(io::DEFAULT_IMAGE_WIDTH);
+            let canvas = io::load_image(path, width, args.max_size)
+                .with_context(|| format!("could not open {}", path.display()))?;
+            Some(vec![Layer::new(canvas)])
+        }
+        Some(path) => Some(
+            io::load_layers(path, args.max_size)
+                .with_context(|| format!("could not open {}", path.display()))?,
+        ),
+        None => None,
+    };
+
     install_panic_hook();
 
     let mut stdout = stdio::stdout();
@@ -45,39 +196,149 @@ fn run() -> Result<()> {
 
     let initial_size = terminal.size()?;
     let initial_area = Rect::new(0, 0, initial_size.width, initial_size.height);
-    let initial_ui = ui::build_ui_state(initial_area);
+    let initial_ui = ui::build_ui_state(initial_area, &PaintColor::quick_palette());
     let mut app = App::new(
         initial_ui.canvas_inner.width.max(1),
         initial_ui.canvas_inner.height.max(1),
+        args.undo_limit,
     );
     app.last_ui = initial_ui;
+    app.max_canvas_dim = args.max_size;
+    app.autosave_path = io::autosave_path();
+    let autosave_lock = app.autosave_path.as_deref().and_then(io::lock_autosave);
+    if args.file.is_none() {
+        if let Some(path) = io::autosave_dir().and_then(|dir| io::orphaned_autosave(&dir)) {
+            app.prompt = PromptState::Confirm(CanvasAction::Recover(path));
+        }
+    }
+    app.recent_files_path = io::recent_files_path();
+    if let Some(path) = &app.recent_files_path {
+        app.recent_files = io::load_recent_files(path).unwrap_or_default();
+    }
+    app.palette_path = io::palette_path();
+    if let Some(path) = &app.palette_path {
+        match io::load_palette(path) {
+            Ok(colors) if !colors.is_empty() => app.palette = colors,
+            Ok(_) => {}
+            Err(err) => app.report_error(format!("Using the built-in palette: {err:#}")),
+        }
+    }
+    if let Some(path) = io::keybindings_path() {
+        match io::load_keymap(&path) {
+            Ok(keymap) => app.keymap = keymap,
+            Err(err) => app.report_error(format!("Using the default keys: {err:#}")),
+        }
+    }
+    if let Some(layers) = loaded {
+        app.set_layers(layers);
+        if let Some(path) = &args.file {
+            app.remember_file(path);
+        }
+        match args.file {
+            // A converted picture is a new drawing; saving must not overwrite the source image.
+            Some(path) if io::is_image_file(&path) => {
+                app.status = format!("Converted {} to ASCII art", path.display());
+                app.mark_dirty();
+            }
+            file => {
+                app.current_file = file;
+                app.status = format!("Opened {}", app.current_file_name().unwrap_or_default());
+            }
+        }
+    }
+    if let Some((width, height)) = args.size {
+        // A requested size wins over both the window and a loaded file's own size.
+        app.resize_layers(width, height);
+        app.fixed_size = true;
+    }
 
     let tick_rate = Duration::from_millis(16);
 
     loop {
         let size = terminal.size()?;
         let area = Rect::new(0, 0, size.width, size.height);
-        let ui_state = ui::build_ui_state(area);
+        let ui_state = ui::build_ui_state(area, &app.palette);
         app.resize_to_fit(&ui_state);
         app.last_ui = ui_state.clone();
 
         let preview_points = app.preview_points();
+        let flattened = app.flattened();
         let render_ctx = ui::RenderContext {
-            canvas: &app.canvas,
+            canvas: &flattened,
+            reference: app.reference.as_ref(),
             current_tool: app.tool,
             brush_char: app.brush_char,
-            brush_size: app.brush_size,
+            pattern: app
+                .clipboard
+                .as_ref()
+                .filter(|_| app.pattern_brush)
+                .map(|block| (block.width, block.height)),
+            brush_size: app.active_size(),
+            brush_shape: app.brush_shape,
+            mirror: (app.symmetry != Symmetry::Off).then(|| (app.symmetry, app.mirror_axis())),
+            brush_spacing: app.brush_spacing,
             color: app.color,
+            palette: &app.palette,
+            secondary_color: app.secondary_color,
+            bg: app.bg,
+            gradient_end: app.gradient_end,
+            attrs: app.attrs,
+            layer: (app.layers.len() > 1
+                || !app.layers[0].visible
+                || app.layers[0].opacity < FULL_OPACITY)
+                .then(|| {
+                    (
+                        app.active_layer + 1,
+                        app.layers.len(),
+                        &app.layers[app.active_layer],
+                    )
+                }),
+            rainbow: app.rainbow,
+            background_mode: app.background_mode,
             filled_shapes: app.filled_shapes,
             hover: app.hover,
+            half_block: app.half_block,
+            zoom: app.zoom,
+            minimap: app.minimap,
+            resize_handle: app.fixed_size,
+            blank_glyph: app.blank_glyph,
             preview_points: &preview_points,
             preview_style: app.preview_style(),
+            selection: app.visible_selection(),
+            scroll: app.scroll,
+            cursor: app.cursor,
+            grid: app.show_grid.then_some(app.grid_spacing),
+            crosshair: app.crosshair,
+            fill_warning: app.fill_warning(preview_points.len()),
             status: &app.status,
             file_name: app.current_file_name(),
+            dirty: app.dirty,
+            undo_len: app.history.undo_len(),
+            redo_len: app.history.redo_len(),
+            undo_limit: app.history.capacity(),
             prompt: app.prompt_view(),
+            list: app.list_view(),
+            message_log: app.message_log_view(),
+            keymap: &app.keymap,
+            help: match app.prompt {
+                PromptState::Help(scroll) => Some(scroll),
+                _ => None,
+            },
+            color_picker: match app.prompt {
+                PromptState::ColorPicker(selected) => Some(selected),
+                _ => None,
+            },
+            stats: match &app.prompt {
+                PromptState::Stats(stats) => Some(stats),
+                _ => None,
+            },
         };
 
         terminal.draw(|f| ui::render(f, &ui_state, &render_ctx))?;
+        app.tick();
+        app.expire_status();
+        app.autosave_tick();
+        app.log_status();
 
         if event::poll(tick_rate)? {
             match event::read()? {
@@ -93,6 +354,14 @@ fn run() -> Result<()> {
                 Event::FocusGained | Event::FocusLost | Event::Paste(_) => {}
             }
         }
+        app.log_status();
+    }
+
+    // A clean exit leaves nothing to recover. Other instances' autosaves stay put.
+    app.finish_autosave();
+    drop(autosave_lock);
+    if let Some(path) = &app.autosave_path {
+        io::remove_autosave(path);
     }
 
     Ok(())
@@ -125,15 +394,98 @@ struct DrawSpec {
     tool: Tool,
     ch: char,
     color: PaintColor,
+    /// The background glyphs are painted on, if any.
+    bg: Option<PaintColor>,
     size: u8,
+    shape: BrushShape,
+    spacing: u8,
+    rainbow: bool,
+    /// A space brush writes plain spaces instead of blocks of color.
+    literal_space: bool,
+    background: bool,
+    symmetry: Symmetry,
+    mirror_axis: Point,
+    line_style: LineStyle,
+    corner_radius: u8,
+    shaded_lines: bool,
+    attrs: CellAttrs,
+}
+
+impl DrawSpec {
+    /// Only the Eraser clears cells; every other tool paints, a space brush included.
+    fn erases(self) -> bool {
+        self.tool == Tool::Eraser
+    }
+
+    fn preview_style(self) -> PreviewStyle {
+        PreviewStyle {
+            ch: self.ch,
+            fg: self.color,
+            bg: self.bg,
+            literal_space: self.literal_space,
+            erase: self.erases(),
+            background: self.background,
+        }
+    }
+
+    /// The cell a stroke leaves behind; background strokes keep the existing glyph and fg.
+    fn paint(self, existing: PaintCell) -> PaintCell {
+        if !self.background {
+            return self.draw_cell();
+        }
+        existing.with_bg((!self.erases()).then_some(self.color))
+    }
+
+    fn draw_cell(self) -> PaintCell {
+        if self.erases() {
+            PaintCell::blank()
+        } else {
+            PaintCell::brush(self.ch, self.color, self.bg, self.literal_space)
+                .with_attrs(self.attrs)
+        }
+    }
+
+    /// Whether the Line tool draws with shading glyphs; half-block pixels have no glyph to vary.
+    fn shades_lines(self) -> bool {
+        self.shaded_lines && self.ch != HALF_BLOCK_PIXEL
+    }
+
+    fn next_stamp(self, color_cycle: &mut usize) -> DrawSpec {
+        if !self.rainbow {
+            return self;
+        }
+
+        let palette = PaintColor::quick_palette();
+        let color = palette[*color_cycle % palette.len()];
+        *color_cycle += 1;
+        DrawSpec { color, ..self }
+    }
+
+    /// `points` plus their reflections under this spec's symmetry, without repeats.
+    fn mirrored(self, points: &[Point]) -> Vec<Point> {
+        if self.symmetry == Symmetry::Off {
+            return points.to_vec();
+        }
+        let mut out: Vec<Point> = points
+            .iter()
+            .flat_map(|&p| self.symmetry.reflect(p, self.mirror_axis))
+            .collect();
+        out.sort_by_key(|p| (p.y, p.x));
+        out.dedup();
+        out
+    }
 }
 
 enum MouseMode {
     Idle,
     FreeDrag {
         last: Point,
+        since_stamp: u8,
+        color_cycle: usize,
         spec: DrawSpec,
         builder: OperationBuilder,
+        /// With the pattern brush on, the clipboard block the Pencil stamps instead of its brush.
+        pattern: Option<CellBlock>,
     },
     ShapeDrag {
         start: Point,
@@ -141,54 +493,468 @@ enum MouseMode {
         spec: DrawSpec,
         tool: Tool,
         filled: bool,
+        /// Shift is held: rectangles become squares and ellipses circles.
+        square: bool,
+        /// Rectangles and ellipses grow from `start` as their center, not a corner.
+        centered: bool,
+    },
+    TextEntry {
+        origin: Point,
+        cursor: Point,
+        spec: DrawSpec,
+        builder: OperationBuilder,
+    },
+    Selecting {
+        start: Point,
+        current: Point,
+    },
+    /// A Measure drag: only the status line and a guide line change, never the canvas.
+    Measure {
+        start: Point,
+        current: Point,
+    },
+    Pan {
+        anchor: (u16, u16),
+        start_scroll: (u16, u16),
     },
+    MoveSelection {
+        origin: Rect,
+        grab: Point,
+        offset: Point,
+        block: CellBlock,
+        builder: OperationBuilder,
+    },
+    /// Dragging the corner handle of a fixed-size canvas; `before` is restored on Esc and
+    /// is what each new size is cut from, so shrinking and growing back loses nothing.
+    ResizeCanvas {
+        before: Canvas,
+    },
+}
+
+enum CanvasAction {
+    RecolorBands(Vec<PaintColor>),
+    Backdrop(PaintColor),
+    Clear,
+    FlipHorizontal,
+    FlipVertical,
+    /// A quarter turn, clockwise when true.
+    Rotate(bool),
+    Trim,
+    Recover(PathBuf),
+}
+
+impl CanvasAction {
+    fn confirm_title(&self) -> &'static str {
+        match self {
+            CanvasAction::Clear => "Clear the whole canvas? Enter to confirm, Esc to cancel",
+            CanvasAction::FlipHorizontal => {
+                "Flip the canvas left to right? Enter to confirm, Esc to cancel"
+            }
+            CanvasAction::FlipVertical => {
+                "Flip the canvas top to bottom? Enter to confirm, Esc to cancel"
+            }
+            CanvasAction::Rotate(true) => {
+                "Rotate the canvas clockwise? Enter to confirm, Esc to cancel"
+            }
+            CanvasAction::Rotate(false) => {
+                "Rotate the canvas counter-clockwise? Enter to confirm, Esc to cancel"
+            }
+            CanvasAction::Trim => {
+                "Crop the canvas to its content? Enter to confirm, Esc to cancel"
+            }
+            CanvasAction::Recover(_) => {
+                "Recover the autosaved drawing from an earlier session? Enter to load, Esc to discard"
+            }
+            CanvasAction::RecolorBands(_) => {
+                "Recolor the whole canvas by brightness? Enter to confirm, Esc to cancel"
+            }
+            CanvasAction::Backdrop(_) => {
+                "Set the background of every empty cell? Enter to confirm, Esc to cancel"
+            }
+        }
+    }
 }
 
 enum PromptState {
     None,
     Save(String),
+    ExportView(String),
     Load(String),
+    Import(String),
+    Reference(String),
+    ExportCellSize(String),
+    SvgDefaultFill(String),
+    ImageColors(String),
+    FillWarnThreshold(String),
+    GridSpacing(String),
+    BrushSize(String),
+    BrushChar(String),
+    GradientEnd(String),
+    LuminanceBands(String),
+    Backdrop(String),
+    Color(String),
+    CanvasSize(String),
+    RenameLayer(String),
+    Confirm(CanvasAction),
+    ConfirmQuit,
+    /// The recent-files picker, with the highlighted entry.
+    RecentFiles(usize),
+    /// The message log overlay, scrolled down by this many lines.
+    MessageLog(u16),
+    /// The keybinding help overlay, scrolled down by this many lines.
+    Help(u16),
+    /// The undo history list, with the highlighted entry.
+    History(usize),
+    /// The 256-color picker, with the highlighted palette index.
+    ColorPicker(u8),
+    /// The canvas stats overlay.
+    Stats(ui::StatsView),
 }
 
 struct App {
+    /// The active layer's cells, which every tool draws into.
     canvas: Canvas,
+    /// The layer stack, bottom first. The active layer's slot only holds a placeholder while
+    /// its cells are out in `canvas`; `attach_active` and `detach_active` move them.
+    layers: Vec<Layer>,
+    active_layer: usize,
+    /// A read-only drawing shown dimmed wherever the canvas is blank.
+    reference: Option<Canvas>,
     tool: Tool,
     brush_char: char,
     brush_size: u8,
+    /// The Eraser's own size, so a fat brush and a precise eraser don't share one setting.
+    eraser_size: u8,
+    brush_shape: BrushShape,
+    symmetry: Symmetry,
+    mirror_axis: Option<Point>,
+    line_style: LineStyle,
+    corner_radius: u8,
+    shaded_lines: bool,
+    attrs: CellAttrs,
+    brush_spacing: u8,
     color: PaintColor,
+    /// Swapped with `color` by `x`; right-dragging paints with it.
+    secondary_color: PaintColor,
+    /// The background foreground strokes put under their glyph, picked up by sampling a cell
+    /// that has one and cleared by the default color.
+    bg: Option<PaintColor>,
+    /// Rectangles and ellipses are drawn from the center out instead of corner to corner.
+    from_center: bool,
+    /// Where the right button went down, until it either drags (paint) or is released (sample).
+    right_press: Option<Point>,
+    gradient_end: PaintColor,
+    rainbow: bool,
     filled_shapes: bool,
+    half_block: bool,
+    /// Each canvas cell is drawn as a `zoom`x`zoom` block of terminal cells (1 to `MAX_ZOOM`).
+    zoom: u16,
+    minimap: bool,
+    blank_glyph: char,
+    literal_spaces: bool,
+    background_mode: bool,
+    multi_fill: bool,
+    fill_seeds: Vec<Point>,
+    polygon: Vec<Point>,
+    last_vertex_at: Instant,
+    /// The tool and time of the last edit that may take quick repeats into its undo step.
+    last_commit: Option<(Tool, Instant)>,
+    /// Set while a keyboard-cursor press runs, so a held key makes one undo step.
+    keyboard_press: bool,
+    fill_warn_threshold: usize,
+    show_grid: bool,
+    crosshair: bool,
+    grid_spacing: u16,
+    fill_connectivity: Connectivity,
+    fill_match: FillMatch,
+    fill_pattern: FillPattern,
+    confirm_destructive: bool,
     hover: Option<Point>,
     mouse_mode: MouseMode,
     history: History,
     status: String,
+    /// The status as of the last frame, to notice when it changes.
+    shown_status: String,
+    status_set_at: Instant,
+    /// The last status set through `report_error`, which stays up longer.
+    error_status: Option<String>,
+    started: Instant,
+    message_log: VecDeque<(Duration, String)>,
     prompt: PromptState,
     current_file: Option<PathBuf>,
+    export_options: ExportOptions,
+    dirty: bool,
+    canvas_clipped: bool,
+    fixed_size: bool,
+    /// Largest canvas side a loaded file, a size prompt or a handle drag may give; `--max-size`
+    /// sets it.
+    max_canvas_dim: u16,
+    scroll: (u16, u16),
+    cursor: Option<Point>,
+    selection: Option<Rect>,
+    clipboard: Option<CellBlock>,
+    /// The Pencil stamps the clipboard along its path instead of drawing its brush.
+    pattern_brush: bool,
+    last_spray: Instant,
+    autosave_path: Option<PathBuf>,
+    recent_files_path: Option<PathBuf>,
+    recent_files: Vec<PathBuf>,
+    palette_path: Option<PathBuf>,
+    /// The color row and number keys: the user palette, or the built-in one until it's customized.
+    palette: Vec<PaintColor>,
+    keymap: KeyMap,
+    last_autosave: Instant,
+    autosave_pending: bool,
+    autosave_job: Option<std::thread::JoinHandle<Result<()>>>,
     last_ui: UiState,
 }
 
 impl App {
-    fn new(canvas_width: u16, canvas_height: u16) -> Self {
+    fn new(canvas_width: u16, canvas_height: u16, undo_limit: usize) -> Self {
         Self {
             canvas: Canvas::new(canvas_width, canvas_height),
+            layers: vec![Layer::new(Canvas::new(1, 1))],
+            active_layer: 0,
+            reference: None,
             tool: Tool::Pencil,
             brush_char: '#',
             brush_size: 1,
+            eraser_size: 1,
+            brush_shape: BrushShape::Square,
+            symmetry: Symmetry::Off,
+            mirror_axis: None,
+            line_style: LineStyle::Solid,
+            corner_radius: 0,
+            shaded_lines: false,
+            attrs: CellAttrs::NONE,
+            brush_spacing: 1,
             color: PaintColor::White,
+            secondary_color: PaintColor::Black,
+            bg: None,
+            from_center: false,
+            right_press: None,
+            gradient_end: PaintColor::Blue,
+            rainbow: false,
             filled_shapes: false,
+            half_block: false,
+            zoom: 1,
+            minimap: true,
+            blank_glyph: ' ',
+            literal_spaces: false,
+            background_mode: false,
+            multi_fill: false,
+            fill_seeds: Vec::new(),
+            polygon: Vec::new(),
+            last_vertex_at: Instant::now(),
+            last_commit: None,
+            keyboard_press: false,
+            fill_warn_threshold: DEFAULT_FILL_WARN_THRESHOLD,
+            show_grid: false,
+            crosshair: false,
+            grid_spacing: DEFAULT_GRID_SPACING,
+            fill_connectivity: Connectivity::Four,
+            fill_match: FillMatch::Exact,
+            fill_pattern: FillPattern::Solid,
+            confirm_destructive: true,
             hover: None,
             mouse_mode: MouseMode::Idle,
-            history: History::new(UNDO_LIMIT),
-            status: "Ready".to_string(),
+            history: History::new(undo_limit),
+            status: READY_STATUS.to_string(),
+            shown_status: String::new(),
+            status_set_at: Instant::now(),
+            error_status: None,
+            started: Instant::now(),
+            message_log: VecDeque::new(),
             prompt: PromptState::None,
             current_file: None,
+            export_options: ExportOptions::default(),
+            dirty: false,
+            canvas_clipped: false,
+            fixed_size: false,
+            max_canvas_dim: MAX_CANVAS_DIM,
+            scroll: (0, 0),
+            cursor: None,
+            selection: None,
+            clipboard: None,
+            pattern_brush: false,
+            last_spray: Instant::now(),
+            autosave_path: None,
+            recent_files_path: None,
+            recent_files: Vec::new(),
+            palette_path: None,
+            palette: PaintColor::quick_palette().to_vec(),
+            keymap: KeyMap::default(),
+            last_autosave: Instant::now(),
+            autosave_pending: false,
+            autosave_job: None,
             last_ui: UiState::default(),
         }
     }
 
     fn resize_to_fit(&mut self, ui_state: &UiState) {
-        let width = ui_state.canvas_inner.width.max(1);
-        let height = ui_state.canvas_inner.height.max(1);
-        self.canvas.resize_preserve(width, height);
+        let rows_per_cell = if self.half_block { 2 } else { 1 };
+
+        if !self.fixed_size {
+            // Never shrink past painted cells; content outside the window is kept, just not shown.
+            // The size follows the unzoomed window, so zooming never resizes the canvas.
+            let (content_width, content_height) = self.content_extent();
+            let width = ui_state.canvas_inner.width.max(1).max(content_width);
+            let height = (ui_state.canvas_inner.height.max(1) * rows_per_cell).max(content_height);
+            self.resize_layers(width, height);
+        }
+
+        let (view_width, view_rows) = ui::canvas_view_size(ui_state, self.zoom);
+        let view_height = view_rows * rows_per_cell;
+
+        let width = self.canvas.width();
+        let height = self.canvas.height();
+        let clipped = width > view_width || height > view_height;
+        if clipped && !self.canvas_clipped {
+            self.status = format!(
+                "Canvas {}x{} is larger than the window - Ctrl+arrows to scroll",
+                width, height
+            );
+        }
+        self.canvas_clipped = clipped;
+
+        // Half-block rows pair two canvas rows, so vertical scrolling stays on even rows.
+        let max_x = width.saturating_sub(view_width);
+        let max_y = height
+            .saturating_sub(view_height)
+            .next_multiple_of(rows_per_cell);
+        self.scroll.0 = self.scroll.0.min(max_x);
+        self.scroll.1 = (self.scroll.1.min(max_y) / rows_per_cell) * rows_per_cell;
+    }
+
+    /// The canvas cells currently in view, in canvas coordinates.
+    fn visible_region(&self) -> Rect {
+        let rows_per_cell = if self.half_block { 2 } else { 1 };
+        let (view_width, view_rows) = ui::canvas_view_size(&self.last_ui, self.zoom);
+        let (x, y) = self.scroll;
+        let width = view_width.min(self.canvas.width().saturating_sub(x));
+        let height = (view_rows * rows_per_cell).min(self.canvas.height().saturating_sub(y));
+        Rect::new(x, y, width.max(1), height.max(1))
+    }
+
+    /// Moves the keyboard cursor, continuing any drag it started like a mouse would.
+    fn move_cursor(&mut self, dx: i32, dy: i32) {
+        let Some(cursor) = self.cursor else {
+            self.show_cursor();
+            return;
+        };
+
+        let point = Point::new(
+            (cursor.x + dx).clamp(0, self.canvas.width() as i32 - 1),
+            (cursor.y + dy).clamp(0, self.canvas.height() as i32 - 1),
+        );
+        self.cursor = Some(point);
+        self.hover = Some(point);
+        self.scroll_to_cursor(point);
+
+        if !matches!(
+            self.mouse_mode,
+            MouseMode::Idle | MouseMode::TextEntry { .. } | MouseMode::Pan { .. }
+        ) {
+            self.drag_left_draw(point);
+        }
+    }
+
+    fn show_cursor(&mut self) {
+        let point = self
+            .hover
+            .unwrap_or(Point::new(self.scroll.0 as i32, self.scroll.1 as i32));
+        self.cursor = Some(point);
+        self.hover = Some(point);
+        self.status = "Keyboard cursor: arrows move, Space/Enter presses".to_string();
+    }
+
+    /// Space/Enter acts as a click: the first press starts a stroke or shape at the
+    /// cursor, the next one finishes it there.
+    fn cursor_press(&mut self) {
+        let Some(point) = self.cursor else {
+            self.show_cursor();
+            return;
+        };
+
+        self.keyboard_press = true;
+        match self.mouse_mode {
+            MouseMode::Idle => {
+                self.begin_left_draw(point, self.color);
+                if matches!(self.mouse_mode, MouseMode::ShapeDrag { .. }) {
+                    self.status = "Move to the end point and press again".to_string();
+                }
+            }
+            MouseMode::Pan { .. } => {}
+            _ => self.finish_left_draw(Some(point)),
+        }
+        self.keyboard_press = false;
+    }
+
+    fn scroll_to_cursor(&mut self, point: Point) {
+        let rows_per_cell = if self.half_block { 2 } else { 1 };
+        let (view_width, view_rows) = ui::canvas_view_size(&self.last_ui, self.zoom);
+        let (view_width, view_height) = (view_width as i32, (view_rows * rows_per_cell) as i32);
+
+        let mut x = self.scroll.0 as i32;
+        let mut y = self.scroll.1 as i32;
+        if point.x < x {
+            x = point.x;
+        } else if point.x >= x + view_width {
+            x = point.x - view_width + 1;
+        }
+        if point.y < y {
+            y = point.y;
+        } else if point.y >= y + view_height {
+            y = point.y - view_height + 1;
+        }
+        // resize_to_fit snaps half-block scrolling back onto even rows.
+        self.scroll = (x.max(0) as u16, y.max(0) as u16);
+    }
+
+    /// Zooms the view, keeping the hovered or cursor cell in sight; the canvas size never changes.
+    fn set_zoom(&mut self, zoom: u16) {
+        self.zoom = zoom.clamp(1, MAX_ZOOM);
+        self.status = format!("Zoom: {}x", self.zoom);
+        if let Some(point) = self.cursor.or(self.hover) {
+            self.scroll_to_cursor(point);
+        }
+    }
+
+    /// Back to the top-left corner at 1x, for when panning and zooming got lost. A canvas smaller
+    /// than the window is drawn centered in it.
+    fn reset_view(&mut self) {
+        self.zoom = 1;
+        self.scroll = (0, 0);
+        self.status = "View reset to the top-left corner at 1x".to_string();
+    }
+
+    fn center_view_on(&mut self, point: Point) {
+        let rows_per_cell = if self.half_block { 2 } else { 1 };
+        let (view_width, view_rows) = ui::canvas_view_size(&self.last_ui, self.zoom);
+        let x = point.x - i32::from(view_width / 2);
+        let y = point.y - i32::from(view_rows * rows_per_cell / 2);
+        // resize_to_fit clamps the far edge on the next frame.
+        self.scroll = (x.max(0) as u16, y.max(0) as u16);
+    }
+
+    fn scroll_by(&mut self, dx: i32, dy: i32) {
+        let rows_per_cell = if self.half_block { 2 } else { 1 };
+        let x = self.scroll.0 as i32 + dx * SCROLL_STEP as i32;
+        let y = self.scroll.1 as i32 + dy * (SCROLL_STEP * rows_per_cell) as i32;
+        // resize_to_fit clamps the far edge on the next frame.
+        self.scroll = (x.max(0) as u16, y.max(0) as u16);
+    }
+
+    fn set_canvas_size(&mut self, size: Option<(u16, u16)>) {
+        let Some((width, height)) = size else {
+            self.fixed_size = false;
+            self.status = "Canvas follows the window size".to_string();
+            return;
+        };
+
+        self.reshape_layers(|canvas| canvas.resize_preserve(width, height));
+        self.fixed_size = true;
+        self.status = format!("Canvas size fixed at {}x{}", width, height);
     }
 
     fn current_file_name(&self) -> Option<&str> {
@@ -198,26 +964,178 @@ impl App {
             .and_then(|s| s.to_str())
     }
 
+    /// Centers the symmetry axes on the pointer, or back on the canvas when they are there already.
+    fn move_mirror_axis(&mut self) {
+        let target = self.hover.map(|p| Point::new(p.x * 2, p.y * 2));
+        if target.is_none() || target == self.mirror_axis {
+            self.mirror_axis = None;
+            self.status = "Symmetry axes at the canvas center".to_string();
+        } else {
+            self.mirror_axis = target;
+            if let Some(p) = self.hover {
+                self.status = format!("Symmetry axes through {},{}", p.x, p.y);
+            }
+        }
+    }
+
+    /// Toggles the attribute with this `.tpaint` letter (`b`, `i`, `u`, `r`) for new cells.
+    fn toggle_attr(&mut self, letter: char) {
+        let Some(&(attr, _, name)) = CellAttrs::ALL.iter().find(|(_, l, _)| *l == letter) else {
+            return;
+        };
+        self.attrs = self.attrs.toggled(attr);
+        let state = if self.attrs.contains(attr) {
+            "on"
+        } else {
+            "off"
+        };
+        self.status = format!("{}{} {state}", name[..1].to_uppercase(), &name[1..]);
+    }
+
+    /// The size tools stamp with: the Eraser has its own, everything else uses the brush size.
+    fn active_size(&self) -> u8 {
+        if self.tool == Tool::Eraser {
+            self.eraser_size
+        } else {
+            self.brush_size
+        }
+    }
+
+    fn set_active_size(&mut self, size: u8) {
+        if self.tool == Tool::Eraser {
+            self.eraser_size = size;
+            self.status = format!("Eraser size: {size}");
+        } else {
+            self.brush_size = size;
+            self.status = format!("Brush size: {size}");
+        }
+    }
+
     fn current_draw_spec(&self) -> DrawSpec {
         DrawSpec {
             tool: self.tool,
-            ch: self.brush_char,
+            ch: if self.half_block {
+                HALF_BLOCK_PIXEL
+            } else {
+                self.brush_char
+            },
             color: self.color,
-            size: self.brush_size,
+            bg: self.bg,
+            size: self.active_size(),
+            shape: self.brush_shape,
+            spacing: self.brush_spacing,
+            rainbow: self.rainbow,
+            literal_space: self.literal_spaces,
+            background: self.background_mode,
+            symmetry: self.symmetry,
+            mirror_axis: self.mirror_axis(),
+            line_style: self.line_style,
+            corner_radius: self.corner_radius,
+            shaded_lines: self.shaded_lines,
+            attrs: self.attrs,
         }
     }
 
+    /// Twice the position of the symmetry axes; the canvas center unless moved with Shift+A.
+    fn mirror_axis(&self) -> Point {
+        self.mirror_axis.unwrap_or(Point::new(
+            self.canvas.width() as i32 - 1,
+            self.canvas.height() as i32 - 1,
+        ))
+    }
+
     fn prompt_view(&self) -> Option<ui::PromptView<'_>> {
         match &self.prompt {
             PromptState::Save(input) => Some(ui::PromptView {
-                title:
-                    "Save file (JSON if .json, otherwise ASCII) - Enter to confirm, Esc to cancel",
+                title: "Save file (.json, .json.gz/.tpz, .tpaint, .ppm/.png/.svg/.html export, otherwise ASCII) - Enter to confirm, Esc to cancel",
+                input,
+            }),
+            PromptState::ExportView(input) => Some(ui::PromptView {
+                title: "Export only the part of the canvas in view (any save format) - Enter to confirm, Esc to cancel",
+                input,
+            }),
+            PromptState::Import(input) => Some(ui::PromptView {
+                title: "Import file at the pointer (blank cells stay transparent) - Enter to confirm",
+                input,
+            }),
+            PromptState::Reference(input) => Some(ui::PromptView {
+                title: "Reference drawing shown dimmed behind the canvas (leave empty to remove it) - Enter to confirm",
                 input,
             }),
             PromptState::Load(input) => Some(ui::PromptView {
-                title: "Load file (.json or ASCII) - Enter to confirm, Esc to cancel",
+                title: "Load file (.json, .json.gz/.tpz, .tpaint, .png/.jpg as ASCII art, or ASCII) - Enter to confirm, Esc to cancel",
+                input,
+            }),
+            PromptState::ExportCellSize(input) => Some(ui::PromptView {
+                title: "Image export cell size in pixels (WxH) - Enter to confirm, Esc to cancel",
+                input,
+            }),
+            PromptState::SvgDefaultFill(input) => Some(ui::PromptView {
+                title: "SVG text color for default-colored cells (#RRGGBB) - Enter to confirm",
+                input,
+            }),
+            PromptState::ImageColors(input) => Some(ui::PromptView {
+                title: "PPM/PNG default foreground and background (#RRGGBB #RRGGBB) - Enter to confirm",
+                input,
+            }),
+            PromptState::FillWarnThreshold(input) => Some(ui::PromptView {
+                title: "Warn when a fill covers more than N cells - Enter to confirm, Esc to cancel",
+                input,
+            }),
+            PromptState::GradientEnd(input) => Some(ui::PromptView {
+                title: "Gradient end color (1-8, name or #RRGGBB) - Enter to confirm",
+                input,
+            }),
+            PromptState::BrushChar(input) => Some(ui::PromptView {
+                title: "Brush character (any single glyph, e.g. │ or ─) - Enter to confirm",
                 input,
             }),
+            PromptState::BrushSize(input) => Some(ui::PromptView {
+                title: if self.tool == Tool::Eraser {
+                    "Eraser size 1-16 - Enter to confirm, Esc to cancel"
+                } else {
+                    "Brush size 1-16 - Enter to confirm, Esc to cancel"
+                },
+                input,
+            }),
+            PromptState::GridSpacing(input) => Some(ui::PromptView {
+                title: "Grid line every N cells - Enter to confirm, Esc to cancel",
+                input,
+            }),
+            PromptState::Backdrop(input) => Some(ui::PromptView {
+                title: "Backdrop color for empty cells (1-8, name or #RRGGBB; 0 removes it) - Enter to confirm",
+                input,
+            }),
+            PromptState::LuminanceBands(input) => Some(ui::PromptView {
+                title: "Recolor by brightness: colors dark to light, e.g. 4,2,7 or #003,#0a0,#fff",
+                input,
+            }),
+            PromptState::CanvasSize(input) => Some(ui::PromptView {
+                title: "Canvas size WxH (empty or 'auto' to follow the window) - Enter to confirm",
+                input,
+            }),
+            PromptState::RenameLayer(input) => Some(ui::PromptView {
+                title: "Layer name (empty for none) - Enter to confirm, Esc to cancel",
+                input,
+            }),
+            PromptState::Color(input) => Some(ui::PromptView {
+                title: "Hex color (#RGB or #RRGGBB) - Enter to confirm, Esc to cancel",
+                input,
+            }),
+            PromptState::Confirm(action) => Some(ui::PromptView {
+                title: action.confirm_title(),
+                input: "",
+            }),
+            PromptState::ConfirmQuit => Some(ui::PromptView {
+                title: "Unsaved changes - press q again to quit, s to save, Esc to cancel",
+                input: "",
+            }),
+            PromptState::RecentFiles(_)
+            | PromptState::MessageLog(_)
+            | PromptState::Help(_)
+            | PromptState::History(_)
+            | PromptState::ColorPicker(_)
+            | PromptState::Stats(_) => None,
             PromptState::None => None,
         }
     }
@@ -232,71 +1150,61 @@ impl App {
         }
 
         if self.prompt_is_active() {
-            self.handle_prompt_key(key);
-            return false;
+            return self.handle_prompt_key(key);
         }
 
-        if is_undo_shortcut(key) {
-            self.perform_undo();
-            return false;
+        if matches!(self.mouse_mode, MouseMode::TextEntry { .. }) {
+            if !has_shortcut_modifier(key.modifiers) && self.handle_text_key(key) {
+                return false;
+            }
+            // Anything the text session doesn't consume ends it before running as usual.
+            self.finish_text_entry();
         }
 
-        if is_redo_shortcut(key) {
-            self.perform_redo();
+        if key.modifiers.contains(KeyModifiers::ALT) {
+            if let KeyCode::Char(letter) = key.code {
+                self.toggle_attr(letter.to_ascii_lowercase());
+            }
             return false;
         }
 
-        if has_shortcut_modifier(key.modifiers) {
-            match key.code {
-                KeyCode::Char('s') | KeyCode::Char('S') => {
-                    self.open_save_prompt();
-                    return false;
-                }
-                KeyCode::Char('o') | KeyCode::Char('O') => {
-                    self.open_load_prompt();
-                    return false;
-                }
-                _ => {}
-            }
+        if let Some(action) = self.keymap.action(key) {
+            return self.run_action(action);
         }
 
         match key.code {
-            KeyCode::Char(ch) => {
-                let ch = ch.to_ascii_lowercase();
-                match ch {
-                    'q' => return true,
-                    'p' => self.tool = Tool::Pencil,
-                    'e' => self.tool = Tool::Eraser,
-                    'l' => self.tool = Tool::Line,
-                    'r' => self.tool = Tool::Rectangle,
-                    'c' => self.tool = Tool::Circle,
-                    'f' => self.tool = Tool::Fill,
-                    'u' => self.perform_undo(),
-                    'y' => self.perform_redo(),
-                    '[' => {
-                        self.brush_size = self.brush_size.saturating_sub(1).max(1);
-                    }
-                    ']' => {
-                        self.brush_size = (self.brush_size + 1).min(3);
-                    }
-                    't' => {
-                        self.filled_shapes = !self.filled_shapes;
-                    }
-                    'b' => self.cycle_brush_char(true),
-                    '0' | 'd' => self.color = PaintColor::Default,
-                    '1'..='8' => {
-                        let idx = (ch as u8) - b'0';
-                        if let Some(color) = PaintColor::from_quick_index(idx) {
-                            self.color = color;
-                        }
-                    }
-                    _ => {}
+            KeyCode::Char(ch @ '1'..='9') => {
+                let idx = usize::from(ch as u8 - b'1');
+                if let Some(&color) = self.palette.get(idx) {
+                    self.color = color;
                 }
             }
+            KeyCode::Enter if !self.fill_seeds.is_empty() => self.commit_multi_fill(),
+            KeyCode::Enter if !self.polygon.is_empty() => self.finish_polygon(true),
+            KeyCode::Enter => self.cursor_press(),
             KeyCode::Esc => {
-                if matches!(self.mouse_mode, MouseMode::ShapeDrag { .. }) {
+                if let MouseMode::ResizeCanvas { before } = &mut self.mouse_mode {
+                    self.canvas = std::mem::replace(before, Canvas::new(1, 1));
+                    self.mouse_mode = MouseMode::Idle;
+                    self.status = "Resize cancelled".to_string();
+                } else if matches!(self.mouse_mode, MouseMode::ShapeDrag { .. }) {
                     self.mouse_mode = MouseMode::Idle;
                     self.status = "Shape cancelled".to_string();
+                } else if matches!(self.mouse_mode, MouseMode::Measure { .. }) {
+                    self.mouse_mode = MouseMode::Idle;
+                    self.status = "Measure cancelled".to_string();
+                } else if let MouseMode::MoveSelection { builder, .. } = &mut self.mouse_mode {
+                    builder.revert(&mut self.canvas);
+                    self.mouse_mode = MouseMode::Idle;
+                    self.status = "Move cancelled".to_string();
+                } else if !self.polygon.is_empty() {
+                    self.polygon.clear();
+                    self.status = "Polygon cancelled".to_string();
+                } else if !self.fill_seeds.is_empty() {
+                    self.fill_seeds.clear();
+                    self.status = "Fill seeds cleared".to_string();
+                } else if self.selection.take().is_some() {
+                    self.status = "Selection cleared".to_string();
                 }
             }
             _ => {}
@@ -305,59 +1213,767 @@ impl App {
         false
     }
 
-    fn perform_undo(&mut self) {
-        if self.history.undo(&mut self.canvas) {
-            self.status = "Undo".to_string();
-        } else {
-            self.status = "Nothing to undo".to_string();
-        }
-    }
-
-    fn perform_redo(&mut self) {
-        if self.history.redo(&mut self.canvas) {
-            self.status = "Redo".to_string();
-        } else {
-            self.status = "Nothing to redo".to_string();
-        }
-    }
-
-    fn handle_prompt_key(&mut self, key: KeyEvent) {
-        match key.code {
-            KeyCode::Esc => {
-                self.prompt = PromptState::None;
-                self.status = "Prompt cancelled".to_string();
-                return;
-            }
-            KeyCode::Enter => {
-                self.commit_prompt();
-                return;
+    /// Runs a key-mapped action; returns true when the app should quit.
+    fn run_action(&mut self, action: Action) -> bool {
+        match action {
+            Action::Quit if self.dirty => self.prompt = PromptState::ConfirmQuit,
+            Action::Quit => return true,
+            Action::Undo => self.perform_undo(),
+            Action::Redo => self.perform_redo(),
+            Action::Save => self.open_save_prompt(),
+            Action::ExportView => self.prompt = PromptState::ExportView("view.txt".to_string()),
+            Action::Load => self.open_load_prompt(),
+            Action::Import => self.prompt = PromptState::Import(String::new()),
+            Action::Reference => self.prompt = PromptState::Reference(String::new()),
+            Action::RecentFiles => self.open_recent_files(),
+            Action::ExportCellSize => {
+                self.prompt = PromptState::ExportCellSize(format!(
+                    "{}x{}",
+                    self.export_options.cell_width, self.export_options.cell_height
+                ));
             }
-            KeyCode::Backspace => {
-                if let Some(input) = self.prompt_input_mut() {
-                    input.pop();
+            Action::SvgFill => self.prompt = PromptState::SvgDefaultFill(String::new()),
+            Action::ImageColors => self.prompt = PromptState::ImageColors(String::new()),
+            Action::LuminanceBands => self.prompt = PromptState::LuminanceBands(String::new()),
+            Action::Copy => self.copy_selection(),
+            Action::Cut => self.cut_selection(),
+            Action::Paste => self.paste_clipboard(),
+            Action::Clear => {
+                if self.canvas.content_extent() == (0, 0) {
+                    self.status = "Canvas is already blank".to_string();
+                } else {
+                    self.request_canvas_action(CanvasAction::Clear);
                 }
-                return;
             }
-            KeyCode::Char(c) => {
-                if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT {
-                    if let Some(input) = self.prompt_input_mut() {
-                        input.push(c);
-                    }
+            Action::Trim => {
+                if self.trim_bounds().is_some() {
+                    self.request_canvas_action(CanvasAction::Trim);
                 }
             }
-            _ => {}
-        }
-    }
-
-    fn prompt_input_mut(&mut self) -> Option<&mut String> {
-        match &mut self.prompt {
-            PromptState::Save(input) | PromptState::Load(input) => Some(input),
-            PromptState::None => None,
-        }
-    }
-
-    fn open_save_prompt(&mut self) {
-        let default_name = self
+            Action::CanvasSize => {
+                self.prompt = PromptState::CanvasSize(format!(
+                    "{}x{}",
+                    self.canvas.width(),
+                    self.canvas.height()
+                ));
+            }
+            Action::FillWarnThreshold => {
+                self.prompt = PromptState::FillWarnThreshold(self.fill_warn_threshold.to_string());
+            }
+            Action::BrushSize => {
+                self.prompt = PromptState::BrushSize(self.active_size().to_string());
+            }
+            Action::GridSpacing => {
+                self.prompt = PromptState::GridSpacing(self.grid_spacing.to_string());
+            }
+            Action::ScrollLeft => self.scroll_by(-1, 0),
+            Action::ScrollRight => self.scroll_by(1, 0),
+            Action::ScrollUp => self.scroll_by(0, -1),
+            Action::ScrollDown => self.scroll_by(0, 1),
+            Action::CursorLeft => self.nudge_or_move_cursor(-1, 0),
+            Action::CursorRight => self.nudge_or_move_cursor(1, 0),
+            Action::CursorUp => self.nudge_or_move_cursor(0, -1),
+            Action::CursorDown => self.nudge_or_move_cursor(0, 1),
+            Action::Press => self.cursor_press(),
+            Action::Pencil => self.tool = Tool::Pencil,
+            Action::Eraser => self.tool = Tool::Eraser,
+            Action::Line => self.tool = Tool::Line,
+            Action::Rectangle => self.tool = Tool::Rectangle,
+            Action::Circle => self.tool = Tool::Circle,
+            Action::Fill => self.tool = Tool::Fill,
+            Action::Spray => self.tool = Tool::Spray,
+            Action::Text => self.tool = Tool::Text,
+            Action::Select => self.tool = Tool::Select,
+            Action::BoxLine => self.tool = Tool::BoxLine,
+            Action::Gradient => self.tool = Tool::Gradient,
+            Action::ReplaceAll => self.tool = Tool::ReplaceAll,
+            Action::Arrow => self.tool = Tool::Arrow,
+            Action::Polygon => self.tool = Tool::Polygon,
+            Action::Measure => self.tool = Tool::Measure,
+            Action::NewLayer if !self.layers_busy() => self.add_layer(),
+            Action::DeleteLayer if !self.layers_busy() => self.delete_layer(),
+            Action::LayerUp if !self.layers_busy() => self.step_layer(true),
+            Action::LayerDown if !self.layers_busy() => self.step_layer(false),
+            Action::FlattenLayers if !self.layers_busy() => self.flatten_layers(),
+            Action::MoveLayerUp if !self.layers_busy() => self.move_layer(true),
+            Action::MoveLayerDown if !self.layers_busy() => self.move_layer(false),
+            Action::NewLayer
+            | Action::DeleteLayer
+            | Action::LayerUp
+            | Action::LayerDown
+            | Action::FlattenLayers
+            | Action::MoveLayerUp
+            | Action::MoveLayerDown => {}
+            Action::LayerOpacityDown => self.step_layer_opacity(false),
+            Action::LayerOpacityUp => self.step_layer_opacity(true),
+            Action::RenameLayer => {
+                let name = self.layers[self.active_layer].name.clone();
+                self.prompt = PromptState::RenameLayer(name);
+            }
+            Action::ToggleLayer => self.toggle_layer_visibility(),
+            Action::FromCenter => {
+                self.from_center = !self.from_center;
+                self.status = if self.from_center {
+                    "Rectangles and ellipses grow from the center".to_string()
+                } else {
+                    "Rectangles and ellipses go corner to corner".to_string()
+                };
+            }
+            Action::NextTool => {
+                self.tool = self.tool.next();
+                self.status = format!("Tool: {}", self.tool.name());
+            }
+            Action::PrevTool => {
+                self.tool = self.tool.prev();
+                self.status = format!("Tool: {}", self.tool.name());
+            }
+            Action::AsciiHeader => {
+                self.export_options.ascii_header = !self.export_options.ascii_header;
+                self.status = if self.export_options.ascii_header {
+                    "ASCII saves include a size header".to_string()
+                } else {
+                    "ASCII saves without size header".to_string()
+                };
+            }
+            Action::MessageLog => self.prompt = PromptState::MessageLog(0),
+            Action::History => self.open_history(),
+            Action::ColorPicker => self.open_color_picker(),
+            Action::Backdrop => self.prompt = PromptState::Backdrop(self.color.name()),
+            Action::Stats => {
+                self.prompt = PromptState::Stats(ui::StatsView {
+                    canvas: self.canvas.stats(),
+                    undo_len: self.history.undo_len(),
+                    redo_len: self.history.redo_len(),
+                    history_bytes: self.history.memory_usage(),
+                })
+            }
+            Action::Minimap => {
+                self.minimap = !self.minimap;
+                self.status = if self.minimap {
+                    "Minimap shown while the canvas is larger than the view".to_string()
+                } else {
+                    "Minimap hidden".to_string()
+                };
+            }
+            Action::ZoomIn => self.set_zoom(self.zoom + 1),
+            Action::ZoomOut => self.set_zoom(self.zoom - 1),
+            Action::ResetView => self.reset_view(),
+            Action::ShadedLines => {
+                self.shaded_lines = !self.shaded_lines;
+                self.status = if self.shaded_lines {
+                    "Shaded lines: ░▒▓█ smooth out diagonals".to_string()
+                } else {
+                    "Shaded lines off".to_string()
+                };
+            }
+            Action::CornerRadius => {
+                self.corner_radius = (self.corner_radius + 1) % (MAX_CORNER_RADIUS + 1);
+                self.status = if self.corner_radius == 0 {
+                    "Rectangle corners: square".to_string()
+                } else {
+                    format!("Rectangle corners: rounded, radius {}", self.corner_radius)
+                };
+            }
+            Action::Symmetry => {
+                self.symmetry = self.symmetry.next();
+                self.status = format!("Symmetry: {}", self.symmetry.name());
+            }
+            Action::MirrorAxis => self.move_mirror_axis(),
+            Action::LineStyle => {
+                self.line_style = self.line_style.next();
+                self.status = format!("Line style: {}", self.line_style.name());
+            }
+            Action::Help => self.prompt = PromptState::Help(0),
+            Action::FillPattern => {
+                self.fill_pattern = self.fill_pattern.next();
+                self.status = format!("Fill pattern: {}", self.fill_pattern.name());
+            }
+            Action::Grid => {
+                self.show_grid = !self.show_grid;
+                self.status = if self.show_grid {
+                    format!("Grid on (every {} cells)", self.grid_spacing)
+                } else {
+                    "Grid off".to_string()
+                };
+            }
+            Action::ConfirmDestructive => {
+                self.confirm_destructive = !self.confirm_destructive;
+                self.status = if self.confirm_destructive {
+                    "Whole-canvas actions ask for confirmation".to_string()
+                } else {
+                    "Whole-canvas actions run without confirmation".to_string()
+                };
+            }
+            Action::FlipHorizontal => self.request_canvas_action(CanvasAction::FlipHorizontal),
+            Action::FlipVertical => self.request_canvas_action(CanvasAction::FlipVertical),
+            Action::MultiFill => {
+                self.multi_fill = !self.multi_fill;
+                self.fill_seeds.clear();
+                self.status = if self.multi_fill {
+                    "Multi-seed fill: click seeds, Enter to fill".to_string()
+                } else {
+                    "Multi-seed fill disabled".to_string()
+                };
+            }
+            Action::GradientEnd => self.prompt = PromptState::GradientEnd(String::new()),
+            Action::FillConnectivity => {
+                self.fill_connectivity = match self.fill_connectivity {
+                    Connectivity::Four => Connectivity::Eight,
+                    Connectivity::Eight => Connectivity::Four,
+                };
+                self.status = format!("Fill spreads {}", self.fill_connectivity.name());
+            }
+            Action::FillMatch => {
+                self.fill_match = self.fill_match.next();
+                self.status = format!("Fill matches {}", self.fill_match.name());
+            }
+            Action::SizeDown => {
+                self.set_active_size(self.active_size().saturating_sub(1).max(1));
+            }
+            Action::SizeUp => {
+                self.set_active_size((self.active_size() + 1).min(MAX_BRUSH_SIZE));
+            }
+            Action::SpacingDown => {
+                self.brush_spacing = self.brush_spacing.saturating_sub(1).max(1);
+                self.status = format!("Brush spacing: {}", self.brush_spacing);
+            }
+            Action::PatternBrush => self.toggle_pattern_brush(),
+            Action::SpacingUp => {
+                self.brush_spacing = (self.brush_spacing + 1).min(MAX_BRUSH_SPACING);
+                self.status = format!("Brush spacing: {}", self.brush_spacing);
+            }
+            Action::FilledShapes => self.toggle_filled_shapes(),
+            Action::Lighten => {
+                self.color = self.color.lighten(BRIGHTNESS_STEP_PERCENT);
+                self.status = format!("Color: {}", self.color.name());
+            }
+            Action::Darken => {
+                self.color = self.color.darken(BRIGHTNESS_STEP_PERCENT);
+                self.status = format!("Color: {}", self.color.name());
+            }
+            Action::HalfBlock => {
+                self.half_block = !self.half_block;
+                self.status = if self.half_block {
+                    "Half-block mode: each row holds two pixels".to_string()
+                } else {
+                    "Character mode".to_string()
+                };
+            }
+            Action::BlankGlyph => self.cycle_blank_glyph(),
+            Action::LiteralSpaces => {
+                self.literal_spaces = !self.literal_spaces;
+                self.status = if self.literal_spaces {
+                    "Space brush paints literal spaces".to_string()
+                } else {
+                    "Space brush paints blocks of color".to_string()
+                };
+            }
+            Action::BackgroundMode => {
+                self.background_mode = !self.background_mode;
+                self.status = if self.background_mode {
+                    "Background mode: tools paint cell backgrounds".to_string()
+                } else {
+                    "Foreground mode".to_string()
+                };
+            }
+            Action::Rainbow => {
+                self.rainbow = !self.rainbow;
+                self.status = if self.rainbow {
+                    "Rainbow brush enabled".to_string()
+                } else {
+                    "Rainbow brush disabled".to_string()
+                };
+            }
+            Action::Crosshair => {
+                self.crosshair = !self.crosshair;
+                self.status = if self.crosshair {
+                    "Crosshair guides on".to_string()
+                } else {
+                    "Crosshair guides off".to_string()
+                };
+            }
+            Action::BrushShape => {
+                self.brush_shape = self.brush_shape.toggled();
+                self.status = format!("Brush shape: {}", self.brush_shape.name());
+            }
+            Action::NextBrushChar => self.cycle_brush_char(true),
+            Action::BrushChar => self.prompt = PromptState::BrushChar(String::new()),
+            Action::RotateRight => self.request_canvas_action(CanvasAction::Rotate(true)),
+            Action::RotateLeft => self.request_canvas_action(CanvasAction::Rotate(false)),
+            Action::ColorPrompt => self.prompt = PromptState::Color(String::new()),
+            Action::DefaultColor => {
+                self.color = PaintColor::Default;
+                self.bg = None;
+            }
+            Action::SwapColors => {
+                std::mem::swap(&mut self.color, &mut self.secondary_color);
+                self.status = format!(
+                    "Color: {} (secondary: {})",
+                    self.color.name(),
+                    self.secondary_color.name()
+                );
+            }
+            Action::PaletteAdd => self.add_to_palette(),
+            Action::PaletteRemove => self.remove_from_palette(),
+        }
+        false
+    }
+
+    /// Handles a key while typing on the canvas. Returns false for keys the session ignores.
+    fn handle_text_key(&mut self, key: KeyEvent) -> bool {
+        let width = self.canvas.width() as i32;
+        let height = self.canvas.height() as i32;
+        let MouseMode::TextEntry {
+            origin,
+            cursor,
+            spec,
+            builder,
+        } = &mut self.mouse_mode
+        else {
+            return false;
+        };
+
+        match key.code {
+            KeyCode::Char(ch) => {
+                // Typed text is plain glyphs, so a typed space is a literal one rather than a
+                // block of background like the space brush paints.
+                let cell = PaintCell::new(ch, spec.color).with_attrs(spec.attrs);
+                builder.apply(&mut self.canvas, cursor.x, cursor.y, cell);
+                cursor.x += 1;
+                if cursor.x >= width {
+                    cursor.x = origin.x;
+                    cursor.y += 1;
+                }
+            }
+            KeyCode::Backspace => {
+                if cursor.x > origin.x {
+                    cursor.x -= 1;
+                } else if cursor.y > origin.y {
+                    cursor.x = width - 1;
+                    cursor.y -= 1;
+                } else {
+                    return true;
+                }
+                builder.apply(&mut self.canvas, cursor.x, cursor.y, PaintCell::blank());
+            }
+            KeyCode::Enter => {
+                cursor.x = origin.x;
+                cursor.y += 1;
+            }
+            // Tab moves to the next tab stop instead of switching tools mid-sentence.
+            KeyCode::Tab => {
+                let column = cursor.x - origin.x;
+                cursor.x = origin.x + (column / TEXT_TAB_WIDTH + 1) * TEXT_TAB_WIDTH;
+                if cursor.x >= width {
+                    cursor.x = origin.x;
+                    cursor.y += 1;
+                }
+            }
+            KeyCode::BackTab => {}
+            KeyCode::Esc => {
+                self.finish_text_entry();
+                return true;
+            }
+            _ => return false,
+        }
+
+        if cursor.y >= height {
+            self.finish_text_entry();
+            self.status = "Text reached the bottom of the canvas".to_string();
+        }
+        true
+    }
+
+    fn finish_text_entry(&mut self) {
+        if !matches!(self.mouse_mode, MouseMode::TextEntry { .. }) {
+            return;
+        }
+        if let MouseMode::TextEntry { builder, .. } =
+            std::mem::replace(&mut self.mouse_mode, MouseMode::Idle)
+        {
+            self.commit_builder(builder);
+            self.status = "Text committed".to_string();
+        }
+    }
+
+    fn perform_undo(&mut self) {
+        self.attach_active();
+        let undone = self.history.undo(&mut self.layers);
+        self.detach_active();
+        if undone {
+            self.mark_dirty();
+            self.status = "Undo".to_string();
+        } else {
+            self.status = "Nothing to undo".to_string();
+        }
+    }
+
+    fn perform_redo(&mut self) {
+        self.attach_active();
+        let redone = self.history.redo(&mut self.layers);
+        self.detach_active();
+        if redone {
+            self.mark_dirty();
+            self.status = "Redo".to_string();
+        } else {
+            self.status = "Nothing to redo".to_string();
+        }
+    }
+
+    fn list_view(&self) -> Option<ui::ListView> {
+        match self.prompt {
+            PromptState::RecentFiles(selected) => Some(ui::ListView {
+                title: " Recent files - Up/Down, Enter to open, Esc to cancel ",
+                items: self
+                    .recent_files
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect(),
+                selected,
+            }),
+            PromptState::History(selected) => Some(ui::ListView {
+                title: " History, newest first - Up/Down to scroll, Esc to close ",
+                items: self.history_items(),
+                selected,
+            }),
+            _ => None,
+        }
+    }
+
+    /// One line per history step, newest first: pending redos above the undo stack.
+    fn history_items(&self) -> Vec<String> {
+        let describe = |op: &Operation| match op.bounds() {
+            Some(bounds) if op.is_whole_canvas() => {
+                format!("whole canvas, {}x{}", bounds.width, bounds.height)
+            }
+            Some(bounds) => format!(
+                "{} cell{} in {}x{} at {},{}",
+                op.changes.len(),
+                if op.changes.len() == 1 { "" } else { "s" },
+                bounds.width,
+                bounds.height,
+                bounds.x,
+                bounds.y
+            ),
+            None => "no changes".to_string(),
+        };
+
+        let mut items: Vec<String> = self
+            .history
+            .redo_ops()
+            .enumerate()
+            .map(|(i, op)| format!("redo {:>3}: {}", i + 1, describe(op)))
+            .collect();
+        items.reverse();
+        items.extend(
+            self.history
+                .undo_ops()
+                .enumerate()
+                .map(|(i, op)| format!("undo {:>3}: {}", i + 1, describe(op))),
+        );
+        items
+    }
+
+    /// Opens the color picker on the current color's swatch, if it has one.
+    fn open_color_picker(&mut self) {
+        let selected = (0..=u8::MAX)
+            .find(|&index| PaintColor::xterm(index) == self.color)
+            .unwrap_or(0);
+        self.prompt = PromptState::ColorPicker(selected);
+    }
+
+    fn pick_palette_color(&mut self, index: u8) {
+        self.prompt = PromptState::None;
+        self.color = PaintColor::xterm(index);
+        self.status = format!("Color: {}", self.color.name());
+    }
+
+    fn open_history(&mut self) {
+        if self.history.undo_len() + self.history.redo_len() == 0 {
+            self.status = "No history yet".to_string();
+        } else {
+            self.prompt = PromptState::History(self.history.redo_len());
+        }
+    }
+
+    fn open_recent_files(&mut self) {
+        if self.recent_files.is_empty() {
+            self.status = "No recent files yet".to_string();
+        } else {
+            self.prompt = PromptState::RecentFiles(0);
+        }
+    }
+
+    /// Moves `path` to the front of the recent-files list and persists it.
+    fn remember_file(&mut self, path: &Path) {
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.recent_files.retain(|existing| *existing != path);
+        self.recent_files.insert(0, path);
+        self.recent_files.truncate(MAX_RECENT_FILES);
+
+        if let Some(list_path) = &self.recent_files_path {
+            if let Err(err) = io::save_recent_files(list_path, &self.recent_files) {
+                self.report_error(format!("Could not update recent files: {err}"));
+            }
+        }
+    }
+
+    fn add_to_palette(&mut self) {
+        if self.color == PaintColor::Default {
+            self.status = "Default is always in the color row".to_string();
+        } else if self.palette.contains(&self.color) {
+            self.status = format!("{} is already in the palette", self.color.name());
+        } else {
+            self.palette.push(self.color);
+            self.status = format!(
+                "Added {} to the palette as {}",
+                self.color.name(),
+                self.palette.len()
+            );
+            self.save_palette();
+        }
+    }
+
+    fn remove_from_palette(&mut self) {
+        let Some(idx) = self.palette.iter().position(|color| *color == self.color) else {
+            self.status = format!("{} is not in the palette", self.color.name());
+            return;
+        };
+        if self.palette.len() == 1 {
+            self.status = "The palette needs at least one color".to_string();
+            return;
+        }
+        self.palette.remove(idx);
+        self.status = format!("Removed {} from the palette", self.color.name());
+        self.save_palette();
+    }
+
+    fn save_palette(&mut self) {
+        if let Some(path) = &self.palette_path {
+            if let Err(err) = io::save_palette(path, &self.palette) {
+                self.report_error(format!("Could not save the palette: {err}"));
+            }
+        }
+    }
+
+    /// Shows an error in the status line for `ERROR_STATUS_TIMEOUT` instead of the usual timeout.
+    fn report_error(&mut self, message: String) {
+        self.error_status = Some(message.clone());
+        self.status = message;
+    }
+
+    /// Restarts the status timeout whenever the message changes, and puts `READY_STATUS` back once
+    /// it runs out. The rest of the status bar (tool, brush, color, position) is always shown.
+    fn expire_status(&mut self) {
+        if self.status != self.shown_status {
+            self.shown_status.clone_from(&self.status);
+            self.status_set_at = Instant::now();
+            return;
+        }
+        let timeout = if self.error_status.as_ref() == Some(&self.status) {
+            ERROR_STATUS_TIMEOUT
+        } else {
+            STATUS_TIMEOUT
+        };
+        if self.status != READY_STATUS && self.status_set_at.elapsed() >= timeout {
+            self.status = READY_STATUS.to_string();
+            self.shown_status.clone_from(&self.status);
+        }
+    }
+
+    /// Records the status line in the message log whenever it changes.
+    fn log_status(&mut self) {
+        if self.status.is_empty()
+            || self.status == READY_STATUS
+            || self
+                .message_log
+                .back()
+                .is_some_and(|(_, message)| *message == self.status)
+        {
+            return;
+        }
+        if self.message_log.len() == MAX_LOG_MESSAGES {
+            self.message_log.pop_front();
+        }
+        self.message_log
+            .push_back((self.started.elapsed(), self.status.clone()));
+    }
+
+    fn message_log_view(&self) -> Option<ui::MessageLogView<'_>> {
+        let PromptState::MessageLog(scroll) = self.prompt else {
+            return None;
+        };
+        Some(ui::MessageLogView {
+            entries: &self.message_log,
+            scroll,
+        })
+    }
+
+    /// Returns `true` when the prompt confirmed quitting.
+    fn handle_prompt_key(&mut self, key: KeyEvent) -> bool {
+        if let PromptState::MessageLog(scroll) | PromptState::Help(scroll) = self.prompt {
+            let help = matches!(self.prompt, PromptState::Help(_));
+            // Wrapped lines can make the log taller than its entry count, so that cap is rough.
+            let (max_scroll, close) = if help {
+                let visible = usize::from(self.last_ui.terminal.height.saturating_sub(2));
+                (ui::help_line_count().saturating_sub(visible), '?')
+            } else {
+                (self.message_log.len().saturating_sub(1), 'L')
+            };
+            let max_scroll = u16::try_from(max_scroll).unwrap_or(u16::MAX);
+            let scroll = match key.code {
+                KeyCode::Up => scroll.saturating_sub(1),
+                KeyCode::Down => (scroll + 1).min(max_scroll),
+                KeyCode::PageUp => scroll.saturating_sub(10),
+                KeyCode::PageDown => scroll.saturating_add(10).min(max_scroll),
+                KeyCode::Esc | KeyCode::F(1) => {
+                    self.prompt = PromptState::None;
+                    return false;
+                }
+                KeyCode::Char(ch) if ch == close => {
+                    self.prompt = PromptState::None;
+                    return false;
+                }
+                _ => scroll,
+            };
+            self.prompt = if help {
+                PromptState::Help(scroll)
+            } else {
+                PromptState::MessageLog(scroll)
+            };
+            return false;
+        }
+
+        if let PromptState::RecentFiles(selected) = &mut self.prompt {
+            let last = self.recent_files.len().saturating_sub(1);
+            match key.code {
+                KeyCode::Up => *selected = selected.saturating_sub(1),
+                KeyCode::Down => *selected = (*selected + 1).min(last),
+                KeyCode::Enter => {
+                    let path = self.recent_files.get(*selected).cloned();
+                    self.prompt = PromptState::None;
+                    if let Some(path) = path {
+                        self.load_file(path);
+                    }
+                }
+                KeyCode::Esc => {
+                    self.prompt = PromptState::None;
+                    self.status = "Prompt cancelled".to_string();
+                }
+                _ => {}
+            }
+            return false;
+        }
+
+        if let PromptState::History(selected) = &mut self.prompt {
+            let last = (self.history.undo_len() + self.history.redo_len()).saturating_sub(1);
+            match key.code {
+                KeyCode::Up => *selected = selected.saturating_sub(1),
+                KeyCode::Down => *selected = (*selected + 1).min(last),
+                KeyCode::Esc | KeyCode::Char('U') => self.prompt = PromptState::None,
+                _ => {}
+            }
+            return false;
+        }
+
+        if matches!(self.prompt, PromptState::Stats(_)) {
+            self.prompt = PromptState::None;
+            return false;
+        }
+
+        if let PromptState::ColorPicker(selected) = &mut self.prompt {
+            let columns = 16;
+            match key.code {
+                KeyCode::Left => *selected = selected.saturating_sub(1),
+                KeyCode::Right => *selected = selected.saturating_add(1),
+                KeyCode::Up => *selected = selected.saturating_sub(columns),
+                KeyCode::Down => *selected = selected.saturating_add(columns),
+                KeyCode::Enter => {
+                    let index = *selected;
+                    self.pick_palette_color(index);
+                }
+                KeyCode::Esc | KeyCode::Char('K') => self.prompt = PromptState::None,
+                _ => {}
+            }
+            return false;
+        }
+
+        if matches!(self.prompt, PromptState::ConfirmQuit) {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Char('Q') => return true,
+                KeyCode::Char('s') | KeyCode::Char('S') => self.open_save_prompt(),
+                KeyCode::Esc => {
+                    self.prompt = PromptState::None;
+                    self.status = "Quit cancelled".to_string();
+                }
+                _ => {}
+            }
+            return false;
+        }
+
+        match key.code {
+            KeyCode::Esc => {
+                // A discarded recovery file belongs to an instance that has exited.
+                if let PromptState::Confirm(CanvasAction::Recover(path)) = &self.prompt {
+                    io::remove_autosave(path);
+                }
+                self.prompt = PromptState::None;
+                self.status = "Prompt cancelled".to_string();
+            }
+            KeyCode::Enter => {
+                self.commit_prompt();
+            }
+            KeyCode::Backspace => {
+                if let Some(input) = self.prompt_input_mut() {
+                    input.pop();
+                }
+            }
+            KeyCode::Char(c)
+                if key.modifiers.is_empty() || key.modifiers == KeyModifiers::SHIFT =>
+            {
+                if let Some(input) = self.prompt_input_mut() {
+                    input.push(c);
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+
+    fn prompt_input_mut(&mut self) -> Option<&mut String> {
+        match &mut self.prompt {
+            PromptState::Save(input)
+            | PromptState::ExportView(input)
+            | PromptState::Load(input)
+            | PromptState::Import(input)
+            | PromptState::Reference(input)
+            | PromptState::ExportCellSize(input)
+            | PromptState::SvgDefaultFill(input)
+            | PromptState::ImageColors(input)
+            | PromptState::FillWarnThreshold(input)
+            | PromptState::GridSpacing(input)
+            | PromptState::BrushSize(input)
+            | PromptState::BrushChar(input)
+            | PromptState::GradientEnd(input)
+            | PromptState::LuminanceBands(input)
+            | PromptState::Backdrop(input)
+            | PromptState::Color(input)
+            | PromptState::CanvasSize(input)
+            | PromptState::RenameLayer(input) => Some(input),
+            PromptState::Confirm(_)
+            | PromptState::ConfirmQuit
+            | PromptState::RecentFiles(_)
+            | PromptState::MessageLog(_)
+            | PromptState::Help(_)
+            | PromptState::History(_)
+            | PromptState::ColorPicker(_)
+            | PromptState::Stats(_)
+            | PromptState::None => None,
+        }
+    }
+
+    fn open_save_prompt(&mut self) {
+        let default_name = self
             .current_file
             .as_ref()
             .map(|p| p.display().to_string())
@@ -365,216 +1981,1639 @@ impl App {
         self.prompt = PromptState::Save(default_name);
     }
 
-    fn open_load_prompt(&mut self) {
-        let default_name = self
-            .current_file
-            .as_ref()
-            .map(|p| p.display().to_string())
-            .unwrap_or_else(|| "canvas.json".to_string());
-        self.prompt = PromptState::Load(default_name);
+    fn open_load_prompt(&mut self) {
+        let default_name = self
+            .current_file
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| "canvas.json".to_string());
+        self.prompt = PromptState::Load(default_name);
+    }
+
+    fn commit_prompt(&mut self) {
+        let prompt = std::mem::replace(&mut self.prompt, PromptState::None);
+        match prompt {
+            PromptState::Save(input) => {
+                let path = io::parse_path(&input, "canvas.json");
+                match io::save_layers(&path, &self.layer_stack(), &self.export_options) {
+                    Ok(()) if io::is_image_export(&path) => {
+                        self.status = format!("Exported {}", path.display());
+                    }
+                    Ok(()) => {
+                        self.remember_file(&path);
+                        self.current_file = Some(path.clone());
+                        self.dirty = false;
+                        self.status = format!("Saved {}", path.display());
+                    }
+                    Err(err) => {
+                        self.report_error(format!("Save failed: {err}"));
+                    }
+                }
+            }
+            PromptState::ExportView(input) => {
+                let path = io::parse_path(&input, "view.txt");
+                let region = self.visible_region();
+                let layers = self.layer_stack();
+                match io::save_region(&path, &layers, region, &self.export_options) {
+                    Ok(()) => {
+                        self.status = format!(
+                            "Exported the {}x{} view at {},{} to {}",
+                            region.width,
+                            region.height,
+                            region.x,
+                            region.y,
+                            path.display()
+                        );
+                    }
+                    Err(err) => {
+                        self.report_error(format!("Export failed: {err}"));
+                    }
+                }
+            }
+            PromptState::Load(input) => {
+                self.load_file(io::parse_path(&input, "canvas.json"));
+            }
+            PromptState::Import(input) => {
+                self.import_file(&io::parse_path(&input, "canvas.json"));
+            }
+            PromptState::Reference(input) if input.trim().is_empty() => {
+                self.status = if self.reference.take().is_some() {
+                    "Reference removed".to_string()
+                } else {
+                    "No reference to remove".to_string()
+                };
+            }
+            PromptState::Reference(input) => {
+                self.load_reference(&io::parse_path(&input, "canvas.json"));
+            }
+            PromptState::SvgDefaultFill(input) => {
+                match PaintColor::parse_hex(&input).and_then(PaintColor::to_rgb) {
+                    Some(rgb) => {
+                        self.export_options.svg_default_fill = rgb;
+                        self.status = format!("SVG default text color: {}", input.trim());
+                    }
+                    None => {
+                        self.report_error(format!("Invalid hex color '{}'", input.trim()));
+                    }
+                }
+            }
+            PromptState::ImageColors(input) => {
+                let colors = input
+                    .split_whitespace()
+                    .map(|hex| PaintColor::parse_hex(hex).and_then(PaintColor::to_rgb))
+                    .collect::<Option<Vec<_>>>();
+                match colors.as_deref() {
+                    Some(&[fg, bg]) => {
+                        self.export_options.default_fg = fg;
+                        self.export_options.default_bg = bg;
+                        self.status = format!("PPM/PNG default colors: {}", input.trim());
+                    }
+                    _ => self.report_error(format!(
+                        "Expected a foreground and a background hex color, not '{}'",
+                        input.trim()
+                    )),
+                }
+            }
+            PromptState::ExportCellSize(input) => match ExportOptions::parse_cell_size(&input) {
+                Some((cell_width, cell_height)) => {
+                    self.export_options.cell_width = cell_width;
+                    self.export_options.cell_height = cell_height;
+                    self.status = format!("Export cell size: {}x{} px", cell_width, cell_height);
+                }
+                None => {
+                    self.status = format!(
+                        "Invalid cell size '{}' (expected WxH, 1-{})",
+                        input.trim(),
+                        io::MAX_EXPORT_CELL_PX
+                    );
+                }
+            },
+            PromptState::FillWarnThreshold(input) => match input.trim().parse::<usize>() {
+                Ok(threshold) if threshold > 0 => {
+                    self.fill_warn_threshold = threshold;
+                    self.status = format!("Fill warning above {} cells", threshold);
+                }
+                _ => {
+                    self.report_error(format!("Invalid fill threshold '{}'", input.trim()));
+                }
+            },
+            PromptState::GradientEnd(input) => match PaintColor::parse(&input) {
+                Some(color) => {
+                    self.gradient_end = color;
+                    self.status = format!("Gradient end color: {}", color.name());
+                }
+                None => {
+                    self.report_error(format!("Invalid color '{}'", input.trim()));
+                }
+            },
+            PromptState::BrushChar(input) => {
+                let mut chars = input.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) if !ch.is_control() => {
+                        self.brush_char = ch;
+                        self.status = format!("Brush character: '{}'", ch);
+                    }
+                    _ => {
+                        self.report_error(format!(
+                            "Brush character must be one glyph, got '{}'",
+                            input
+                        ));
+                    }
+                }
+            }
+            PromptState::BrushSize(input) => match input.trim().parse::<u8>() {
+                Ok(size) if (1..=MAX_BRUSH_SIZE).contains(&size) => self.set_active_size(size),
+                _ => {
+                    self.status = format!(
+                        "Invalid brush size '{}' (1-{})",
+                        input.trim(),
+                        MAX_BRUSH_SIZE
+                    );
+                }
+            },
+            PromptState::GridSpacing(input) => match input.trim().parse::<u16>() {
+                Ok(spacing) if spacing > 0 => {
+                    self.grid_spacing = spacing;
+                    self.show_grid = true;
+                    self.status = format!("Grid every {} cells", spacing);
+                }
+                _ => {
+                    self.report_error(format!("Invalid grid spacing '{}'", input.trim()));
+                }
+            },
+            PromptState::Backdrop(input) => match PaintColor::parse(&input) {
+                Some(color) => self.request_canvas_action(CanvasAction::Backdrop(color)),
+                None => self.report_error(format!("Invalid backdrop color '{}'", input.trim())),
+            },
+            PromptState::LuminanceBands(input) => {
+                let colors: Option<Vec<PaintColor>> = input
+                    .split(',')
+                    .filter(|part| !part.trim().is_empty())
+                    .map(PaintColor::parse)
+                    .collect();
+                match colors {
+                    Some(colors) if !colors.is_empty() => {
+                        self.request_canvas_action(CanvasAction::RecolorBands(colors));
+                    }
+                    _ => {
+                        self.report_error(format!("Invalid band colors '{}'", input.trim()));
+                    }
+                }
+            }
+            PromptState::CanvasSize(input) => {
+                let trimmed = input.trim();
+                if trimmed.is_empty() || trimmed.eq_ignore_ascii_case("auto") {
+                    self.set_canvas_size(None);
+                } else if let Some(size) = parse_canvas_size(trimmed, self.max_canvas_dim) {
+                    self.set_canvas_size(Some(size));
+                } else {
+                    self.report_error(format!("Invalid canvas size '{}'", trimmed));
+                }
+            }
+            PromptState::RenameLayer(input) => {
+                self.layers[self.active_layer].name = input.trim().to_string();
+                self.mark_dirty();
+                self.status = self.layer_status();
+            }
+            PromptState::Color(input) => match PaintColor::parse_hex(&input) {
+                Some(color) => {
+                    self.color = color;
+                    self.status = format!("Color: {}", color.name());
+                }
+                None => {
+                    self.report_error(format!("Invalid hex color '{}'", input.trim()));
+                }
+            },
+            PromptState::Confirm(action) => self.execute_canvas_action(action),
+            PromptState::ConfirmQuit
+            | PromptState::RecentFiles(_)
+            | PromptState::MessageLog(_)
+            | PromptState::Help(_)
+            | PromptState::History(_)
+            | PromptState::ColorPicker(_)
+            | PromptState::Stats(_)
+            | PromptState::None => {}
+        }
+    }
+
+    fn load_file(&mut self, path: PathBuf) {
+        if io::is_image_file(&path) {
+            self.load_image(&path);
+            return;
+        }
+        match io::load_layers(&path, self.max_canvas_dim) {
+            Ok(loaded) => {
+                self.replace_layers(loaded);
+                self.remember_file(&path);
+                self.status = format!("Loaded {} (undo restores previous canvas)", path.display());
+                self.current_file = Some(path);
+                self.dirty = false;
+            }
+            Err(err) => {
+                self.report_error(format!("Load failed: {err}"));
+            }
+        }
+    }
+
+    /// Replaces the canvas with a picture converted to ASCII art as wide as the current canvas.
+    /// The result has no file of its own, so saving asks for a name instead of the image's.
+    fn load_image(&mut self, path: &Path) {
+        match io::load_image(path, self.canvas.width(), self.max_canvas_dim) {
+            Ok(loaded) => {
+                self.replace_layers(vec![Layer::new(loaded)]);
+                self.remember_file(path);
+                self.current_file = None;
+                self.status = format!(
+                    "Converted {} to ASCII art (undo restores previous canvas)",
+                    path.display()
+                );
+            }
+            Err(err) => {
+                self.report_error(format!("Load failed: {err}"));
+            }
+        }
+    }
+
+    /// Shows a drawing dimmed behind the canvas to trace over. It is only ever displayed: tools,
+    /// undo and saving all leave it alone.
+    fn load_reference(&mut self, path: &Path) {
+        let loaded = if io::is_image_file(path) {
+            io::load_image(path, self.canvas.width(), self.max_canvas_dim)
+        } else {
+            io::load_canvas(path, self.max_canvas_dim)
+        };
+        match loaded {
+            Ok(reference) => {
+                self.reference = Some(reference);
+                self.status = format!(
+                    "Tracing over {} (Shift+J, Enter removes it)",
+                    path.display()
+                );
+            }
+            Err(err) => {
+                self.report_error(format!("Reference failed: {err}"));
+            }
+        }
+    }
+
+    /// Merges another drawing into this one at the pointer (or keyboard cursor) as one undo step.
+    /// A picture is converted to ASCII art that reaches from the pointer to the right edge.
+    fn import_file(&mut self, path: &Path) {
+        let origin = self.hover.or(self.cursor).unwrap_or(Point::new(0, 0));
+        let loaded = if io::is_image_file(path) {
+            let width = (self.canvas.width() as i32 - origin.x).max(1) as u16;
+            io::load_image(path, width, self.max_canvas_dim)
+        } else {
+            io::load_canvas(path, self.max_canvas_dim)
+        };
+        let imported = match loaded {
+            Ok(imported) => imported,
+            Err(err) => {
+                self.report_error(format!("Import failed: {err}"));
+                return;
+            }
+        };
+
+        let mut builder = OperationBuilder::new();
+        let mut merged = 0;
+        for y in 0..imported.height() {
+            for x in 0..imported.width() {
+                let cell = imported.get(x, y);
+                let (cx, cy) = (origin.x + x as i32, origin.y + y as i32);
+                if cell.is_blank() || !self.canvas.in_bounds_i32(cx, cy) {
+                    continue;
+                }
+                builder.apply(&mut self.canvas, cx, cy, cell);
+                merged += 1;
+            }
+        }
+        self.commit_builder(builder);
+        self.status = format!(
+            "Imported {} cells from {} at {},{}",
+            merged,
+            path.display(),
+            origin.x,
+            origin.y
+        );
+    }
+
+    fn request_canvas_action(&mut self, action: CanvasAction) {
+        if self.confirm_destructive {
+            self.prompt = PromptState::Confirm(action);
+        } else {
+            self.execute_canvas_action(action);
+        }
+    }
+
+    fn execute_canvas_action(&mut self, action: CanvasAction) {
+        match action {
+            CanvasAction::RecolorBands(colors) => self.recolor_by_luminance(&colors),
+            CanvasAction::Backdrop(color) => self.fill_backdrop(color),
+            CanvasAction::Clear => self.clear_canvas(),
+            CanvasAction::FlipHorizontal => {
+                self.transform_canvas("Flipped horizontally", Canvas::flip_horizontal)
+            }
+            CanvasAction::FlipVertical => {
+                self.transform_canvas("Flipped vertically", Canvas::flip_vertical)
+            }
+            CanvasAction::Rotate(clockwise) => self.rotate_canvas(clockwise),
+            CanvasAction::Trim => self.trim_to_content(),
+            CanvasAction::Recover(path) => self.recover_autosave(&path),
+        }
+    }
+
+    /// Runs an in-place transform and records only the cells it actually moved.
+    fn transform_canvas(&mut self, done: &str, transform: fn(&mut Canvas)) {
+        let before = self.canvas.clone();
+        transform(&mut self.canvas);
+        let operation = Operation::diff(&before, &self.canvas).on_layer(self.active_layer);
+        if !operation.is_empty() {
+            self.history.push(operation);
+            self.last_commit = None;
+            self.mark_dirty();
+        }
+        self.status = done.to_string();
+    }
+
+    /// Rotates a quarter turn. The new shape is kept as a fixed size so the window can't re-clip it.
+    fn rotate_canvas(&mut self, clockwise: bool) {
+        if clockwise {
+            self.reshape_layers(Canvas::rotate_cw);
+        } else {
+            self.reshape_layers(Canvas::rotate_ccw);
+        }
+        self.fixed_size = true;
+        self.selection = None;
+        self.status = format!(
+            "Rotated {} to {}x{}",
+            if clockwise {
+                "clockwise"
+            } else {
+                "counter-clockwise"
+            },
+            self.canvas.width(),
+            self.canvas.height()
+        );
+    }
+
+    /// The content's bounds when there are blank margins to trim; otherwise says why not.
+    fn trim_bounds(&mut self) -> Option<Rect> {
+        let Some(bounds) = self.all_layers().content_bounds() else {
+            self.status = "Nothing to trim - the canvas is blank".to_string();
+            return None;
+        };
+        if bounds == Rect::new(0, 0, self.canvas.width(), self.canvas.height()) {
+            self.status = "No blank margins to trim".to_string();
+            return None;
+        }
+        Some(bounds)
+    }
+
+    /// Crops away blank margins; the trimmed size becomes the fixed canvas size.
+    fn trim_to_content(&mut self) {
+        let Some(bounds) = self.trim_bounds() else {
+            return;
+        };
+
+        self.reshape_layers(|canvas| canvas.crop(bounds));
+        self.fixed_size = true;
+        self.selection = None;
+        self.scroll = (0, 0);
+        self.status = format!("Trimmed canvas to {}x{}", bounds.width, bounds.height);
+    }
+
+    fn clear_canvas(&mut self) {
+        let mut builder = OperationBuilder::new();
+        for y in 0..self.canvas.height() {
+            for x in 0..self.canvas.width() {
+                builder.apply(&mut self.canvas, x as i32, y as i32, PaintCell::blank());
+            }
+        }
+        // An already blank canvas yields an empty operation, which never reaches the history.
+        self.commit_builder(builder);
+        self.status = "Canvas cleared".to_string();
+    }
+
+    fn recolor_by_luminance(&mut self, band_colors: &[PaintColor]) {
+        let mut builder = OperationBuilder::new();
+
+        for y in 0..self.canvas.height() {
+            for x in 0..self.canvas.width() {
+                let cell = self.canvas.get(x, y);
+                if cell.is_blank() {
+                    continue;
+                }
+
+                let mut recolored = cell;
+                if cell.ch == ' ' {
+                    if let Some(bg) = cell.bg {
+                        recolored.bg = Some(band_colors[bg.luminance_band(band_colors.len())]);
+                    }
+                } else {
+                    recolored.fg = band_colors[cell.fg.luminance_band(band_colors.len())];
+                }
+                builder.apply(&mut self.canvas, x as i32, y as i32, recolored);
+            }
+        }
+
+        self.commit_builder(builder);
+        self.status = format!("Recolored into {} brightness bands", band_colors.len());
+    }
+
+    /// Gives every cell without a glyph this background, replacing any earlier backdrop; cells
+    /// with a glyph keep theirs. The default color removes the backdrop.
+    fn fill_backdrop(&mut self, color: PaintColor) {
+        let bg = (color != PaintColor::Default).then_some(color);
+        let mut builder = OperationBuilder::new();
+
+        for y in 0..self.canvas.height() {
+            for x in 0..self.canvas.width() {
+                let cell = self.canvas.get(x, y);
+                if cell.ch == ' ' && cell.bg != bg {
+                    builder.apply(&mut self.canvas, x as i32, y as i32, cell.with_bg(bg));
+                }
+            }
+        }
+
+        self.commit_builder(builder);
+        self.status = match bg {
+            Some(bg) => format!("Backdrop: {}", bg.name()),
+            None => "Backdrop removed".to_string(),
+        };
+    }
+
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        if let PromptState::ColorPicker(selected) = &mut self.prompt {
+            let swatch = ui::color_picker_swatch_at(&self.last_ui, mouse.column, mouse.row);
+            match (mouse.kind, swatch) {
+                (MouseEventKind::Down(MouseButton::Left), Some(index)) => {
+                    self.pick_palette_color(index)
+                }
+                (MouseEventKind::Moved, Some(index)) => *selected = index,
+                _ => {}
+            }
+            return;
+        }
+
+        if self.prompt_is_active() {
+            return;
+        }
+
+        let column = mouse.column;
+        let row = mouse.row;
+        let (half_block, zoom, scroll) = (self.half_block, self.zoom, self.scroll);
+        let size = (self.canvas.width(), self.canvas.height());
+        self.hover =
+            ui::mouse_to_canvas(&self.last_ui, size, column, row, half_block, zoom, scroll)
+                .filter(|p| self.canvas.in_bounds_i32(p.x, p.y));
+        let pointer = ui::mouse_to_canvas_unclamped(
+            &self.last_ui,
+            size,
+            column,
+            row,
+            half_block,
+            zoom,
+            scroll,
+        );
+
+        // The minimap sits on top of the canvas; clicking it centers the view there.
+        let on_minimap = (self.minimap && self.canvas_clipped)
+            .then(|| {
+                let (width, height) = (self.canvas.width(), self.canvas.height());
+                ui::minimap_hit(&self.last_ui, width, height, column, row)
+            })
+            .flatten();
+        if let Some(point) = on_minimap.filter(|_| matches!(self.mouse_mode, MouseMode::Idle)) {
+            self.hover = None;
+            if mouse.kind == MouseEventKind::Down(MouseButton::Left) {
+                self.center_view_on(point);
+            }
+            return;
+        }
+        // Where the pointer is, pulled onto the canvas: shapes dragged past the edge end here.
+        let clamped = Point::new(
+            pointer.x.clamp(0, (self.canvas.width() as i32 - 1).max(0)),
+            pointer.y.clamp(0, (self.canvas.height() as i32 - 1).max(0)),
+        );
+
+        // Holding Shift while dragging a rectangle or ellipse keeps it square or round, and
+        // holding Alt flips whether it grows from the center.
+        if let MouseMode::ShapeDrag {
+            square, centered, ..
+        } = &mut self.mouse_mode
+        {
+            *square = mouse.modifiers.contains(KeyModifiers::SHIFT);
+            *centered = self.from_center != mouse.modifiers.contains(KeyModifiers::ALT);
+        }
+
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                if matches!(self.mouse_mode, MouseMode::Pan { .. }) {
+                    return;
+                }
+                self.finish_text_entry();
+
+                if let Some(action) = ui::toolbar_action_at(&self.last_ui, column, row) {
+                    self.apply_toolbar_action(action);
+                    return;
+                }
+
+                if self.resize_handle_pos() == Some((column, row)) {
+                    self.mouse_mode = MouseMode::ResizeCanvas {
+                        before: self.canvas.clone(),
+                    };
+                    self.status = format!(
+                        "Resize to {}x{} - release to apply, Esc cancels",
+                        self.canvas.width(),
+                        self.canvas.height()
+                    );
+                    return;
+                }
+
+                if let Some(point) = self.hover {
+                    self.begin_left_draw(point, self.color);
+                }
+            }
+            MouseEventKind::Drag(MouseButton::Left)
+                if matches!(self.mouse_mode, MouseMode::ResizeCanvas { .. }) =>
+            {
+                self.drag_resize_handle(pointer)
+            }
+            MouseEventKind::Drag(MouseButton::Left) => match self.hover {
+                Some(point) => self.drag_left_draw(point),
+                None if matches!(
+                    self.mouse_mode,
+                    MouseMode::ShapeDrag { .. }
+                        | MouseMode::Selecting { .. }
+                        | MouseMode::Measure { .. }
+                ) =>
+                {
+                    self.drag_left_draw(clamped)
+                }
+                None => self.drag_left_outside(pointer),
+            },
+            MouseEventKind::Up(MouseButton::Left) => {
+                self.finish_left_draw(Some(self.hover.unwrap_or(clamped)));
+            }
+            MouseEventKind::Down(MouseButton::Middle)
+                if matches!(self.mouse_mode, MouseMode::Idle) =>
+            {
+                self.mouse_mode = MouseMode::Pan {
+                    anchor: (column, row),
+                    start_scroll: self.scroll,
+                };
+                self.status = "Panning".to_string();
+            }
+            MouseEventKind::Drag(MouseButton::Middle) => {
+                if let MouseMode::Pan {
+                    anchor,
+                    start_scroll,
+                } = self.mouse_mode
+                {
+                    // Dragging moves the canvas with the pointer, so the view scrolls the other way.
+                    let rows_per_cell = if self.half_block { 2 } else { 1 };
+                    let zoom = self.zoom as i32;
+                    let dx = (anchor.0 as i32 - column as i32) / zoom;
+                    let dy = (anchor.1 as i32 - row as i32) / zoom * rows_per_cell;
+                    self.scroll = (
+                        (start_scroll.0 as i32 + dx).max(0) as u16,
+                        (start_scroll.1 as i32 + dy).max(0) as u16,
+                    );
+                }
+            }
+            MouseEventKind::Up(MouseButton::Middle) => {
+                if matches!(self.mouse_mode, MouseMode::Pan { .. }) {
+                    self.mouse_mode = MouseMode::Idle;
+                    self.status = format!("View at {},{}", self.scroll.0, self.scroll.1);
+                }
+            }
+            MouseEventKind::Down(MouseButton::Right)
+                if matches!(self.mouse_mode, MouseMode::Idle) =>
+            {
+                self.right_press = self.hover;
+            }
+            // Dragging with the right button paints with the secondary color; a plain click samples.
+            MouseEventKind::Drag(MouseButton::Right) => {
+                if let Some(start) = self.right_press.take() {
+                    if self.tool.paints_by_dragging() {
+                        self.finish_text_entry();
+                        self.begin_left_draw(start, self.secondary_color);
+                    }
+                }
+                match self.hover {
+                    Some(point) => self.drag_left_draw(point),
+                    None if matches!(self.mouse_mode, MouseMode::ShapeDrag { .. }) => {
+                        self.drag_left_draw(clamped)
+                    }
+                    None => self.drag_left_outside(pointer),
+                }
+            }
+            MouseEventKind::Up(MouseButton::Right) => {
+                if let Some(point) = self.right_press.take() {
+                    self.sample_cell(point);
+                } else if matches!(
+                    self.mouse_mode,
+                    MouseMode::FreeDrag { .. } | MouseMode::ShapeDrag { .. }
+                ) {
+                    self.finish_left_draw(Some(self.hover.unwrap_or(clamped)));
+                }
+            }
+            MouseEventKind::ScrollUp => self.cycle_color(true),
+            MouseEventKind::ScrollDown => self.cycle_color(false),
+            // Some terminals report motion with the button held as plain moves.
+            MouseEventKind::Moved => {
+                if let MouseMode::ShapeDrag { current, .. } = &mut self.mouse_mode {
+                    *current = self.hover.unwrap_or(clamped);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn apply_toolbar_action(&mut self, action: ToolbarAction) {
+        match action {
+            ToolbarAction::SelectTool(tool) => {
+                self.tool = tool;
+                self.status = format!("Tool: {}", tool.name());
+            }
+            ToolbarAction::SelectBrushChar(ch) => {
+                self.brush_char = ch;
+                self.status = format!("Brush char: {}", printable_char(ch));
+            }
+            ToolbarAction::SelectColor(color) => {
+                self.color = color;
+                self.status = format!("Color: {}", color.name());
+            }
+            ToolbarAction::ToggleFilledShapes => self.toggle_filled_shapes(),
+        }
+    }
+
+    fn toggle_pattern_brush(&mut self) {
+        if self.pattern_brush {
+            self.pattern_brush = false;
+            self.status = "Pattern brush off".to_string();
+            return;
+        }
+        let Some(block) = &self.clipboard else {
+            self.status = "Copy a selection first (Ctrl+C) to use it as a pattern".to_string();
+            return;
+        };
+        self.pattern_brush = true;
+        self.tool = Tool::Pencil;
+        self.status = format!(
+            "Pattern brush: the Pencil stamps the {}x{} clipboard every {} cells ({{ }} to change)",
+            block.width, block.height, self.brush_spacing
+        );
+    }
+
+    fn toggle_filled_shapes(&mut self) {
+        if !self.tool.supports_fill() {
+            self.status = format!("Filled mode does not apply to {}", self.tool.name());
+            return;
+        }
+
+        self.filled_shapes = !self.filled_shapes;
+        self.status = if self.filled_shapes {
+            format!("{} fill enabled", self.tool.name())
+        } else {
+            format!("{} fill disabled", self.tool.name())
+        };
+    }
+
+    /// Whether a press with `spec` would only leave blank cells: the tool stamps the brush, and
+    /// the brush is a space with no color, background or attribute to show.
+    fn paints_nothing(&self, spec: DrawSpec) -> bool {
+        let stamps_brush = match self.tool {
+            Tool::Pencil => !(self.pattern_brush && self.clipboard.is_some()),
+            Tool::Line => !spec.shades_lines(),
+            Tool::Spray
+            | Tool::Rectangle
+            | Tool::Circle
+            | Tool::Arrow
+            | Tool::Polygon
+            | Tool::Fill
+            | Tool::ReplaceAll => true,
+            _ => false,
+        };
+        stamps_brush && !spec.background && spec.draw_cell().is_blank()
+    }
+
+    fn begin_left_draw(&mut self, point: Point, color: PaintColor) {
+        let spec = DrawSpec {
+            color,
+            ..self.current_draw_spec()
+        };
+        if self.paints_nothing(spec) {
+            self.status = "The space brush needs a color or background to show (the Eraser clears)"
+                .to_string();
+            return;
+        }
+
+        match self.tool {
+            Tool::Pencil | Tool::Eraser | Tool::Spray => {
+                let mut builder = OperationBuilder::new();
+                let mut color_cycle = 0;
+                let pattern = self
+                    .clipboard
+                    .as_ref()
+                    .filter(|_| self.pattern_brush && self.tool == Tool::Pencil)
+                    .cloned();
+                match &pattern {
+                    Some(block) => {
+                        stamp_pattern(&mut self.canvas, &mut builder, block, point, spec)
+                    }
+                    None => apply_point_with_spec(
+                        &mut self.canvas,
+                        &mut builder,
+                        point,
+                        spec.next_stamp(&mut color_cycle),
+                    ),
+                }
+                self.mouse_mode = MouseMode::FreeDrag {
+                    last: point,
+                    since_stamp: 0,
+                    color_cycle,
+                    spec,
+                    builder,
+                    pattern,
+                };
+            }
+            Tool::BoxLine if self.half_block => {
+                self.status = "Box lines need full-cell mode (h)".to_string();
+            }
+            Tool::Line
+            | Tool::Rectangle
+            | Tool::Circle
+            | Tool::BoxLine
+            | Tool::Gradient
+            | Tool::Arrow => {
+                self.mouse_mode = MouseMode::ShapeDrag {
+                    start: point,
+                    current: point,
+                    spec,
+                    tool: self.tool,
+                    filled: self.filled_shapes,
+                    square: false,
+                    centered: self.from_center,
+                };
+            }
+            Tool::Fill if self.multi_fill => {
+                if let Some(idx) = self.fill_seeds.iter().position(|p| *p == point) {
+                    self.fill_seeds.remove(idx);
+                } else {
+                    self.fill_seeds.push(point);
+                }
+                self.status = format!(
+                    "{} fill seed(s) - Enter to fill, Esc to clear",
+                    self.fill_seeds.len()
+                );
+            }
+            Tool::Fill => {
+                let mut builder = OperationBuilder::new();
+                let filled = self.apply_fill(&[point], spec, &mut builder);
+                self.commit_repeatable(builder);
+                if filled > self.fill_warn_threshold {
+                    self.status = format!("Large fill: {} cells (u to undo)", filled);
+                }
+            }
+            Tool::Polygon => {
+                let double_click = self.polygon.last() == Some(&point)
+                    && self.last_vertex_at.elapsed() < DOUBLE_CLICK;
+                if double_click {
+                    self.finish_polygon(false);
+                    return;
+                }
+                self.polygon.push(point);
+                self.last_vertex_at = Instant::now();
+                self.status = format!(
+                    "Polygon: {} vertices - Enter closes, double-click ends, Esc cancels",
+                    self.polygon.len()
+                );
+            }
+            Tool::ReplaceAll => {
+                let points = matching_points(&self.canvas, point, self.fill_match);
+                let mut builder = OperationBuilder::new();
+                let count = self.paint_fill_points(points, spec, &mut builder);
+                self.commit_builder(builder);
+                self.status = format!("Replaced {} matching cells (u to undo)", count);
+            }
+            Tool::Text if self.half_block => {
+                self.status = "Text needs full-cell mode (h)".to_string();
+            }
+            Tool::Text => {
+                self.mouse_mode = MouseMode::TextEntry {
+                    origin: point,
+                    cursor: point,
+                    spec,
+                    builder: OperationBuilder::new(),
+                };
+                self.status = "Typing - Esc to finish".to_string();
+            }
+            Tool::Select if self.selection_contains(point) => {
+                let Some(origin) = self.selection else {
+                    return;
+                };
+                self.mouse_mode = MouseMode::MoveSelection {
+                    origin,
+                    grab: point,
+                    offset: Point::new(0, 0),
+                    block: self.canvas.copy_region(origin),
+                    builder: OperationBuilder::new(),
+                };
+                self.status = "Moving selection - Esc to cancel".to_string();
+            }
+            Tool::Measure => {
+                self.mouse_mode = MouseMode::Measure {
+                    start: point,
+                    current: point,
+                };
+                self.status = measure_status(point, point);
+            }
+            Tool::Select => {
+                self.mouse_mode = MouseMode::Selecting {
+                    start: point,
+                    current: point,
+                };
+            }
+        }
+    }
+
+    /// Where the corner resize handle is on screen; only fixed-size canvases have one.
+    fn resize_handle_pos(&self) -> Option<(u16, u16)> {
+        if !self.fixed_size {
+            return None;
+        }
+        let size = (self.canvas.width(), self.canvas.height());
+        ui::resize_handle_pos(&self.last_ui, size, self.half_block, self.zoom, self.scroll)
     }
 
-    fn commit_prompt(&mut self) {
-        let prompt = std::mem::replace(&mut self.prompt, PromptState::None);
-        match prompt {
-            PromptState::Save(input) => {
-                let path = io::parse_path(&input, "canvas.json");
-                match io::save_canvas(&path, &self.canvas) {
-                    Ok(()) => {
-                        self.current_file = Some(path.clone());
-                        self.status = format!("Saved {}", path.display());
-                    }
-                    Err(err) => {
-                        self.status = format!("Save failed: {err}");
+    /// Resizes the canvas live so its bottom-right corner follows the pointer. Each size is cut
+    /// from the canvas as it was when the drag began.
+    fn drag_resize_handle(&mut self, pointer: Point) {
+        let MouseMode::ResizeCanvas { before } = &self.mouse_mode else {
+            return;
+        };
+        let max = i32::from(self.max_canvas_dim);
+        let width = pointer.x.clamp(1, max) as u16;
+        let height = pointer.y.clamp(1, max) as u16;
+        if (width, height) != (self.canvas.width(), self.canvas.height()) {
+            let mut canvas = before.clone();
+            canvas.resize_preserve(width, height);
+            self.canvas = canvas;
+        }
+        self.status = format!("Resize to {width}x{height} - release to apply, Esc cancels");
+    }
+
+    /// Continues a freehand stroke towards a pointer that has left the canvas. The stroke runs up
+    /// to the edge, and that edge point is where it reconnects once the pointer comes back in.
+    fn drag_left_outside(&mut self, point: Point) {
+        let MouseMode::FreeDrag { last, spec, .. } = &self.mouse_mode else {
+            return;
+        };
+        if spec.tool == Tool::Spray {
+            return;
+        }
+
+        let edge = bresenham_line(*last, point)
+            .into_iter()
+            .take_while(|p| self.canvas.in_bounds_i32(p.x, p.y))
+            .last();
+        if let Some(edge) = edge.filter(|edge| edge != last) {
+            self.drag_left_draw(edge);
+        }
+    }
+
+    fn drag_left_draw(&mut self, point: Point) {
+        let canvas = &mut self.canvas;
+
+        match &mut self.mouse_mode {
+            MouseMode::Idle => {}
+            MouseMode::FreeDrag {
+                last,
+                since_stamp,
+                color_cycle,
+                spec,
+                builder,
+                ..
+            } if spec.tool == Tool::Spray => {
+                apply_point_with_spec(canvas, builder, point, spec.next_stamp(color_cycle));
+                *last = point;
+            }
+            MouseMode::FreeDrag {
+                last,
+                since_stamp,
+                color_cycle,
+                spec,
+                builder,
+                pattern,
+            } => {
+                // The first point of the segment is the previous sample, which was already handled.
+                for p in bresenham_line(*last, point).into_iter().skip(1) {
+                    *since_stamp += 1;
+                    if *since_stamp >= spec.spacing {
+                        match pattern {
+                            Some(block) => stamp_pattern(canvas, builder, block, p, *spec),
+                            None => apply_point_with_spec(
+                                canvas,
+                                builder,
+                                p,
+                                spec.next_stamp(color_cycle),
+                            ),
+                        }
+                        *since_stamp = 0;
                     }
                 }
+                *last = point;
             }
-            PromptState::Load(input) => {
-                let path = io::parse_path(&input, "canvas.json");
-                match io::load_canvas(&path) {
-                    Ok(mut loaded) => {
-                        let width = self.canvas.width();
-                        let height = self.canvas.height();
-                        loaded.resize_preserve(width, height);
-                        self.canvas = loaded;
-                        self.history.clear();
-                        self.current_file = Some(path.clone());
-                        self.status = format!("Loaded {}", path.display());
-                    }
-                    Err(err) => {
-                        self.status = format!("Load failed: {err}");
+            MouseMode::ShapeDrag { current, .. } => {
+                *current = point;
+            }
+            MouseMode::Selecting { current, .. } | MouseMode::Measure { current, .. } => {
+                *current = point;
+            }
+            MouseMode::MoveSelection {
+                origin,
+                grab,
+                offset,
+                block,
+                builder,
+            } => {
+                *offset = Point::new(point.x - grab.x, point.y - grab.y);
+                // Re-lay the move from scratch so the source is only cleared once and
+                // earlier destinations don't leave a trail.
+                builder.revert(canvas);
+                for y in origin.y..origin.bottom() {
+                    for x in origin.x..origin.right() {
+                        builder.apply(canvas, x as i32, y as i32, PaintCell::blank());
                     }
                 }
+                let dest = Point::new(origin.x as i32 + offset.x, origin.y as i32 + offset.y);
+                stamp_block(canvas, builder, block, dest);
             }
-            PromptState::None => {}
+            MouseMode::TextEntry { .. }
+            | MouseMode::Pan { .. }
+            | MouseMode::ResizeCanvas { .. } => {}
+        }
+
+        if let MouseMode::Measure { start, current } = self.mouse_mode {
+            self.status = measure_status(start, current);
         }
     }
 
-    fn handle_mouse(&mut self, mouse: MouseEvent) {
-        if self.prompt_is_active() {
+    /// Keeps an airbrush stroke building up while the pointer is held still.
+    fn tick(&mut self) {
+        let MouseMode::FreeDrag {
+            last,
+            color_cycle,
+            spec,
+            builder,
+            ..
+        } = &mut self.mouse_mode
+        else {
+            return;
+        };
+        if spec.tool != Tool::Spray || self.last_spray.elapsed() < SPRAY_INTERVAL {
             return;
         }
 
-        let column = mouse.column;
-        let row = mouse.row;
-        self.hover = ui::mouse_to_canvas(&self.last_ui, column, row);
+        apply_point_with_spec(
+            &mut self.canvas,
+            builder,
+            *last,
+            spec.next_stamp(color_cycle),
+        );
+        self.last_spray = Instant::now();
+    }
 
-        match mouse.kind {
-            MouseEventKind::Down(MouseButton::Left) => {
-                if let Some(action) = ui::toolbar_action_at(&self.last_ui, column, row) {
-                    self.apply_toolbar_action(action);
-                    return;
-                }
+    fn finish_left_draw(&mut self, maybe_end: Option<Point>) {
+        let mode = std::mem::replace(&mut self.mouse_mode, MouseMode::Idle);
 
-                if let Some(point) = self.hover {
-                    self.begin_left_draw(point);
+        match mode {
+            MouseMode::Idle => {}
+            MouseMode::FreeDrag { builder, .. } => {
+                self.commit_builder(builder);
+            }
+            MouseMode::ResizeCanvas { before } => {
+                let (width, height) = (self.canvas.width(), self.canvas.height());
+                // Undo starts from the size the drag began at; only the active layer was
+                // resized live, so the rest catch up here.
+                self.canvas = before;
+                if (width, height) == (self.canvas.width(), self.canvas.height()) {
+                    self.status = "Canvas size unchanged".to_string();
+                } else {
+                    self.reshape_layers(|canvas| canvas.resize_preserve(width, height));
+                    self.status = format!("Canvas size fixed at {}x{}", width, height);
                 }
             }
-            MouseEventKind::Drag(MouseButton::Left) => {
-                if let Some(point) = self.hover {
-                    self.drag_left_draw(point);
+            MouseMode::ShapeDrag {
+                start,
+                current,
+                spec,
+                tool,
+                filled,
+                square,
+                centered,
+            } => {
+                let end = maybe_end.unwrap_or(current);
+                let mut builder = OperationBuilder::new();
+
+                if tool == Tool::BoxLine {
+                    apply_box_line(&mut self.canvas, &mut builder, start, end, spec);
+                } else if tool == Tool::Gradient {
+                    let points =
+                        gradient_points_with_colors(start, end, spec.color, self.gradient_end);
+                    for (point, color) in points {
+                        let spec = DrawSpec {
+                            color,
+                            size: 1,
+                            ..spec
+                        };
+                        apply_point_with_spec(&mut self.canvas, &mut builder, point, spec);
+                    }
+                } else if tool == Tool::Line && spec.shades_lines() {
+                    let spec = DrawSpec { size: 1, ..spec };
+                    for (point, coverage) in shaded_line_cells(start, end) {
+                        if let Some(ch) = coverage_glyph(coverage) {
+                            let cell_spec = DrawSpec { ch, ..spec };
+                            apply_point_with_spec(&mut self.canvas, &mut builder, point, cell_spec);
+                        }
+                    }
+                } else if tool == Tool::Arrow {
+                    apply_arrow(&mut self.canvas, &mut builder, start, end, spec);
+                } else {
+                    let points = shape_points(tool, start, end, filled, square, centered, spec);
+                    for point in points {
+                        apply_point_with_spec(&mut self.canvas, &mut builder, point, spec);
+                    }
                 }
+
+                self.commit_builder(builder);
             }
-            MouseEventKind::Up(MouseButton::Left) => {
-                self.finish_left_draw(self.hover);
+            MouseMode::TextEntry { .. } | MouseMode::Pan { .. } => {
+                // Releasing the click that placed the text cursor keeps the session open,
+                // and a left release never ends a middle-button pan.
+                self.mouse_mode = mode;
             }
-            MouseEventKind::Down(MouseButton::Right) => {
-                if let Some(point) = self.hover {
-                    self.sample_cell(point);
+            MouseMode::MoveSelection {
+                origin,
+                offset,
+                builder,
+                ..
+            } => {
+                self.commit_builder(builder);
+                self.selection = self.moved_selection(origin, offset);
+                self.status = "Selection moved".to_string();
+            }
+            MouseMode::Measure { start, current } => {
+                // The last reading stays in the status line; nothing is committed.
+                self.status = measure_status(start, maybe_end.unwrap_or(current));
+            }
+            MouseMode::Selecting { start, current } => {
+                let end = maybe_end.unwrap_or(current);
+                self.selection = self.selection_rect(start, end);
+                if let Some(rect) = self.selection {
+                    self.status =
+                        format!("Selected {}x{} - Ctrl+C to copy", rect.width, rect.height);
                 }
             }
-            MouseEventKind::ScrollUp => self.cycle_color(true),
-            MouseEventKind::ScrollDown => self.cycle_color(false),
-            MouseEventKind::Moved => {}
-            _ => {}
         }
     }
 
-    fn apply_toolbar_action(&mut self, action: ToolbarAction) {
-        match action {
-            ToolbarAction::SelectTool(tool) => {
-                self.tool = tool;
-                self.status = format!("Tool: {}", tool.name());
+    /// Normalises two corners into a rectangle clipped to the canvas.
+    fn selection_rect(&self, a: Point, b: Point) -> Option<Rect> {
+        let min_x = a.x.min(b.x).max(0);
+        let min_y = a.y.min(b.y).max(0);
+        let max_x = a.x.max(b.x).min(self.canvas.width() as i32 - 1);
+        let max_y = a.y.max(b.y).min(self.canvas.height() as i32 - 1);
+        if min_x > max_x || min_y > max_y {
+            return None;
+        }
+        Some(Rect::new(
+            min_x as u16,
+            min_y as u16,
+            (max_x - min_x + 1) as u16,
+            (max_y - min_y + 1) as u16,
+        ))
+    }
+
+    fn moved_selection(&self, origin: Rect, offset: Point) -> Option<Rect> {
+        let start = Point::new(origin.x as i32 + offset.x, origin.y as i32 + offset.y);
+        let end = Point::new(
+            start.x + origin.width as i32 - 1,
+            start.y + origin.height as i32 - 1,
+        );
+        self.selection_rect(start, end)
+    }
+
+    fn selection_contains(&self, point: Point) -> bool {
+        self.selection.is_some_and(|rect| {
+            point.x >= rect.x as i32
+                && point.y >= rect.y as i32
+                && point.x < rect.right() as i32
+                && point.y < rect.bottom() as i32
+        })
+    }
+
+    fn visible_selection(&self) -> Option<Rect> {
+        match &self.mouse_mode {
+            MouseMode::Selecting { start, current } => self.selection_rect(*start, *current),
+            MouseMode::MoveSelection { origin, offset, .. } => {
+                self.moved_selection(*origin, *offset)
             }
-            ToolbarAction::SelectBrushChar(ch) => {
-                self.brush_char = ch;
-                self.status = format!("Brush char: {}", printable_char(ch));
+            _ => self.selection,
+        }
+    }
+
+    /// The cursor keys nudge a finished selection by one cell instead of moving the cursor.
+    fn nudge_or_move_cursor(&mut self, dx: i32, dy: i32) {
+        match self.selection {
+            Some(rect) if matches!(self.mouse_mode, MouseMode::Idle) => {
+                self.nudge_selection(rect, dx, dy)
             }
-            ToolbarAction::SelectColor(color) => {
-                self.color = color;
-                self.status = format!("Color: {}", color.name());
+            _ => self.move_cursor(dx, dy),
+        }
+    }
+
+    /// Moves the selected cells as one undo step, keeping the whole block on the canvas.
+    fn nudge_selection(&mut self, rect: Rect, dx: i32, dy: i32) {
+        let max_x = self.canvas.width() as i32 - rect.width as i32;
+        let max_y = self.canvas.height() as i32 - rect.height as i32;
+        let dest = Point::new(
+            (rect.x as i32 + dx).clamp(0, max_x.max(0)),
+            (rect.y as i32 + dy).clamp(0, max_y.max(0)),
+        );
+        if dest == Point::new(rect.x as i32, rect.y as i32) {
+            self.status = "Selection is at the canvas edge".to_string();
+            return;
+        }
+
+        let block = self.canvas.copy_region(rect);
+        let mut builder = OperationBuilder::new();
+        for y in rect.y..rect.bottom() {
+            for x in rect.x..rect.right() {
+                builder.apply(&mut self.canvas, x as i32, y as i32, PaintCell::blank());
             }
-            ToolbarAction::ToggleFilledShapes => {
-                self.filled_shapes = !self.filled_shapes;
-                self.status = if self.filled_shapes {
-                    "Rectangle fill enabled".to_string()
-                } else {
-                    "Rectangle fill disabled".to_string()
-                };
+        }
+        stamp_block(&mut self.canvas, &mut builder, &block, dest);
+        self.commit_repeatable(builder);
+        self.selection = Some(Rect::new(
+            dest.x as u16,
+            dest.y as u16,
+            rect.width,
+            rect.height,
+        ));
+        self.status = format!("Selection at {},{} - Esc to deselect", dest.x, dest.y);
+    }
+
+    fn cut_selection(&mut self) {
+        let Some(rect) = self.selection else {
+            self.status = "Nothing selected".to_string();
+            return;
+        };
+        self.clipboard = Some(self.canvas.copy_region(rect));
+
+        let mut builder = OperationBuilder::new();
+        for y in rect.y..rect.bottom() {
+            for x in rect.x..rect.right() {
+                builder.apply(&mut self.canvas, x as i32, y as i32, PaintCell::blank());
             }
         }
+        self.commit_builder(builder);
+        self.status = format!("Cut {}x{}", rect.width, rect.height);
+    }
+
+    fn copy_selection(&mut self) {
+        let Some(rect) = self.selection else {
+            self.status = "Nothing selected".to_string();
+            return;
+        };
+        self.clipboard = Some(self.canvas.copy_region(rect));
+        self.status = format!("Copied {}x{}", rect.width, rect.height);
+    }
+
+    fn paste_clipboard(&mut self) {
+        let Some(block) = self.clipboard.take() else {
+            self.status = "Clipboard is empty".to_string();
+            return;
+        };
+        let Some(origin) = self.hover else {
+            self.status = "Point at the canvas to paste".to_string();
+            self.clipboard = Some(block);
+            return;
+        };
+
+        let mut builder = OperationBuilder::new();
+        stamp_block(&mut self.canvas, &mut builder, &block, origin);
+        self.commit_builder(builder);
+
+        let end = Point::new(
+            origin.x + block.width as i32 - 1,
+            origin.y + block.height as i32 - 1,
+        );
+        self.selection = self.selection_rect(origin, end);
+        self.status = format!("Pasted {}x{}", block.width, block.height);
+        self.clipboard = Some(block);
+    }
+
+    fn apply_fill(
+        &mut self,
+        seeds: &[Point],
+        spec: DrawSpec,
+        builder: &mut OperationBuilder,
+    ) -> usize {
+        let points = flood_fill_points(
+            &self.canvas,
+            &spec.mirrored(seeds),
+            self.fill_connectivity,
+            self.fill_match,
+            |cell| spec.paint(cell),
+        );
+        self.paint_fill_points(points, spec, builder)
+    }
+
+    /// Paints the cells a fill or replace-all matched, applying the fill pattern.
+    fn paint_fill_points(
+        &mut self,
+        points: Vec<Point>,
+        spec: DrawSpec,
+        builder: &mut OperationBuilder,
+    ) -> usize {
+        // The region is collected before anything is written, so the pattern never
+        // affects which cells match the seed.
+        let count = points.len();
+        let pattern = if self.half_block {
+            FillPattern::Solid
+        } else {
+            self.fill_pattern
+        };
+        for p in points {
+            let spec = DrawSpec {
+                ch: pattern.char_at(p, spec.ch),
+                ..spec
+            };
+            let cell = spec.paint(self.canvas.get(p.x as u16, p.y as u16));
+            builder.apply(&mut self.canvas, p.x, p.y, cell);
+        }
+        count
     }
 
-    fn begin_left_draw(&mut self, point: Point) {
+    /// Rasterizes the pending polygon as one operation, joining the last vertex back to the
+    /// first when `closed`.
+    fn finish_polygon(&mut self, closed: bool) {
+        let vertices = std::mem::take(&mut self.polygon);
+        let closed = closed && vertices.len() > 2;
         let spec = self.current_draw_spec();
+        let mut builder = OperationBuilder::new();
+        for point in spec.line_style.apply(polyline_points(&vertices, closed)) {
+            apply_point_with_spec(&mut self.canvas, &mut builder, point, spec);
+        }
+        self.commit_builder(builder);
+        self.status = format!(
+            "{} with {} vertices",
+            if closed { "Polygon" } else { "Polyline" },
+            vertices.len()
+        );
+    }
 
-        match self.tool {
-            Tool::Pencil | Tool::Eraser => {
-                let mut builder = OperationBuilder::new();
-                apply_point_with_spec(&mut self.canvas, &mut builder, point, spec);
-                self.mouse_mode = MouseMode::FreeDrag {
-                    last: point,
-                    spec,
-                    builder,
-                };
-            }
-            Tool::Line | Tool::Rectangle | Tool::Circle => {
-                self.mouse_mode = MouseMode::ShapeDrag {
-                    start: point,
-                    current: point,
-                    spec,
-                    tool: self.tool,
-                    filled: self.filled_shapes,
-                };
-            }
-            Tool::Fill => {
-                let mut builder = OperationBuilder::new();
-                self.apply_fill(point, spec, &mut builder);
-                self.commit_builder(builder);
+    fn commit_multi_fill(&mut self) {
+        let seeds = std::mem::take(&mut self.fill_seeds);
+        let spec = self.current_draw_spec();
+        let mut builder = OperationBuilder::new();
+        self.apply_fill(&seeds, spec, &mut builder);
+        self.commit_builder(builder);
+        self.status = format!("Filled from {} seed(s)", seeds.len());
+    }
+
+    /// Puts the active layer's cells back into its slot so the whole stack can be worked on.
+    fn attach_active(&mut self) {
+        std::mem::swap(&mut self.canvas, &mut self.layers[self.active_layer].canvas);
+    }
+
+    /// Takes the active layer's cells out into `canvas` again, after `attach_active`. The stack
+    /// may have changed in between, so the active index is kept within it.
+    fn detach_active(&mut self) {
+        self.active_layer = self.active_layer.min(self.layers.len() - 1);
+        std::mem::swap(&mut self.canvas, &mut self.layers[self.active_layer].canvas);
+    }
+
+    /// A copy of every layer with the active one's cells in place, for saving and undo snapshots.
+    fn layer_stack(&self) -> Vec<Layer> {
+        let mut layers = self.layers.clone();
+        layers[self.active_layer].canvas = self.canvas.clone();
+        layers
+    }
+
+    /// Swaps in a new stack without recording an undo step. The active index stays put as far
+    /// as the new stack allows.
+    fn set_layers(&mut self, layers: Vec<Layer>) {
+        self.attach_active();
+        self.layers = layers;
+        self.detach_active();
+    }
+
+    /// Swaps in a new stack as one undo step, as loading or resizing does.
+    fn replace_layers(&mut self, layers: Vec<Layer>) {
+        let before = self.layer_stack();
+        self.set_layers(layers);
+        self.history
+            .push(Operation::replace_layers(before, self.layer_stack()));
+        self.last_commit = None;
+        self.mark_dirty();
+    }
+
+    /// Runs a size-changing edit, such as a crop or a rotation, on every layer as one undo step.
+    fn reshape_layers(&mut self, reshape: impl Fn(&mut Canvas)) {
+        let mut layers = self.layer_stack();
+        for layer in &mut layers {
+            reshape(&mut layer.canvas);
+        }
+        self.replace_layers(layers);
+    }
+
+    /// Resizes every layer without an undo step, as following the window does.
+    fn resize_layers(&mut self, width: u16, height: u16) {
+        self.canvas.resize_preserve(width, height);
+        for (index, layer) in self.layers.iter_mut().enumerate() {
+            if index != self.active_layer {
+                layer.canvas.resize_preserve(width, height);
             }
         }
     }
 
-    fn drag_left_draw(&mut self, point: Point) {
-        let canvas = &mut self.canvas;
+    /// Every layer's cells stacked into one canvas, hidden ones included.
+    fn all_layers(&self) -> Cow<'_, Canvas> {
+        if self.layers.len() == 1 {
+            return Cow::Borrowed(&self.canvas);
+        }
+        Cow::Owned(flatten(
+            self.canvas.width(),
+            self.canvas.height(),
+            self.layer_canvases()
+                .map(|(canvas, _)| (canvas, FULL_OPACITY)),
+        ))
+    }
+
+    /// What the canvas shows: the visible layers stacked at the active layer's size.
+    fn flattened(&self) -> Cow<'_, Canvas> {
+        let only = &self.layers[0];
+        if self.layers.len() == 1 && only.visible && only.opacity >= FULL_OPACITY {
+            return Cow::Borrowed(&self.canvas);
+        }
+        Cow::Owned(flatten(
+            self.canvas.width(),
+            self.canvas.height(),
+            self.layer_canvases()
+                .filter(|(_, layer)| layer.visible)
+                .map(|(canvas, layer)| (canvas, layer.opacity)),
+        ))
+    }
+
+    /// Each layer's cells and its settings, bottom first, reading the active layer's cells from
+    /// `canvas`.
+    fn layer_canvases(&self) -> impl Iterator<Item = (&Canvas, &Layer)> {
+        self.layers.iter().enumerate().map(|(index, layer)| {
+            let canvas = if index == self.active_layer {
+                &self.canvas
+            } else {
+                &layer.canvas
+            };
+            (canvas, layer)
+        })
+    }
+
+    /// The furthest painted cell across all layers, which auto-resizing must not cut off.
+    fn content_extent(&self) -> (u16, u16) {
+        self.layer_canvases()
+            .map(|(canvas, _)| canvas.content_extent())
+            .fold((0, 0), |(w, h), (cw, ch)| (w.max(cw), h.max(ch)))
+    }
+
+    /// Layers can't be added, removed or switched while a stroke or move is being built on the
+    /// active one.
+    fn layers_busy(&mut self) -> bool {
+        let busy = matches!(
+            self.mouse_mode,
+            MouseMode::FreeDrag { .. }
+                | MouseMode::ShapeDrag { .. }
+                | MouseMode::TextEntry { .. }
+                | MouseMode::MoveSelection { .. }
+                | MouseMode::ResizeCanvas { .. }
+        );
+        if busy {
+            self.status = "Finish drawing before changing layers".to_string();
+        }
+        busy
+    }
+
+    fn add_layer(&mut self) {
+        if self.layers.len() >= MAX_LAYERS {
+            self.status = format!("At most {MAX_LAYERS} layers");
+            return;
+        }
+        let mut layers = self.layer_stack();
+        let blank = Canvas::new(self.canvas.width(), self.canvas.height());
+        layers.insert(self.active_layer + 1, Layer::new(blank));
+        let active = self.active_layer + 1;
+        self.replace_layers(layers);
+        self.select_layer(active);
+        self.status = format!("Added a layer - {}", self.layer_status());
+    }
+
+    fn delete_layer(&mut self) {
+        if self.layers.len() == 1 {
+            self.status = "The only layer can't be deleted".to_string();
+            return;
+        }
+        let mut layers = self.layer_stack();
+        layers.remove(self.active_layer);
+        let active = self.active_layer.saturating_sub(1);
+        self.replace_layers(layers);
+        self.select_layer(active);
+        self.status = format!("Deleted the layer (u to undo) - {}", self.layer_status());
+    }
+
+    /// Merges the visible layers into one, the way exports see them; hidden layers are dropped.
+    fn flatten_layers(&mut self) {
+        if self.layers.len() == 1 {
+            self.status = "There is only one layer".to_string();
+            return;
+        }
+        let flat = io::flatten_visible(&self.layer_stack());
+        self.replace_layers(vec![Layer::new(flat)]);
+        self.status = format!("Flattened the layers (u to undo) - {}", self.layer_status());
+    }
+
+    /// Swaps the active layer with the one above or below it, as one undo step.
+    fn move_layer(&mut self, up: bool) {
+        let target = if up {
+            Some(self.active_layer + 1).filter(|&index| index < self.layers.len())
+        } else {
+            self.active_layer.checked_sub(1)
+        };
+        let Some(target) = target else {
+            self.status = format!(
+                "The layer is already at the {}",
+                if up { "top" } else { "bottom" }
+            );
+            return;
+        };
+        let mut layers = self.layer_stack();
+        layers.swap(self.active_layer, target);
+        self.replace_layers(layers);
+        self.select_layer(target);
+        self.status = format!("Moved the layer - {}", self.layer_status());
+    }
+
+    fn select_layer(&mut self, index: usize) {
+        self.attach_active();
+        self.active_layer = index;
+        self.detach_active();
+        self.status = self.layer_status();
+    }
+
+    fn step_layer(&mut self, up: bool) {
+        let index = if up {
+            (self.active_layer + 1).min(self.layers.len() - 1)
+        } else {
+            self.active_layer.saturating_sub(1)
+        };
+        self.select_layer(index);
+    }
+
+    fn step_layer_opacity(&mut self, up: bool) {
+        let layer = &mut self.layers[self.active_layer];
+        layer.opacity = if up {
+            layer.opacity.saturating_add(OPACITY_STEP).min(FULL_OPACITY)
+        } else {
+            layer.opacity.saturating_sub(OPACITY_STEP)
+        };
+        self.mark_dirty();
+        self.status = self.layer_status();
+    }
 
-        match &mut self.mouse_mode {
-            MouseMode::Idle => {}
-            MouseMode::FreeDrag {
-                last,
-                spec,
-                builder,
-            } => {
-                for p in bresenham_line(*last, point) {
-                    apply_point_with_spec(canvas, builder, p, *spec);
-                }
-                *last = point;
-            }
-            MouseMode::ShapeDrag { current, .. } => {
-                *current = point;
-            }
-        }
+    fn toggle_layer_visibility(&mut self) {
+        let layer = &mut self.layers[self.active_layer];
+        layer.visible = !layer.visible;
+        self.mark_dirty();
+        self.status = self.layer_status();
     }
 
-    fn finish_left_draw(&mut self, maybe_end: Option<Point>) {
-        let mode = std::mem::replace(&mut self.mouse_mode, MouseMode::Idle);
+    fn layer_status(&self) -> String {
+        let layer = &self.layers[self.active_layer];
+        let hidden = if layer.visible { "" } else { " (hidden)" };
+        let opacity = if layer.opacity < FULL_OPACITY {
+            format!(" at {}%", layer.opacity)
+        } else {
+            String::new()
+        };
+        let name = &self.layers[self.active_layer].name;
+        let name = if name.is_empty() {
+            String::new()
+        } else {
+            format!(" \"{name}\"")
+        };
+        format!(
+            "Layer {} of {}{}{}{}",
+            self.active_layer + 1,
+            self.layers.len(),
+            name,
+            opacity,
+            hidden
+        )
+    }
 
-        match mode {
-            MouseMode::Idle => {}
-            MouseMode::FreeDrag { builder, .. } => {
-                self.commit_builder(builder);
-            }
-            MouseMode::ShapeDrag {
-                start,
-                current,
-                spec,
-                tool,
-                filled,
-            } => {
-                let end = maybe_end.unwrap_or(current);
-                let base_points = shape_points(tool, start, end, filled);
-                let mut builder = OperationBuilder::new();
+    /// Records a finished edit as its own undo step, unless it comes from a keyboard-cursor
+    /// press, which repeats like `commit_repeatable`.
+    fn commit_builder(&mut self, builder: OperationBuilder) {
+        self.record_edit(builder, self.keyboard_press);
+    }
 
-                for point in base_points {
-                    apply_point_with_spec(&mut self.canvas, &mut builder, point, spec);
-                }
+    /// Records an edit that is usually repeated, like a fill click or a selection nudge. Quick
+    /// repeats with the same tool join the previous undo step.
+    fn commit_repeatable(&mut self, builder: OperationBuilder) {
+        self.record_edit(builder, true);
+    }
 
-                self.commit_builder(builder);
-            }
+    /// Returns whether there was anything to record.
+    fn record_edit(&mut self, builder: OperationBuilder, repeatable: bool) -> bool {
+        let operation = builder.into_operation().on_layer(self.active_layer);
+        if operation.is_empty() {
+            return false;
         }
+        let now = Instant::now();
+        let merge = repeatable
+            && self.last_commit.is_some_and(|(tool, at)| {
+                tool == self.tool && now.duration_since(at) < MERGE_WINDOW
+            });
+        self.history.push_merged(operation, merge);
+        self.last_commit = repeatable.then_some((self.tool, now));
+        self.mark_dirty();
+        true
     }
 
-    fn apply_fill(&mut self, point: Point, spec: DrawSpec, builder: &mut OperationBuilder) {
-        let Some(target) = self.canvas.get_i32(point.x, point.y) else {
+    /// Flags unsaved changes for both the status line and the next autosave.
+    fn mark_dirty(&mut self) {
+        self.dirty = true;
+        self.autosave_pending = true;
+    }
+
+    /// Writes a recovery copy every `AUTOSAVE_INTERVAL` while there are new changes. The write runs on
+    /// a background thread with its own copy of the canvas so large drawings don't stall input.
+    fn autosave_tick(&mut self) {
+        if self
+            .autosave_job
+            .as_ref()
+            .is_some_and(|job| job.is_finished())
+        {
+            self.finish_autosave();
+        }
+        if self.autosave_job.is_some()
+            || !self.autosave_pending
+            || self.last_autosave.elapsed() < AUTOSAVE_INTERVAL
+        {
+            return;
+        }
+        let Some(path) = self.autosave_path.clone() else {
             return;
         };
 
-        let replacement = if spec.tool == Tool::Eraser {
-            PaintCell::blank()
-        } else {
-            PaintCell::new(spec.ch, spec.color)
-        };
+        let layers = self.layer_stack();
+        self.autosave_job = Some(std::thread::spawn(move || {
+            io::write_autosave(&path, &layers)
+        }));
+        self.autosave_pending = false;
+        self.last_autosave = Instant::now();
+    }
 
-        let points = flood_fill_points(&self.canvas, point, target, replacement);
-        for p in points {
-            builder.apply(&mut self.canvas, p.x, p.y, replacement);
+    fn finish_autosave(&mut self) {
+        let Some(job) = self.autosave_job.take() else {
+            return;
+        };
+        match job.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                self.report_error(format!("Autosave failed: {err}"));
+                self.autosave_pending = true;
+            }
+            Err(_) => {
+                self.report_error("Autosave failed".to_string());
+                self.autosave_pending = true;
+            }
         }
     }
 
-    fn commit_builder(&mut self, builder: OperationBuilder) {
-        let operation = builder.into_operation();
-        if !operation.is_empty() {
-            self.history.push(operation);
+    /// Loads an exited instance's autosave. The drawing is autosaved under this instance's name
+    /// from then on, so the old file is removed.
+    fn recover_autosave(&mut self, path: &Path) {
+        match io::load_layers(path, self.max_canvas_dim) {
+            Ok(recovered) => {
+                self.replace_layers(recovered);
+                io::remove_autosave(path);
+                self.status = "Recovered the autosave - save it to keep it".to_string();
+            }
+            Err(err) => {
+                self.report_error(format!("Could not recover autosave: {err}"));
+            }
         }
     }
 
@@ -586,36 +3625,116 @@ impl App {
         if cell.ch != ' ' {
             self.brush_char = cell.ch;
         }
-        self.color = cell.fg;
-        self.status = format!(
-            "Sampled '{}' / {}",
-            printable_char(self.brush_char),
-            self.color.name()
-        );
+        // A cell without a background keeps the current one, so sampling glyphs off a plain
+        // canvas doesn't lose it. Background mode paints with the current color as the
+        // background, so there the current color takes the background too.
+        self.bg = cell.bg.or(self.bg);
+        self.color = if self.background_mode {
+            self.bg.unwrap_or(self.color)
+        } else {
+            cell.fg
+        };
+        self.attrs = cell.attrs;
+
+        let mut style = format!("'{}' {}", printable_char(cell.ch), cell.fg.name());
+        if let Some(bg) = cell.bg {
+            style.push_str(&format!(" on {}", bg.name()));
+        }
+        for (attr, _, name) in CellAttrs::ALL {
+            if cell.attrs.contains(attr) {
+                style.push_str(&format!(" {name}"));
+            }
+        }
+        self.status = format!("Sampled {style}");
+    }
+
+    fn fill_preview_seeds(&self) -> Option<Vec<Point>> {
+        if !self.fill_seeds.is_empty() {
+            return Some(self.fill_seeds.clone());
+        }
+        if self.tool == Tool::Fill && matches!(self.mouse_mode, MouseMode::Idle) {
+            return self.hover.map(|p| vec![p]);
+        }
+        None
+    }
+
+    fn fill_warning(&self, preview_len: usize) -> Option<usize> {
+        let is_fill_preview = self.fill_preview_seeds().is_some()
+            || (self.tool == Tool::ReplaceAll && self.hover.is_some());
+        (is_fill_preview && preview_len > self.fill_warn_threshold).then_some(preview_len)
     }
 
     fn preview_points(&self) -> Vec<Point> {
+        if let Some(seeds) = self.fill_preview_seeds() {
+            let spec = self.current_draw_spec();
+            return flood_fill_points(
+                &self.canvas,
+                &spec.mirrored(&seeds),
+                self.fill_connectivity,
+                self.fill_match,
+                |cell| spec.paint(cell),
+            );
+        }
+
+        if !self.polygon.is_empty() {
+            // The pending edges, plus a live segment out to the pointer.
+            let spec = self.current_draw_spec();
+            let mut vertices = self.polygon.clone();
+            vertices.extend(self.hover);
+            return spec.mirrored(&spec.line_style.apply(polyline_points(&vertices, false)));
+        }
+
+        if let (Tool::ReplaceAll, MouseMode::Idle, Some(hover)) =
+            (self.tool, &self.mouse_mode, self.hover)
+        {
+            return matching_points(&self.canvas, hover, self.fill_match);
+        }
+
+        if let MouseMode::TextEntry { cursor, .. } = self.mouse_mode {
+            return vec![cursor];
+        }
+
+        if let MouseMode::Measure { start, current } = self.mouse_mode {
+            return bresenham_line(start, current);
+        }
+
         let MouseMode::ShapeDrag {
             start,
             current,
             spec,
             tool,
             filled,
+            square,
+            centered,
         } = self.mouse_mode
         else {
             return Vec::new();
         };
 
-        let base_points = shape_points(tool, start, current, filled);
-        if spec.size <= 1 {
+        let base_points = shape_points(tool, start, current, filled, square, centered, spec);
+        if tool == Tool::BoxLine {
             return base_points;
         }
+        if spec.size <= 1 || tool == Tool::Gradient {
+            return spec.mirrored(&base_points);
+        }
 
-        let mut set = HashSet::new();
+        // Large brushes overlap heavily, so dedup against a per-cell bitmap instead of hashing;
+        // only on-canvas cells are ever shown.
+        let width = self.canvas.width() as i32;
+        let height = self.canvas.height() as i32;
+        let mut seen = vec![false; width as usize * height as usize];
         let mut out = Vec::new();
         for point in base_points {
-            for brush in brush_points(point, spec.size) {
-                if set.insert((brush.x, brush.y)) {
+            let brush_points = brush_points_iter(point, spec.size, spec.shape)
+                .flat_map(|p| spec.symmetry.reflect(p, spec.mirror_axis));
+            for brush in brush_points {
+                if brush.x < 0 || brush.y < 0 || brush.x >= width || brush.y >= height {
+                    continue;
+                }
+                let index = (brush.y * width + brush.x) as usize;
+                if !seen[index] {
+                    seen[index] = true;
                     out.push(brush);
                 }
             }
@@ -625,76 +3744,151 @@ impl App {
 
     fn preview_style(&self) -> Option<PreviewStyle> {
         match self.mouse_mode {
-            MouseMode::ShapeDrag { spec, .. } => Some(PreviewStyle {
-                ch: spec.ch,
-                fg: spec.color,
-                erase: spec.tool == Tool::Eraser,
+            MouseMode::ShapeDrag {
+                tool: Tool::BoxLine,
+                start,
+                current,
+                spec,
+                ..
+            } => {
+                let points = box_line_points(start, current);
+                let ch = match points.as_slice() {
+                    [first, second, ..] if first.y != second.y => '│',
+                    _ => '─',
+                };
+                Some(DrawSpec { ch, ..spec }.preview_style())
+            }
+            MouseMode::ShapeDrag { spec, .. } | MouseMode::TextEntry { spec, .. } => {
+                Some(spec.preview_style())
+            }
+            MouseMode::Measure { .. } => Some(PreviewStyle {
+                ch: '·',
+                fg: PaintColor::Yellow,
+                bg: None,
+                literal_space: false,
+                erase: false,
+                background: false,
             }),
+            _ if self.fill_preview_seeds().is_some()
+                || !self.polygon.is_empty()
+                || (self.tool == Tool::ReplaceAll && self.hover.is_some()) =>
+            {
+                Some(self.current_draw_spec().preview_style())
+            }
             _ => None,
         }
     }
 
     fn cycle_color(&mut self, forward: bool) {
-        let palette = PaintColor::quick_palette();
+        let palette = &self.palette;
 
-        let mut idx = palette.iter().position(|c| *c == self.color).unwrap_or(0);
-        if forward {
-            idx = (idx + 1) % palette.len();
-        } else if idx == 0 {
-            idx = palette.len() - 1;
-        } else {
-            idx -= 1;
-        }
+        // An RGB color outside the palette steps onto the palette's first or last entry.
+        let idx = match palette.iter().position(|c| *c == self.color) {
+            Some(idx) if forward => (idx + 1) % palette.len(),
+            Some(0) | None if !forward => palette.len() - 1,
+            Some(idx) if !forward => idx - 1,
+            _ => 0,
+        };
 
         self.color = palette[idx];
     }
 
-    fn cycle_brush_char(&mut self, forward: bool) {
-        let choices = ui::BRUSH_CHOICES;
-        let mut idx = choices
+    fn cycle_blank_glyph(&mut self) {
+        let choices = ui::BLANK_GLYPHS;
+        let idx = choices
             .iter()
-            .position(|ch| *ch == self.brush_char)
+            .position(|ch| *ch == self.blank_glyph)
             .unwrap_or(0);
+        self.blank_glyph = choices[(idx + 1) % choices.len()];
+        self.status = format!(
+            "Blank cells shown as '{}'",
+            printable_char(self.blank_glyph)
+        );
+    }
 
-        if forward {
-            idx = (idx + 1) % choices.len();
-        } else if idx == 0 {
-            idx = choices.len() - 1;
-        } else {
-            idx -= 1;
-        }
+    fn cycle_brush_char(&mut self, forward: bool) {
+        let choices = ui::BRUSH_CHOICES;
+        let current = choices.iter().position(|ch| *ch == self.brush_char);
+
+        // A custom character isn't in the list, so cycling starts again from either end.
+        let idx = match (current, forward) {
+            (Some(idx), true) => (idx + 1) % choices.len(),
+            (Some(0), false) | (None, false) => choices.len() - 1,
+            (Some(idx), false) => idx - 1,
+            (None, true) => 0,
+        };
 
         self.brush_char = choices[idx];
     }
 }
 
-fn has_shortcut_modifier(modifiers: KeyModifiers) -> bool {
-    modifiers.contains(KeyModifiers::CONTROL) || modifiers.contains(KeyModifiers::SUPER)
+fn parse_canvas_size(input: &str, max_dim: u16) -> Option<(u16, u16)> {
+    let (w, h) = input.trim().split_once(['x', 'X'])?;
+    let w = w.trim().parse::<u16>().ok()?;
+    let h = h.trim().parse::<u16>().ok()?;
+    let valid = 1..=max_dim;
+    (valid.contains(&w) && valid.contains(&h)).then_some((w, h))
 }
 
-fn is_undo_shortcut(key: KeyEvent) -> bool {
-    if !has_shortcut_modifier(key.modifiers) || key.modifiers.contains(KeyModifiers::SHIFT) {
-        return false;
-    }
+/// Draws a box-drawing segment. Each cell joins the sides the segment passes through with the
+/// sides of any box glyph already there that still lead to a connected neighbour.
+fn apply_box_line(
+    canvas: &mut Canvas,
+    builder: &mut OperationBuilder,
+    start: Point,
+    end: Point,
+    spec: DrawSpec,
+) {
+    let points = box_line_points(start, end);
+    for (i, &point) in points.iter().enumerate() {
+        let Some(existing) = canvas.get_i32(point.x, point.y) else {
+            continue;
+        };
 
-    match key.code {
-        KeyCode::Char(ch) => ch.eq_ignore_ascii_case(&'z'),
-        _ => false,
+        let mut sides = connected_box_sides(canvas, point);
+        if let Some(&prev) = i.checked_sub(1).and_then(|j| points.get(j)) {
+            sides |= box_side_towards(point, prev);
+        }
+        if let Some(&next) = points.get(i + 1) {
+            sides |= box_side_towards(point, next);
+        }
+
+        let ch = box_glyph(sides);
+        builder.apply(
+            canvas,
+            point.x,
+            point.y,
+            DrawSpec { ch, ..spec }.paint(existing),
+        );
     }
 }
 
-fn is_redo_shortcut(key: KeyEvent) -> bool {
-    if !has_shortcut_modifier(key.modifiers) {
-        return false;
-    }
+fn connected_box_sides(canvas: &Canvas, point: Point) -> u8 {
+    let Some(cell) = canvas.get_i32(point.x, point.y) else {
+        return 0;
+    };
 
-    match key.code {
-        KeyCode::Char(ch) => {
-            let lower = ch.to_ascii_lowercase();
-            lower == 'y' || (lower == 'z' && key.modifiers.contains(KeyModifiers::SHIFT))
-        }
-        _ => false,
-    }
+    let neighbours = [
+        Point::new(point.x, point.y - 1),
+        Point::new(point.x + 1, point.y),
+        Point::new(point.x, point.y + 1),
+        Point::new(point.x - 1, point.y),
+    ];
+    neighbours
+        .into_iter()
+        .map(|neighbour| {
+            let side = box_side_towards(point, neighbour);
+            let facing = box_side_towards(neighbour, point);
+            let linked = canvas
+                .get_i32(neighbour.x, neighbour.y)
+                .is_some_and(|n| box_sides(n.ch) & facing != 0);
+            if box_sides(cell.ch) & side != 0 && linked {
+                side
+            } else {
+                0
+            }
+        })
+        .fold(0, |sides, side| sides | side)
 }
 
 fn apply_point_with_spec(
@@ -703,22 +3897,147 @@ fn apply_point_with_spec(
     point: Point,
     spec: DrawSpec,
 ) {
-    let draw_cell = if spec.tool == Tool::Eraser {
-        PaintCell::blank()
-    } else {
-        PaintCell::new(spec.ch, spec.color)
+    // `spec.paint` decides the cell: the Eraser leaves `PaintCell::blank()`, while a space
+    // brush leaves a space on a background (or a literal space with attributes), which still
+    // counts as painted for fills, layers and exports.
+    let mut stamp = |p: Point| {
+        // Each reflection is stamped once; the builder keeps repeats from stacking up in undo.
+        for p in spec.symmetry.reflect(p, spec.mirror_axis) {
+            if let Some(existing) = canvas.get_i32(p.x, p.y) {
+                builder.apply(canvas, p.x, p.y, spec.paint(existing));
+            }
+        }
+    };
+
+    if spec.tool == Tool::Spray {
+        // Brush size widens the spray radius rather than stamping a square.
+        let radius = spec.size + 1;
+        for p in spray_points(point, radius, SPRAY_DENSITY, &mut rand::thread_rng()) {
+            stamp(p);
+        }
+        return;
+    }
+
+    for p in brush_points_iter(point, spec.size, spec.shape) {
+        stamp(p);
+    }
+}
+
+/// Draws the shaft with the current brush, then a one-cell-wide arrowhead at `end`. Mirrored
+/// copies get heads that point their own way rather than reflections of the original glyphs.
+fn apply_arrow(
+    canvas: &mut Canvas,
+    builder: &mut OperationBuilder,
+    start: Point,
+    end: Point,
+    spec: DrawSpec,
+) {
+    for point in spec.line_style.apply(bresenham_line(start, end)) {
+        apply_point_with_spec(canvas, builder, point, spec);
+    }
+
+    let head_spec = DrawSpec {
+        size: 1,
+        symmetry: Symmetry::Off,
+        ..spec
     };
+    let tips = spec.symmetry.images(end, spec.mirror_axis);
+    let tails = spec.symmetry.images(start, spec.mirror_axis);
+    for (tip, tail) in tips.into_iter().zip(tails) {
+        for (point, glyph) in arrowhead_cells(tip, tail) {
+            // Half-block pixels have no glyph to change, only a color.
+            let ch = if spec.ch == HALF_BLOCK_PIXEL {
+                spec.ch
+            } else {
+                glyph
+            };
+            apply_point_with_spec(canvas, builder, point, DrawSpec { ch, ..head_spec });
+        }
+    }
+}
+
+/// Writes a block with its top-left corner at `origin`; cells past the canvas edge are dropped.
+fn stamp_block(
+    canvas: &mut Canvas,
+    builder: &mut OperationBuilder,
+    block: &CellBlock,
+    origin: Point,
+) {
+    for y in 0..block.height {
+        for x in 0..block.width {
+            builder.apply(
+                canvas,
+                origin.x + x as i32,
+                origin.y + y as i32,
+                block.get(x, y),
+            );
+        }
+    }
+}
 
-    for p in brush_points(point, spec.size) {
-        builder.apply(canvas, p.x, p.y, draw_cell);
+/// Stamps a pattern-brush block centered on `center` (and its mirror images). Blank cells in
+/// the block are transparent, so overlapping stamps and existing art show through.
+fn stamp_pattern(
+    canvas: &mut Canvas,
+    builder: &mut OperationBuilder,
+    block: &CellBlock,
+    center: Point,
+    spec: DrawSpec,
+) {
+    let half = Point::new(block.width as i32 / 2, block.height as i32 / 2);
+    for center in spec.symmetry.reflect(center, spec.mirror_axis) {
+        for y in 0..block.height {
+            for x in 0..block.width {
+                let cell = block.get(x, y);
+                if !cell.is_blank() {
+                    let (cx, cy) = (center.x - half.x + x as i32, center.y - half.y + y as i32);
+                    builder.apply(canvas, cx, cy, cell);
+                }
+            }
+        }
     }
 }
 
-fn shape_points(tool: Tool, start: Point, end: Point, filled: bool) -> Vec<Point> {
+fn shape_points(
+    tool: Tool,
+    start: Point,
+    end: Point,
+    filled: bool,
+    square: bool,
+    centered: bool,
+    spec: DrawSpec,
+) -> Vec<Point> {
+    let boxed = matches!(tool, Tool::Rectangle | Tool::Circle);
+    let end = if square && boxed {
+        // Half-block pixels are square already; text cells are about twice as tall as wide.
+        let aspect = if spec.ch == HALF_BLOCK_PIXEL { 1 } else { 2 };
+        square_end(start, end, aspect)
+    } else {
+        end
+    };
+    // From the center, the opposite corner mirrors the pointer through the click point.
+    let start = if centered && boxed {
+        Point::new(2 * start.x - end.x, 2 * start.y - end.y)
+    } else {
+        start
+    };
     match tool {
-        Tool::Line => bresenham_line(start, end),
-        Tool::Rectangle => rectangle_points(start, end, filled),
+        Tool::Line if spec.shades_lines() => shaded_line_cells(start, end)
+            .into_iter()
+            .filter(|&(_, coverage)| coverage_glyph(coverage).is_some())
+            .map(|(point, _)| point)
+            .collect(),
+        Tool::Line => spec.line_style.apply(bresenham_line(start, end)),
+        Tool::Rectangle => rounded_rectangle_points(start, end, spec.corner_radius, filled),
+        Tool::Circle if filled => filled_ellipse_points(start, end),
         Tool::Circle => ellipse_points(start, end),
+        Tool::BoxLine => box_line_points(start, end),
+        Tool::Gradient => rectangle_points(start, end, true),
+        Tool::Arrow => {
+            let mut points = spec.line_style.apply(bresenham_line(start, end));
+            points.extend(arrowhead_points(end, start));
+            points
+        }
         _ => Vec::new(),
     }
 }
@@ -730,3 +4049,433 @@ fn printable_char(ch: char) -> String {
         ch.to_string()
     }
 }
+
+/// A Measure reading: both ends, the offset between them, the Chebyshev (king-move) and
+/// straight-line distances in cells, and the size of the box the two points span.
+fn measure_status(start: Point, end: Point) -> String {
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let chebyshev = dx.abs().max(dy.abs());
+    let euclidean = f64::from(dx).hypot(f64::from(dy));
+    format!(
+        "{},{} -> {},{} dx:{} dy:{} dist:{} ({:.2}) box:{}x{}",
+        start.x,
+        start.y,
+        end.x,
+        end.y,
+        dx,
+        dy,
+        chebyshev,
+        euclidean,
+        dx.abs() + 1,
+        dy.abs() + 1
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An app laid out for an 80x24 terminal, with the canvas filling the drawing area.
+    fn app_in_window() -> App {
+        let mut app = App::new(1, 1, 100);
+        let ui_state = ui::build_ui_state(Rect::new(0, 0, 80, 24), &app.palette);
+        app.resize_to_fit(&ui_state);
+        app.last_ui = ui_state;
+        app
+    }
+
+    fn mouse(app: &mut App, kind: MouseEventKind, x: i32, y: i32) {
+        let inner = app.last_ui.canvas_inner;
+        app.handle_mouse(MouseEvent {
+            kind,
+            column: (inner.x as i32 + x) as u16,
+            row: (inner.y as i32 + y) as u16,
+            modifiers: KeyModifiers::NONE,
+        });
+    }
+
+    /// Presses at the first point, drags through the rest and releases at the last, all in
+    /// canvas-area cell offsets.
+    fn stroke(app: &mut App, points: &[(i32, i32)]) {
+        let left = MouseButton::Left;
+        let (first, last) = (points[0], points[points.len() - 1]);
+        mouse(app, MouseEventKind::Down(left), first.0, first.1);
+        for &(x, y) in &points[1..] {
+            mouse(app, MouseEventKind::Drag(left), x, y);
+        }
+        mouse(app, MouseEventKind::Up(left), last.0, last.1);
+    }
+
+    impl App {
+        /// Runs a batch of draws on the layer called `name`, adding it on top if there is none, and
+        /// records them as one undo step on that layer without changing the active layer. Returns
+        /// the recorded step, or `None` when the draws changed nothing or there was no room for a
+        /// new layer.
+        fn with_layer(
+            &mut self,
+            name: &str,
+            draw: impl FnOnce(&mut Canvas, &mut OperationBuilder),
+        ) -> Option<&Operation> {
+            let index = match self.layers.iter().position(|layer| layer.name == name) {
+                Some(index) => index,
+                None if self.layers.len() >= MAX_LAYERS => return None,
+                None => {
+                    let mut layers = self.layer_stack();
+                    let blank = Canvas::new(self.canvas.width(), self.canvas.height());
+                    layers.push(Layer {
+                        name: name.to_string(),
+                        ..Layer::new(blank)
+                    });
+                    self.replace_layers(layers);
+                    self.layers.len() - 1
+                }
+            };
+
+            let active = self.active_layer;
+            self.attach_active();
+            self.active_layer = index;
+            self.detach_active();
+            let mut builder = OperationBuilder::new();
+            draw(&mut self.canvas, &mut builder);
+            let recorded = self.record_edit(builder, false);
+            self.attach_active();
+            self.active_layer = active;
+            self.detach_active();
+
+            recorded.then(|| self.history.undo_ops().next()).flatten()
+        }
+    }
+
+    #[test]
+    fn rainbow_stroke_changes_color_every_cell() {
+        let mut app = app_in_window();
+        app.rainbow = true;
+        stroke(&mut app, &[(1, 1), (6, 1)]);
+
+        let colors: Vec<PaintColor> = (1..=6).map(|x| app.canvas.get(x, 1).fg).collect();
+        assert!(colors.iter().all(|color| *color != PaintColor::Default));
+        for pair in colors.windows(2) {
+            assert_ne!(pair[0], pair[1], "{colors:?}");
+        }
+        assert_eq!(app.history.undo_len(), 1);
+    }
+
+    #[test]
+    fn only_the_eraser_leaves_blank_cells() {
+        let mut app = app_in_window();
+        app.brush_char = ' ';
+        app.color = PaintColor::Default;
+        app.bg = Some(PaintColor::Blue);
+        stroke(&mut app, &[(1, 1), (3, 1)]);
+        let painted = app.canvas.get(2, 1);
+        assert!(!painted.is_blank());
+        assert_eq!(painted.bg, Some(PaintColor::Blue));
+
+        app.literal_spaces = true;
+        app.attrs = CellAttrs::UNDERLINE;
+        app.bg = None;
+        stroke(&mut app, &[(1, 2), (3, 2)]);
+        assert!(!app.canvas.get(2, 2).is_blank());
+
+        app.attrs = CellAttrs::NONE;
+        let steps = app.history.undo_len();
+        for literal in [true, false] {
+            app.literal_spaces = literal;
+            stroke(&mut app, &[(1, 1), (3, 1)]);
+        }
+        assert_eq!(app.history.undo_len(), steps);
+        assert_eq!(app.canvas.get(2, 1), painted);
+
+        app.run_action(Action::Eraser);
+        stroke(&mut app, &[(1, 1), (3, 1)]);
+        assert!(app.canvas.get(2, 1).is_blank());
+    }
+
+    #[test]
+    fn only_repeated_edits_share_an_undo_step() {
+        let mut app = app_in_window();
+        stroke(&mut app, &[(1, 1), (3, 1)]);
+        stroke(&mut app, &[(1, 2), (3, 2)]);
+        app.perform_undo();
+        assert!(app.canvas.get(1, 2).is_blank());
+        assert!(!app.canvas.get(1, 1).is_blank());
+
+        let pencil = app.canvas.get(1, 1);
+        app.run_action(Action::Fill);
+        app.color = PaintColor::Red;
+        stroke(&mut app, &[(0, 0)]);
+        app.color = PaintColor::Blue;
+        stroke(&mut app, &[(0, 0)]);
+        assert_eq!(app.canvas.get(0, 0).fg, PaintColor::Blue);
+        app.perform_undo();
+        assert!(app.canvas.get(0, 0).is_blank());
+        assert_eq!(app.canvas.get(1, 1), pencil);
+    }
+
+    #[test]
+    fn sampling_picks_up_both_colors_and_keeps_a_background_it_cannot_see() {
+        let mut app = app_in_window();
+        let right = MouseButton::Right;
+        let styled = PaintCell::new('#', PaintColor::Yellow).with_bg(Some(PaintColor::Blue));
+        app.canvas.set(0, 0, styled);
+        app.canvas.set(1, 0, PaintCell::new('*', PaintColor::Red));
+
+        mouse(&mut app, MouseEventKind::Down(right), 0, 0);
+        mouse(&mut app, MouseEventKind::Up(right), 0, 0);
+        assert_eq!(
+            (app.color, app.bg),
+            (PaintColor::Yellow, Some(PaintColor::Blue))
+        );
+        assert_eq!(app.status, "Sampled '#' Yellow on Blue");
+
+        mouse(&mut app, MouseEventKind::Down(right), 1, 0);
+        mouse(&mut app, MouseEventKind::Up(right), 1, 0);
+        assert_eq!(
+            (app.color, app.bg),
+            (PaintColor::Red, Some(PaintColor::Blue))
+        );
+        stroke(&mut app, &[(3, 0)]);
+        assert_eq!(
+            app.canvas.get(3, 0),
+            PaintCell::new('*', PaintColor::Red).with_bg(Some(PaintColor::Blue))
+        );
+
+        app.run_action(Action::BackgroundMode);
+        mouse(&mut app, MouseEventKind::Down(right), 1, 0);
+        mouse(&mut app, MouseEventKind::Up(right), 1, 0);
+        assert_eq!(app.color, PaintColor::Blue);
+        app.run_action(Action::DefaultColor);
+        assert_eq!(app.bg, None);
+    }
+
+    #[test]
+    fn stroke_leaving_the_canvas_runs_to_the_edge_and_reconnects() {
+        let mut app = app_in_window();
+        let inner = app.last_ui.canvas_inner;
+        assert_eq!(app.canvas.width(), inner.width);
+        let edge = inner.width as i32 - 1;
+
+        // Out past the right edge along row 1, then back in two rows lower.
+        stroke(&mut app, &[(edge - 2, 1), (edge + 6, 1), (edge - 2, 3)]);
+
+        let painted: Vec<(u16, u16)> = (0..app.canvas.height())
+            .flat_map(|y| (0..app.canvas.width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| !app.canvas.get(x, y).is_blank())
+            .collect();
+        let edge = edge as u16;
+        let mut expected = vec![
+            (edge - 2, 1),
+            (edge - 1, 1),
+            (edge, 1),
+            (edge - 1, 2),
+            (edge - 2, 3),
+        ];
+        expected.sort_by_key(|&(x, y)| (y, x));
+        assert_eq!(painted, expected);
+        assert_eq!(app.history.undo_len(), 1);
+    }
+
+    #[test]
+    fn flip_waits_for_confirmation() {
+        let mut app = app_in_window();
+        app.canvas
+            .set(0, 0, PaintCell::new('x', PaintColor::Default));
+        let right = app.canvas.width() - 1;
+
+        app.run_action(Action::FlipHorizontal);
+        assert!(matches!(app.prompt, PromptState::Confirm(_)));
+        assert_eq!(app.canvas.get(0, 0).ch, 'x');
+
+        app.commit_prompt();
+        assert_eq!(app.canvas.get(right, 0).ch, 'x');
+        assert!(app.canvas.get(0, 0).is_blank());
+    }
+
+    #[test]
+    fn reset_view_centers_a_small_canvas() {
+        let mut app = app_in_window();
+        app.set_canvas_size(Some((10, 4)));
+        app.zoom = 2;
+        app.run_action(Action::ResetView);
+        assert_eq!((app.zoom, app.scroll), (1, (0, 0)));
+
+        let inner = app.last_ui.canvas_inner;
+        let (x, y) = ((inner.width as i32 - 10) / 2, (inner.height as i32 - 4) / 2);
+        stroke(&mut app, &[(x, y)]);
+        assert!(!app.canvas.get(0, 0).is_blank());
+        stroke(&mut app, &[(x + 9, y + 3)]);
+        assert!(!app.canvas.get(9, 3).is_blank());
+        assert_eq!(
+            app.resize_handle_pos(),
+            Some((
+                (inner.x as i32 + x + 10) as u16,
+                (inner.y as i32 + y + 4) as u16
+            ))
+        );
+    }
+
+    #[test]
+    fn dragging_the_resize_handle_stops_at_the_size_limit() {
+        let mut app = app_in_window();
+        app.set_canvas_size(Some((10, 4)));
+        app.max_canvas_dim = 12;
+        let inner = app.last_ui.canvas_inner;
+        let (x, y) = app.resize_handle_pos().unwrap();
+        let (x, y) = (x as i32 - inner.x as i32, y as i32 - inner.y as i32);
+        stroke(&mut app, &[(x, y), (x + 20, y + 20)]);
+        assert_eq!((app.canvas.width(), app.canvas.height()), (12, 12));
+    }
+
+    /// Two layers: a red `a` at (0,0) below, a green `b` at (1,0) on top, and a hidden third
+    /// layer with a `c` at (2,0). The bottom layer is active.
+    fn layered_app() -> App {
+        let mut app = app_in_window();
+        app.canvas.set(0, 0, PaintCell::new('a', PaintColor::Red));
+        app.add_layer();
+        app.canvas.set(1, 0, PaintCell::new('b', PaintColor::Green));
+        app.add_layer();
+        app.canvas.set(2, 0, PaintCell::new('c', PaintColor::Blue));
+        app.toggle_layer_visibility();
+        app.select_layer(0);
+        app
+    }
+
+    #[test]
+    fn flattening_matches_the_rendered_layers_and_undoes() {
+        let mut app = layered_app();
+        let rendered = app.flattened().into_owned();
+        let stack = app.layer_stack();
+
+        app.run_action(Action::FlattenLayers);
+        assert_eq!(app.layers.len(), 1);
+        assert_eq!(app.canvas, rendered);
+        assert_eq!(app.canvas.get(1, 0).ch, 'b');
+        assert!(app.canvas.get(2, 0).is_blank());
+
+        app.run_action(Action::Undo);
+        assert_eq!(app.layer_stack(), stack);
+    }
+
+    #[test]
+    fn moving_a_layer_changes_the_stacking_order() {
+        let mut app = layered_app();
+        app.canvas.set(1, 0, PaintCell::new('A', PaintColor::Red));
+        assert_eq!(app.flattened().get(1, 0).ch, 'b');
+
+        app.run_action(Action::MoveLayerUp);
+        assert_eq!(app.active_layer, 1);
+        assert_eq!(app.canvas.get(0, 0).ch, 'a');
+        assert_eq!(app.flattened().get(1, 0).ch, 'A');
+
+        app.run_action(Action::Undo);
+        assert_eq!(app.flattened().get(1, 0).ch, 'b');
+    }
+
+    #[test]
+    fn hiding_a_layer_is_an_unsaved_change() {
+        let mut app = layered_app();
+        app.dirty = false;
+        app.run_action(Action::ToggleLayer);
+        assert!(!app.layers[0].visible);
+        assert!(app.dirty);
+    }
+
+    #[test]
+    fn undo_on_one_layer_leaves_the_others_alone() {
+        let mut app = layered_app();
+        app.layers[1].name = "middle".to_string();
+        let before = app.layer_stack();
+        app.with_layer("middle", |canvas, builder| {
+            builder.apply(canvas, 0, 0, PaintCell::new('m', PaintColor::Yellow));
+            builder.apply(canvas, 1, 0, PaintCell::new('m', PaintColor::Yellow));
+        });
+        let after = app.layer_stack();
+        assert_eq!(after[0], before[0]);
+        assert_eq!(after[2], before[2]);
+        assert_eq!(after[1].canvas.get(1, 0).ch, 'm');
+
+        app.perform_undo();
+        assert_eq!(app.layer_stack(), before);
+        assert_eq!(app.active_layer, 0);
+        app.perform_redo();
+        assert_eq!(app.layer_stack(), after);
+        assert_eq!(app.canvas, before[0].canvas);
+    }
+
+    #[test]
+    fn layers_survive_a_json_save_and_load() {
+        let mut app = layered_app();
+        app.layers[2].name = "top".to_string();
+        app.step_layer_opacity(false);
+        let stack = app.layer_stack();
+        let path =
+            std::env::temp_dir().join(format!("termipaint-layers-{}.json", std::process::id()));
+        io::save_layers(&path, &stack, &app.export_options).unwrap();
+
+        let mut loaded = app_in_window();
+        loaded.load_file(path.clone());
+        std::fs::remove_file(&path).ok();
+        assert_eq!(loaded.layer_stack(), stack);
+        assert!(!loaded.layers[2].visible);
+        assert_eq!(loaded.layers[0].opacity, FULL_OPACITY - OPACITY_STEP);
+    }
+
+    #[test]
+    fn renamed_layers_keep_their_name_in_json() {
+        let mut app = layered_app();
+        app.run_action(Action::RenameLayer);
+        let PromptState::RenameLayer(input) = &mut app.prompt else {
+            panic!("no rename prompt");
+        };
+        input.push_str(" sky ");
+        app.commit_prompt();
+        assert_eq!(app.layers[0].name, "sky");
+        assert!(app.dirty);
+
+        let text = serde_json::to_string(&app.layer_stack()).unwrap();
+        let layers: Vec<Layer> = serde_json::from_str(&text).unwrap();
+        assert_eq!(layers[0].name, "sky");
+        assert_eq!(layers[1].name, "");
+    }
+
+    #[test]
+    fn named_layers_compose_bottom_to_top() {
+        let mut app = app_in_window();
+        let water = PaintCell::new('~', PaintColor::Blue);
+        let green = PaintCell::new('^', PaintColor::Green);
+        app.with_layer("sky", |canvas, builder| {
+            for x in 0..4 {
+                builder.apply(canvas, x, 0, water);
+            }
+        });
+        let tree = app.with_layer("tree", |canvas, builder| {
+            builder.apply(canvas, 1, 0, green);
+            builder.apply(canvas, 1, 1, green);
+        });
+        assert!(tree.is_some_and(|operation| !operation.is_empty()));
+        let steps = app.history.undo_len();
+        assert!(app.with_layer("tree", |_, _| {}).is_none());
+        assert_eq!(app.history.undo_len(), steps);
+
+        assert_eq!(app.layers.len(), 3);
+        assert_eq!(app.active_layer, 0);
+        let flat = app.flattened();
+        let row: String = (0..5).map(|x| flat.get(x, 0).ch).collect();
+        assert_eq!(row, "~^~~ ");
+        assert_eq!(flat.get(1, 1), green);
+
+        app.perform_undo();
+        let flat = app.flattened();
+        assert_eq!(flat.get(1, 0), water);
+        assert!(flat.get(1, 1).is_blank());
+    }
+
+    #[test]
+    fn max_size_is_capped() {
+        let parse = |args: &[&str]| Args::parse(args.iter().map(|arg| arg.to_string()));
+        let args = parse(&["--size", "3000x10", "--max-size", "4000"]).unwrap();
+        assert_eq!((args.max_size, args.size), (4000, Some((3000, 10))));
+        assert!(parse(&["--max-size", "4001"]).is_err());
+        assert!(parse(&["--max-size", "100", "--image-width", "101"]).is_err());
+    }
+}
@@ -1,6 +1,8 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BTreeMap, HashSet, VecDeque};
 
-use crate::canvas::{Canvas, PaintCell};
+use rand::{Rng, RngCore};
+
+use crate::canvas::{Canvas, PaintCell, PaintColor, BAYER_4X4, DEFAULT_FG_RGB};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Point {
@@ -22,10 +24,19 @@ pub enum Tool {
     Rectangle,
     Circle,
     Fill,
+    Spray,
+    Text,
+    Select,
+    BoxLine,
+    Gradient,
+    ReplaceAll,
+    Arrow,
+    Polygon,
+    Measure,
 }
 
 impl Tool {
-    pub const fn all() -> [Tool; 6] {
+    pub const fn all() -> [Tool; 15] {
         [
             Tool::Pencil,
             Tool::Eraser,
@@ -33,9 +44,37 @@ impl Tool {
             Tool::Rectangle,
             Tool::Circle,
             Tool::Fill,
+            Tool::Spray,
+            Tool::Text,
+            Tool::Select,
+            Tool::BoxLine,
+            Tool::Gradient,
+            Tool::ReplaceAll,
+            Tool::Arrow,
+            Tool::Polygon,
+            Tool::Measure,
         ]
     }
 
+    /// The tool after this one in `all()`, wrapping around.
+    pub fn next(self) -> Self {
+        let all = Self::all();
+        all[(self.index() + 1) % all.len()]
+    }
+
+    /// The tool before this one in `all()`, wrapping around.
+    pub fn prev(self) -> Self {
+        let all = Self::all();
+        all[(self.index() + all.len() - 1) % all.len()]
+    }
+
+    fn index(self) -> usize {
+        Self::all()
+            .iter()
+            .position(|&tool| tool == self)
+            .unwrap_or(0)
+    }
+
     pub const fn name(self) -> &'static str {
         match self {
             Tool::Pencil => "Pencil",
@@ -44,9 +83,38 @@ impl Tool {
             Tool::Rectangle => "Rectangle",
             Tool::Circle => "Circle",
             Tool::Fill => "Fill",
+            Tool::Spray => "Spray",
+            Tool::Text => "Text",
+            Tool::Select => "Select",
+            Tool::BoxLine => "Box Line",
+            Tool::Gradient => "Gradient",
+            Tool::ReplaceAll => "Replace All",
+            Tool::Arrow => "Arrow",
+            Tool::Polygon => "Polygon",
+            Tool::Measure => "Measure",
         }
     }
 
+    /// Freehand and shape tools, which draw while a button is held rather than on a click.
+    pub const fn paints_by_dragging(self) -> bool {
+        matches!(
+            self,
+            Tool::Pencil
+                | Tool::Eraser
+                | Tool::Spray
+                | Tool::Line
+                | Tool::Rectangle
+                | Tool::Circle
+                | Tool::BoxLine
+                | Tool::Gradient
+                | Tool::Arrow
+        )
+    }
+
+    pub const fn supports_fill(self) -> bool {
+        matches!(self, Tool::Rectangle | Tool::Circle)
+    }
+
     pub const fn short_label(self) -> &'static str {
         match self {
             Tool::Pencil => "Pencil(P)",
@@ -55,21 +123,528 @@ impl Tool {
             Tool::Rectangle => "Rect(R)",
             Tool::Circle => "Circle(C)",
             Tool::Fill => "Fill(F)",
+            Tool::Spray => "Spray(A)",
+            Tool::Text => "Text(I)",
+            Tool::Select => "Select(S)",
+            Tool::BoxLine => "Box(J)",
+            Tool::Gradient => "Gradient(Z)",
+            Tool::ReplaceAll => "Replace(V)",
+            Tool::Arrow => "Arrow(⇧W)",
+            Tool::Polygon => "Poly(⇧O)",
+            Tool::Measure => "Measure(⇧Y)",
+        }
+    }
+}
+
+/// How a fill picks the character for each cell it paints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillPattern {
+    Solid,
+    Checker,
+    Shades,
+    Bayer,
+}
+
+impl FillPattern {
+    pub const fn name(self) -> &'static str {
+        match self {
+            FillPattern::Solid => "solid",
+            FillPattern::Checker => "checker",
+            FillPattern::Shades => "shades",
+            FillPattern::Bayer => "dither",
+        }
+    }
+
+    pub const fn next(self) -> Self {
+        match self {
+            FillPattern::Solid => FillPattern::Checker,
+            FillPattern::Checker => FillPattern::Shades,
+            FillPattern::Shades => FillPattern::Bayer,
+            FillPattern::Bayer => FillPattern::Solid,
+        }
+    }
+
+    /// The character the pattern puts at `point`; `brush` is the solid fill character.
+    pub fn char_at(self, point: Point, brush: char) -> char {
+        const SHADES: [char; 3] = ['░', '▒', '▓'];
+        // Ordered-dither thresholds mapped onto four shading levels.
+        const LEVELS: [char; 4] = ['░', '▒', '▓', '█'];
+
+        let (x, y) = (
+            point.x.rem_euclid(4) as usize,
+            point.y.rem_euclid(4) as usize,
+        );
+        match self {
+            FillPattern::Solid => brush,
+            FillPattern::Checker if (x + y) % 2 == 0 => brush,
+            FillPattern::Checker => '░',
+            FillPattern::Shades => SHADES[(point.x + point.y).rem_euclid(3) as usize],
+            FillPattern::Bayer => LEVELS[BAYER_4X4[y][x] as usize / 4],
+        }
+    }
+}
+
+/// The footprint a brush stamps at each point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrushShape {
+    Square,
+    Round,
+}
+
+impl BrushShape {
+    pub const fn name(self) -> &'static str {
+        match self {
+            BrushShape::Square => "square",
+            BrushShape::Round => "round",
+        }
+    }
+
+    pub const fn glyph(self) -> char {
+        match self {
+            BrushShape::Square => '■',
+            BrushShape::Round => '●',
+        }
+    }
+
+    pub const fn toggled(self) -> Self {
+        match self {
+            BrushShape::Square => BrushShape::Round,
+            BrushShape::Round => BrushShape::Square,
+        }
+    }
+}
+
+/// How the Line tool spaces out the cells of a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineStyle {
+    Solid,
+    Dashed,
+    Dotted,
+}
+
+impl LineStyle {
+    pub const fn name(self) -> &'static str {
+        match self {
+            LineStyle::Solid => "solid",
+            LineStyle::Dashed => "dashed",
+            LineStyle::Dotted => "dotted",
+        }
+    }
+
+    pub const fn next(self) -> Self {
+        match self {
+            LineStyle::Solid => LineStyle::Dashed,
+            LineStyle::Dashed => LineStyle::Dotted,
+            LineStyle::Dotted => LineStyle::Solid,
         }
     }
+
+    /// Whether the cell at `index` along a line is drawn: dashes are 2 on, 1 off; dots 1 on, 1 off.
+    pub const fn keeps(self, index: usize) -> bool {
+        match self {
+            LineStyle::Solid => true,
+            LineStyle::Dashed => index % 3 != 2,
+            LineStyle::Dotted => index.is_multiple_of(2),
+        }
+    }
+
+    /// Drops the points of a line that this style leaves blank.
+    pub fn apply(self, points: Vec<Point>) -> Vec<Point> {
+        if self == LineStyle::Solid {
+            return points;
+        }
+        points
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, _)| self.keeps(i))
+            .map(|(_, p)| p)
+            .collect()
+    }
 }
 
-pub fn brush_points(center: Point, size: u8) -> Vec<Point> {
+/// Which axes strokes are mirrored across while drawing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symmetry {
+    Off,
+    Vertical,
+    Horizontal,
+    Quad,
+}
+
+impl Symmetry {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Symmetry::Off => "off",
+            Symmetry::Vertical => "vertical axis",
+            Symmetry::Horizontal => "horizontal axis",
+            Symmetry::Quad => "both axes",
+        }
+    }
+
+    pub const fn next(self) -> Self {
+        match self {
+            Symmetry::Off => Symmetry::Vertical,
+            Symmetry::Vertical => Symmetry::Horizontal,
+            Symmetry::Horizontal => Symmetry::Quad,
+            Symmetry::Quad => Symmetry::Off,
+        }
+    }
+
+    pub const fn mirrors_x(self) -> bool {
+        matches!(self, Symmetry::Vertical | Symmetry::Quad)
+    }
+
+    pub const fn mirrors_y(self) -> bool {
+        matches!(self, Symmetry::Horizontal | Symmetry::Quad)
+    }
+
+    /// `point` followed by its mirror images, always in the same order (x mirror, y mirror,
+    /// both) so images of different points can be paired up. `axis` holds twice the mirror
+    /// lines' positions, so an axis can run through a cell (even) or between two cells (odd).
+    pub fn images(self, point: Point, axis: Point) -> Vec<Point> {
+        let mut points = vec![point];
+        if self.mirrors_x() {
+            points.push(Point::new(axis.x - point.x, point.y));
+        }
+        if self.mirrors_y() {
+            points.push(Point::new(point.x, axis.y - point.y));
+        }
+        if self == Symmetry::Quad {
+            points.push(Point::new(axis.x - point.x, axis.y - point.y));
+        }
+        points
+    }
+
+    /// `point` and its distinct reflections.
+    pub fn reflect(self, point: Point, axis: Point) -> Vec<Point> {
+        let mut points = self.images(point, axis);
+        points.sort_by_key(|p| (p.y, p.x));
+        points.dedup();
+        points
+    }
+}
+
+/// Which neighbours a flood fill spreads to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    Four,
+    Eight,
+}
+
+impl Connectivity {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Connectivity::Four => "4-way",
+            Connectivity::Eight => "8-way",
+        }
+    }
+
+    const fn offsets(self) -> &'static [(i32, i32)] {
+        match self {
+            Connectivity::Four => &[(1, 0), (-1, 0), (0, 1), (0, -1)],
+            Connectivity::Eight => &[
+                (1, 0),
+                (-1, 0),
+                (0, 1),
+                (0, -1),
+                (1, 1),
+                (1, -1),
+                (-1, 1),
+                (-1, -1),
+            ],
+        }
+    }
+}
+
+/// Which parts of a cell must equal the seed cell for a flood fill to spread into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillMatch {
+    Exact,
+    CharOnly,
+    ColorOnly,
+}
+
+impl FillMatch {
+    pub const fn name(self) -> &'static str {
+        match self {
+            FillMatch::Exact => "exact",
+            FillMatch::CharOnly => "char only",
+            FillMatch::ColorOnly => "color only",
+        }
+    }
+
+    pub const fn next(self) -> Self {
+        match self {
+            FillMatch::Exact => FillMatch::CharOnly,
+            FillMatch::CharOnly => FillMatch::ColorOnly,
+            FillMatch::ColorOnly => FillMatch::Exact,
+        }
+    }
+
+    fn matches(self, target: PaintCell, cell: PaintCell) -> bool {
+        match self {
+            FillMatch::Exact => cell == target,
+            FillMatch::CharOnly => cell.ch == target.ch,
+            FillMatch::ColorOnly => cell.fg == target.fg && cell.bg == target.bg,
+        }
+    }
+}
+
+pub fn brush_points_iter(
+    center: Point,
+    size: u8,
+    shape: BrushShape,
+) -> impl Iterator<Item = Point> {
     let radius = size.saturating_sub(1) as i32;
-    let mut points = Vec::new();
-    for dy in -radius..=radius {
-        for dx in -radius..=radius {
+    // A little slack past r² keeps round dabs from looking pointy at the four extremes.
+    let limit = radius * radius + radius / 2;
+    (-radius..=radius).flat_map(move |dy| {
+        (-radius..=radius)
+            .filter(move |dx| shape == BrushShape::Square || dx * dx + dy * dy <= limit)
+            .map(move |dx| Point::new(center.x + dx, center.y + dy))
+    })
+}
+
+pub fn spray_points(center: Point, radius: u8, density: u8, rng: &mut impl RngCore) -> Vec<Point> {
+    let r = radius as i32;
+    let mut points = Vec::with_capacity(density as usize);
+    while points.len() < density as usize {
+        let dx = rng.gen_range(-r..=r);
+        let dy = rng.gen_range(-r..=r);
+        if dx * dx + dy * dy <= r * r {
             points.push(Point::new(center.x + dx, center.y + dy));
         }
     }
     points
 }
 
+/// Every cell of the rectangle spanned by `start` and `end`, colored by a linear gradient that
+/// runs from `from` at `start` to `to` at `end`. Cells are projected onto the drag direction, so
+/// horizontal, vertical and diagonal drags all work; positions past either end are clamped.
+pub fn gradient_points_with_colors(
+    start: Point,
+    end: Point,
+    from: PaintColor,
+    to: PaintColor,
+) -> Vec<(Point, PaintColor)> {
+    let from = from.to_rgb().unwrap_or(DEFAULT_FG_RGB);
+    let to = to.to_rgb().unwrap_or(DEFAULT_FG_RGB);
+    let (dx, dy) = ((end.x - start.x) as f32, (end.y - start.y) as f32);
+    let length_sq = dx * dx + dy * dy;
+    let lerp = |a: u8, b: u8, t: f32| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+
+    rectangle_points_iter(start, end, true)
+        .map(|p| {
+            let t = if length_sq == 0.0 {
+                0.0
+            } else {
+                (((p.x - start.x) as f32 * dx + (p.y - start.y) as f32 * dy) / length_sq)
+                    .clamp(0.0, 1.0)
+            };
+            let color = PaintColor::Rgb {
+                r: lerp(from.0, to.0, t),
+                g: lerp(from.1, to.1, t),
+                b: lerp(from.2, to.2, t),
+            };
+            (p, color)
+        })
+        .collect()
+}
+
+/// Box-drawing connection bits: which sides of a cell a line leaves through.
+pub const BOX_UP: u8 = 1;
+pub const BOX_RIGHT: u8 = 2;
+pub const BOX_DOWN: u8 = 4;
+pub const BOX_LEFT: u8 = 8;
+
+/// The box-drawing glyph connecting the given sides. A lone side draws a full line.
+pub const fn box_glyph(sides: u8) -> char {
+    match sides {
+        0b0011 => '└',
+        0b0110 => '┌',
+        0b0111 => '├',
+        0b1001 => '┘',
+        0b1011 => '┴',
+        0b1100 => '┐',
+        0b1101 => '┤',
+        0b1110 => '┬',
+        0b1111 => '┼',
+        s if s & (BOX_UP | BOX_DOWN) != 0 => '│',
+        _ => '─',
+    }
+}
+
+/// The sides a box-drawing glyph connects, or 0 for any other character.
+pub const fn box_sides(ch: char) -> u8 {
+    match ch {
+        '─' => BOX_LEFT | BOX_RIGHT,
+        '│' => BOX_UP | BOX_DOWN,
+        '└' => 0b0011,
+        '┌' => 0b0110,
+        '├' => 0b0111,
+        '┘' => 0b1001,
+        '┴' => 0b1011,
+        '┐' => 0b1100,
+        '┤' => 0b1101,
+        '┬' => 0b1110,
+        '┼' => 0b1111,
+        _ => 0,
+    }
+}
+
+/// Snaps a drag to the nearest horizontal or vertical segment.
+pub fn box_line_points(start: Point, end: Point) -> Vec<Point> {
+    let end = if (end.x - start.x).abs() >= (end.y - start.y).abs() {
+        Point::new(end.x, start.y)
+    } else {
+        Point::new(start.x, end.y)
+    };
+    bresenham_line(start, end)
+}
+
+/// The connection bit for the side of `from` that faces the adjacent point `to`.
+pub const fn box_side_towards(from: Point, to: Point) -> u8 {
+    match (to.x - from.x, to.y - from.y) {
+        (0, -1) => BOX_UP,
+        (1, 0) => BOX_RIGHT,
+        (0, 1) => BOX_DOWN,
+        (-1, 0) => BOX_LEFT,
+        _ => 0,
+    }
+}
+
+/// The step from `from` towards `tip`, snapped to one of eight directions. Shallow angles
+/// count as straight, so a slightly tilted drag still gets a `>` rather than a diagonal head.
+fn arrow_direction(tip: Point, from: Point) -> Option<(i32, i32)> {
+    let (dx, dy) = (tip.x - from.x, tip.y - from.y);
+    if dx == 0 && dy == 0 {
+        return None;
+    }
+    if dx.abs() > 2 * dy.abs() {
+        Some((dx.signum(), 0))
+    } else if dy.abs() > 2 * dx.abs() {
+        Some((0, dy.signum()))
+    } else {
+        Some((dx.signum(), dy.signum()))
+    }
+}
+
+/// The cells of an arrowhead at `tip` for a line coming from `from`, each with its glyph: the
+/// tip itself, then two barbs angled back along the line.
+pub fn arrowhead_cells(tip: Point, from: Point) -> Vec<(Point, char)> {
+    let Some((ux, uy)) = arrow_direction(tip, from) else {
+        return Vec::new();
+    };
+    let tip_glyph = match (ux, uy) {
+        (1, 0) => '>',
+        (-1, 0) => '<',
+        (0, -1) => '^',
+        (0, 1) => 'v',
+        (1, -1) => '↗',
+        (1, 1) => '↘',
+        (-1, 1) => '↙',
+        _ => '↖',
+    };
+    let barb_glyph = |bx: i32, by: i32| match (bx, by) {
+        (0, _) => '|',
+        (_, 0) => '-',
+        _ if bx == by => '\\',
+        _ => '/',
+    };
+
+    // The barbs are the backwards direction turned 45 degrees either way.
+    let (bx, by) = (-ux, -uy);
+    let barbs = [
+        ((bx - by).signum(), (bx + by).signum()),
+        ((bx + by).signum(), (by - bx).signum()),
+    ];
+
+    let mut cells = vec![(tip, tip_glyph)];
+    for (dx, dy) in barbs {
+        cells.push((Point::new(tip.x + dx, tip.y + dy), barb_glyph(dx, dy)));
+    }
+    cells
+}
+
+/// Where `arrowhead_cells` draws, without the glyphs.
+pub fn arrowhead_points(tip: Point, from: Point) -> Vec<Point> {
+    arrowhead_cells(tip, from)
+        .into_iter()
+        .map(|(point, _)| point)
+        .collect()
+}
+
+/// Shading glyphs from faint to solid, for drawing partial cell coverage.
+pub const SHADES: [char; 4] = ['░', '▒', '▓', '█'];
+
+/// A line as cells with how much of each the ideal line covers (0.0 to 1.0). Along the major
+/// axis every step lands between two cells, which share the coverage by their distance from
+/// the line (the idea behind Wu's antialiasing).
+pub fn shaded_line_cells(start: Point, end: Point) -> Vec<(Point, f32)> {
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let steps = dx.abs().max(dy.abs());
+    if steps == 0 {
+        return vec![(start, 1.0)];
+    }
+
+    let steep = dy.abs() > dx.abs();
+    let mut cells = Vec::with_capacity(steps as usize * 2 + 2);
+    for i in 0..=steps {
+        let t = i as f32 / steps as f32;
+        // `major` advances one cell per step; `minor` is where the ideal line crosses it.
+        let (major, minor) = if steep {
+            (start.y + i * dy.signum(), start.x as f32 + dx as f32 * t)
+        } else {
+            (start.x + i * dx.signum(), start.y as f32 + dy as f32 * t)
+        };
+        let base = minor.floor();
+        let frac = minor - base;
+        let cell = |offset: i32| {
+            let minor = base as i32 + offset;
+            if steep {
+                Point::new(minor, major)
+            } else {
+                Point::new(major, minor)
+            }
+        };
+        cells.push((cell(0), 1.0 - frac));
+        if frac > 0.0 {
+            cells.push((cell(1), frac));
+        }
+    }
+    cells
+}
+
+/// The shading glyph for a coverage value, or `None` when the cell is barely touched.
+pub fn coverage_glyph(coverage: f32) -> Option<char> {
+    let level = (coverage * SHADES.len() as f32).round() as usize;
+    level
+        .checked_sub(1)
+        .map(|i| SHADES[i.min(SHADES.len() - 1)])
+}
+
+/// The segments joining consecutive vertices, plus the closing segment back to the first one
+/// when `closed`. Shared corners appear once.
+pub fn polyline_points(vertices: &[Point], closed: bool) -> Vec<Point> {
+    let mut points = match vertices {
+        [] => return Vec::new(),
+        [only] => return vec![*only],
+        _ => Vec::new(),
+    };
+    let closing = closed.then(|| (vertices[vertices.len() - 1], vertices[0]));
+    let segments = vertices
+        .windows(2)
+        .map(|pair| (pair[0], pair[1]))
+        .chain(closing);
+    for (from, to) in segments {
+        let line = bresenham_line(from, to);
+        // Each segment starts where the previous one ended.
+        let skip = usize::from(!points.is_empty());
+        points.extend(line.into_iter().skip(skip));
+    }
+    points
+}
+
 pub fn bresenham_line(start: Point, end: Point) -> Vec<Point> {
     let mut points = Vec::new();
 
@@ -112,32 +687,30 @@ pub fn bresenham_line(start: Point, end: Point) -> Vec<Point> {
 }
 
 pub fn rectangle_points(start: Point, end: Point, filled: bool) -> Vec<Point> {
+    rectangle_points_iter(start, end, filled).collect()
+}
+
+pub fn rectangle_points_iter(
+    start: Point,
+    end: Point,
+    filled: bool,
+) -> impl Iterator<Item = Point> {
     let min_x = start.x.min(end.x);
     let max_x = start.x.max(end.x);
     let min_y = start.y.min(end.y);
     let max_y = start.y.max(end.y);
 
-    let mut points = Vec::new();
-
-    if filled {
-        for y in min_y..=max_y {
-            for x in min_x..=max_x {
-                points.push(Point::new(x, y));
-            }
-        }
-        return points;
-    }
-
-    for x in min_x..=max_x {
-        points.push(Point::new(x, min_y));
-        points.push(Point::new(x, max_y));
-    }
-    for y in min_y..=max_y {
-        points.push(Point::new(min_x, y));
-        points.push(Point::new(max_x, y));
-    }
-
-    dedup_points(points)
+    // Outline rows between the top and bottom edges only visit the two side columns,
+    // so every point is yielded exactly once without a dedup pass.
+    (min_y..=max_y).flat_map(move |y| {
+        let full_row = filled || y == min_y || y == max_y;
+        let step = if full_row {
+            1
+        } else {
+            (max_x - min_x).max(1) as usize
+        };
+        (min_x..=max_x).step_by(step).map(move |x| Point::new(x, y))
+    })
 }
 
 pub fn ellipse_points(start: Point, end: Point) -> Vec<Point> {
@@ -218,51 +791,190 @@ pub fn ellipse_points(start: Point, end: Point) -> Vec<Point> {
     dedup_points(points)
 }
 
+pub fn filled_ellipse_points(start: Point, end: Point) -> Vec<Point> {
+    // Scan each row between the outline's leftmost and rightmost cell, so the filled
+    // shape covers exactly the outline drawn for the same drag (degenerate lines included).
+    let mut spans: BTreeMap<i32, (i32, i32)> = BTreeMap::new();
+    for p in ellipse_points(start, end) {
+        let span = spans.entry(p.y).or_insert((p.x, p.x));
+        span.0 = span.0.min(p.x);
+        span.1 = span.1.max(p.x);
+    }
+
+    spans
+        .into_iter()
+        .flat_map(|(y, (left, right))| (left..=right).map(move |x| Point::new(x, y)))
+        .collect()
+}
+
+/// Moves `end` so the box from `start` to it looks square on screen: the shorter side grows to
+/// match the longer one. `aspect` is how many columns are as wide as one row is tall (2 for text
+/// cells, 1 for half-block pixels).
+pub fn square_end(start: Point, end: Point, aspect: i32) -> Point {
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let (width, height) = (dx.abs() + 1, dy.abs() + 1);
+    let (width, height) = if width >= height * aspect {
+        (width, (width + aspect / 2) / aspect)
+    } else {
+        (height * aspect, height)
+    };
+    let direction = |d: i32| if d < 0 { -1 } else { 1 };
+    Point::new(
+        start.x + direction(dx) * (width - 1),
+        start.y + direction(dy) * (height - 1),
+    )
+}
+
+/// A rectangle with quarter-ellipse corners of the given radius. The radius is clamped so the
+/// corners never overlap, so small rectangles degrade gracefully to plain ones.
+pub fn rounded_rectangle_points(start: Point, end: Point, radius: u8, filled: bool) -> Vec<Point> {
+    let min_x = start.x.min(end.x);
+    let max_x = start.x.max(end.x);
+    let min_y = start.y.min(end.y);
+    let max_y = start.y.max(end.y);
+    let smaller_side = (max_x - min_x).min(max_y - min_y) + 1;
+    let r = (radius as i32).min((smaller_side - 1) / 2);
+    if r == 0 {
+        return rectangle_points(start, end, filled);
+    }
+
+    // Corner centers; each corner is one quadrant of a circle around its center.
+    let (left, right) = (min_x + r, max_x - r);
+    let (top, bottom) = (min_y + r, max_y - r);
+    let mut outline: Vec<Point> = ellipse_points(Point::new(0, 0), Point::new(2 * r, 2 * r))
+        .into_iter()
+        .flat_map(|p| {
+            let (dx, dy) = (p.x - r, p.y - r);
+            [
+                (dx <= 0 && dy <= 0).then(|| Point::new(left + dx, top + dy)),
+                (dx >= 0 && dy <= 0).then(|| Point::new(right + dx, top + dy)),
+                (dx <= 0 && dy >= 0).then(|| Point::new(left + dx, bottom + dy)),
+                (dx >= 0 && dy >= 0).then(|| Point::new(right + dx, bottom + dy)),
+            ]
+        })
+        .flatten()
+        .collect();
+    outline.extend((left..=right).flat_map(|x| [Point::new(x, min_y), Point::new(x, max_y)]));
+    outline.extend((top..=bottom).flat_map(|y| [Point::new(min_x, y), Point::new(max_x, y)]));
+
+    if !filled {
+        outline.sort_by_key(|p| (p.y, p.x));
+        outline.dedup();
+        return outline;
+    }
+
+    let mut spans: BTreeMap<i32, (i32, i32)> = BTreeMap::new();
+    for p in outline {
+        let span = spans.entry(p.y).or_insert((p.x, p.x));
+        span.0 = span.0.min(p.x);
+        span.1 = span.1.max(p.x);
+    }
+    spans
+        .into_iter()
+        .flat_map(|(y, (left, right))| (left..=right).map(move |x| Point::new(x, y)))
+        .collect()
+}
+
+/// Every cell on the canvas that matches the one at `target`, connected or not.
+pub fn matching_points(canvas: &Canvas, target: Point, matching: FillMatch) -> Vec<Point> {
+    let Some(target) = canvas.get_i32(target.x, target.y) else {
+        return Vec::new();
+    };
+    (0..canvas.height())
+        .flat_map(|y| (0..canvas.width()).map(move |x| (x, y)))
+        .filter(|&(x, y)| matching.matches(target, canvas.get(x, y)))
+        .map(|(x, y)| Point::new(x as i32, y as i32))
+        .collect()
+}
+
 pub fn flood_fill_points(
     canvas: &Canvas,
-    start: Point,
-    target: PaintCell,
-    replacement: PaintCell,
+    seeds: &[Point],
+    connectivity: Connectivity,
+    matching: FillMatch,
+    paint: impl Fn(PaintCell) -> PaintCell,
 ) -> Vec<Point> {
-    if target == replacement || !canvas.in_bounds_i32(start.x, start.y) {
-        return Vec::new();
-    }
+    flood_fill_iter(canvas, seeds, connectivity, matching, paint).collect()
+}
 
+/// For exact matching, seeds whose cell `paint` would leave unchanged are skipped; the other
+/// modes still spread because neighbours that only partly match the seed may change.
+pub fn flood_fill_iter<'a>(
+    canvas: &'a Canvas,
+    seeds: &[Point],
+    connectivity: Connectivity,
+    matching: FillMatch,
+    paint: impl Fn(PaintCell) -> PaintCell,
+) -> FloodFill<'a> {
     let width = canvas.width() as usize;
     let height = canvas.height() as usize;
-    let mut visited = vec![false; width * height];
     let mut queue = VecDeque::new();
-    let mut out = Vec::new();
-
-    queue.push_back(start);
 
-    while let Some(p) = queue.pop_front() {
-        if !canvas.in_bounds_i32(p.x, p.y) {
+    // Each seed floods the region matching its own cell; regions are merged through `visited`.
+    for &seed in seeds {
+        let Some(target) = canvas.get_i32(seed.x, seed.y) else {
             continue;
+        };
+        if matching != FillMatch::Exact || paint(target) != target {
+            queue.push_back((seed, target));
         }
+    }
 
-        let x = p.x as usize;
-        let y = p.y as usize;
-        let idx = y * width + x;
-        if visited[idx] {
-            continue;
-        }
-        visited[idx] = true;
+    FloodFill {
+        canvas,
+        connectivity,
+        matching,
+        visited: vec![false; width * height],
+        queue,
+    }
+}
 
-        let current = canvas.get(x as u16, y as u16);
-        if current != target {
-            continue;
-        }
+pub struct FloodFill<'a> {
+    canvas: &'a Canvas,
+    connectivity: Connectivity,
+    matching: FillMatch,
+    visited: Vec<bool>,
+    queue: VecDeque<(Point, PaintCell)>,
+}
 
-        out.push(Point::new(p.x, p.y));
+impl Iterator for FloodFill<'_> {
+    type Item = Point;
 
-        queue.push_back(Point::new(p.x + 1, p.y));
-        queue.push_back(Point::new(p.x - 1, p.y));
-        queue.push_back(Point::new(p.x, p.y + 1));
-        queue.push_back(Point::new(p.x, p.y - 1));
-    }
+    fn next(&mut self) -> Option<Point> {
+        let width = self.canvas.width() as usize;
 
-    out
+        while let Some((p, target)) = self.queue.pop_front() {
+            if !self.canvas.in_bounds_i32(p.x, p.y) {
+                continue;
+            }
+
+            let x = p.x as usize;
+            let y = p.y as usize;
+            let idx = y * width + x;
+            if self.visited[idx] {
+                continue;
+            }
+            self.visited[idx] = true;
+
+            // Membership is always judged against the original seed cell, never the
+            // replacement, so painted cells can't pull further cells into the region.
+            let current = self.canvas.get(x as u16, y as u16);
+            if !self.matching.matches(target, current) {
+                continue;
+            }
+
+            // Cells are marked visited before expanding, so each one expands at most once
+            // and even an 8-way fill over a uniform canvas terminates.
+            for &(dx, dy) in self.connectivity.offsets() {
+                self.queue
+                    .push_back((Point::new(p.x + dx, p.y + dy), target));
+            }
+
+            return Some(p);
+        }
+
+        None
+    }
 }
 
 fn plot_ellipse_points(points: &mut Vec<Point>, cx: i64, cy: i64, x: i64, y: i64) {
@@ -282,3 +994,121 @@ fn dedup_points(points: Vec<Point>) -> Vec<Point> {
     }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut points: Vec<Point>) -> Vec<Point> {
+        points.sort_by_key(|p| (p.y, p.x));
+        points
+    }
+
+    fn assert_no_duplicates(points: &[Point]) {
+        assert_eq!(
+            dedup_points(points.to_vec()).len(),
+            points.len(),
+            "{points:?}"
+        );
+    }
+
+    #[test]
+    fn rounded_rectangle_too_small_for_corners_is_a_rectangle() {
+        for (start, end) in [
+            (Point::new(3, 3), Point::new(3, 3)),
+            (Point::new(0, 0), Point::new(1, 5)),
+            (Point::new(4, 2), Point::new(0, 3)),
+        ] {
+            for filled in [false, true] {
+                let rounded = rounded_rectangle_points(start, end, 4, filled);
+                assert_no_duplicates(&rounded);
+                assert_eq!(
+                    sorted(rounded),
+                    sorted(rectangle_points(start, end, filled))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rounded_rectangle_radius_clamps_to_half_the_side() {
+        let (start, end) = (Point::new(0, 0), Point::new(2, 2));
+        let outline = rounded_rectangle_points(start, end, 4, false);
+        assert_no_duplicates(&outline);
+        let diamond = vec![
+            Point::new(1, 0),
+            Point::new(0, 1),
+            Point::new(2, 1),
+            Point::new(1, 2),
+        ];
+        assert_eq!(sorted(outline), diamond);
+
+        let filled = rounded_rectangle_points(start, end, 4, true);
+        assert_no_duplicates(&filled);
+        assert_eq!(filled.len(), 5);
+        assert!(filled.contains(&Point::new(1, 1)));
+    }
+
+    /// A 5x3 blank canvas split in two by a wall of `#` down column 2.
+    fn walled_canvas() -> Canvas {
+        let mut canvas = Canvas::new(5, 3);
+        for y in 0..3 {
+            canvas.set(2, y, PaintCell::new('#', PaintColor::Default));
+        }
+        canvas
+    }
+
+    fn fill(canvas: &Canvas, seeds: &[Point]) -> Vec<Point> {
+        flood_fill_points(canvas, seeds, Connectivity::Four, FillMatch::Exact, |_| {
+            PaintCell::new('o', PaintColor::Red)
+        })
+    }
+
+    #[test]
+    fn fill_from_seeds_in_separate_regions_floods_both() {
+        let canvas = walled_canvas();
+        let points = fill(&canvas, &[Point::new(0, 0), Point::new(4, 2)]);
+        assert_no_duplicates(&points);
+        assert_eq!(points.len(), 12);
+        assert!(points.iter().all(|p| p.x != 2));
+    }
+
+    #[test]
+    fn fill_from_seeds_in_one_region_visits_each_cell_once() {
+        let canvas = walled_canvas();
+        let seeds = [Point::new(0, 0), Point::new(1, 2), Point::new(0, 0)];
+        let points = fill(&canvas, &seeds);
+        assert_no_duplicates(&points);
+        assert_eq!(points.len(), 6);
+        assert!(points.iter().all(|p| p.x < 2));
+    }
+
+    /// Rough benchmark: `cargo test --release -- --ignored --nocapture filled_rectangle`.
+    #[test]
+    #[ignore]
+    fn filled_rectangle_iter_skips_the_allocation() {
+        use std::{hint::black_box, time::Instant};
+
+        let (start, end) = (Point::new(0, 0), Point::new(1999, 1999));
+        let rounds = 20;
+
+        let begin = Instant::now();
+        for _ in 0..rounds {
+            let points = rectangle_points(black_box(start), black_box(end), true);
+            black_box(points.iter().map(|p| p.x as i64).sum::<i64>());
+        }
+        let collected = begin.elapsed() / rounds;
+
+        let begin = Instant::now();
+        for _ in 0..rounds {
+            let points = rectangle_points_iter(black_box(start), black_box(end), true);
+            black_box(points.map(|p| p.x as i64).sum::<i64>());
+        }
+        let streamed = begin.elapsed() / rounds;
+
+        println!(
+            "2000x2000 filled rectangle: Vec {collected:?} ({} MB allocated), iterator {streamed:?}",
+            2000 * 2000 * std::mem::size_of::<Point>() / 1_000_000
+        );
+    }
+}
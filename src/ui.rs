@@ -1,20 +1,42 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashSet, VecDeque},
+    ops::Range,
+    time::Duration,
+};
 
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Modifier, Style},
+    style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Clear, Paragraph},
+    widgets::{Block, Borders, Clear, Paragraph, Wrap},
     Frame,
 };
 
 use crate::{
-    canvas::{Canvas, PaintCell, PaintColor},
-    tools::{Point, Tool},
+    canvas::{Canvas, CanvasStats, CellAttrs, Layer, PaintCell, PaintColor, FULL_OPACITY},
+    keymap::{Action, KeyMap},
+    tools::{rectangle_points, BrushShape, Point, Symmetry, Tool, SHADES},
 };
 
 pub const TOOLBAR_HEIGHT: u16 = 4;
+pub const NARROW_TOOLBAR_HEIGHT: u16 = 5;
+const BRUSH_AREA_WIDTH: u16 = 30;
+/// The color row is at least this wide and grows to fit the palette.
+const MIN_COLOR_AREA_WIDTH: u16 = 34;
+const MIN_INLINE_STATUS_WIDTH: u16 = 60;
+/// The minimap's largest inner size; it keeps the canvas' proportions within it.
+const MINIMAP_MAX_WIDTH: u16 = 24;
+const MINIMAP_MAX_HEIGHT: u16 = 8;
+/// Shortcuts the status line lists while there is room, with their keys from the key map.
+const STATUS_HINTS: &[(Action, &str)] = &[
+    (Action::Quit, "quit"),
+    (Action::Undo, "undo"),
+    (Action::Redo, "redo"),
+    (Action::Save, "save"),
+    (Action::Load, "open"),
+];
 pub const BRUSH_CHOICES: [char; 7] = ['#', '@', '.', '*', '+', '%', ' '];
+pub const BLANK_GLYPHS: [char; 3] = [' ', '·', '.'];
 
 #[derive(Debug, Clone, Copy)]
 pub enum ToolbarAction {
@@ -68,7 +90,31 @@ impl Default for UiState {
 pub struct PreviewStyle {
     pub ch: char,
     pub fg: PaintColor,
+    pub bg: Option<PaintColor>,
+    pub literal_space: bool,
     pub erase: bool,
+    pub background: bool,
+}
+
+/// The message log overlay: timestamped status messages, oldest first.
+pub struct MessageLogView<'a> {
+    pub entries: &'a VecDeque<(Duration, String)>,
+    pub scroll: u16,
+}
+
+/// The stats overlay, captured when it was opened.
+pub struct StatsView {
+    pub canvas: CanvasStats,
+    pub undo_len: usize,
+    pub redo_len: usize,
+    pub history_bytes: usize,
+}
+
+/// A pick-one overlay such as the recent-files menu.
+pub struct ListView {
+    pub title: &'static str,
+    pub items: Vec<String>,
+    pub selected: usize,
 }
 
 pub struct PromptView<'a> {
@@ -78,27 +124,90 @@ pub struct PromptView<'a> {
 
 pub struct RenderContext<'a> {
     pub canvas: &'a Canvas,
+    /// Shown dimmed in blank canvas cells, for tracing.
+    pub reference: Option<&'a Canvas>,
     pub current_tool: Tool,
     pub brush_char: char,
+    /// Size of the clipboard block the Pencil stamps while the pattern brush is on.
+    pub pattern: Option<(u16, u16)>,
     pub brush_size: u8,
+    pub brush_shape: BrushShape,
+    pub brush_spacing: u8,
     pub color: PaintColor,
+    /// The color row: the user palette, or the built-in one.
+    pub palette: &'a [PaintColor],
+    /// The color right-drags paint with, swapped with `color` by `x`.
+    pub secondary_color: PaintColor,
+    /// The background foreground strokes paint under their glyph.
+    pub bg: Option<PaintColor>,
+    pub gradient_end: PaintColor,
+    /// Attributes new cells get.
+    pub attrs: CellAttrs,
+    /// The active layer's number (counting from 1), how many there are and the active layer's
+    /// settings, while there is more than one or the only one is hidden or see-through.
+    pub layer: Option<(usize, usize, &'a Layer)>,
+    pub rainbow: bool,
+    pub background_mode: bool,
     pub filled_shapes: bool,
     pub hover: Option<Point>,
+    pub half_block: bool,
+    /// Each canvas cell is drawn as a `zoom`x`zoom` block.
+    pub zoom: u16,
+    /// Show the minimap while the canvas doesn't fit the view.
+    pub minimap: bool,
+    /// Show the corner handle that resizes a fixed-size canvas.
+    pub resize_handle: bool,
+    pub blank_glyph: char,
     pub preview_points: &'a [Point],
     pub preview_style: Option<PreviewStyle>,
+    pub selection: Option<Rect>,
+    pub scroll: (u16, u16),
+    pub cursor: Option<Point>,
+    /// Grid line spacing when the alignment grid is shown.
+    pub grid: Option<u16>,
+    /// Highlights the hover's whole row and column.
+    pub crosshair: bool,
+    /// Active symmetry mode and twice the axes' position, while symmetry is on.
+    pub mirror: Option<(Symmetry, Point)>,
+    pub fill_warning: Option<usize>,
     pub status: &'a str,
     pub file_name: Option<&'a str>,
+    pub dirty: bool,
+    pub undo_len: usize,
+    pub redo_len: usize,
+    pub undo_limit: usize,
     pub prompt: Option<PromptView<'a>>,
+    pub list: Option<ListView>,
+    pub message_log: Option<MessageLogView<'a>>,
+    /// Scroll offset of the help overlay while it is open.
+    pub help: Option<u16>,
+    /// The live key map, which the help overlay lists keys from.
+    pub keymap: &'a KeyMap,
+    /// The highlighted swatch while the color picker is open.
+    pub color_picker: Option<u8>,
+    pub stats: Option<&'a StatsView>,
 }
 
-pub fn build_ui_state(area: Rect) -> UiState {
+/// Lays out the screen; `palette` is the color row after the default color.
+pub fn build_ui_state(area: Rect, palette: &[PaintColor]) -> UiState {
     let mut ui = UiState {
         terminal: area,
         ..UiState::default()
     };
 
+    // On narrow terminals the status gets its own toolbar row instead of sharing one.
+    let color_width = color_area_width(palette)
+        .min(area.width.saturating_sub(BRUSH_AREA_WIDTH + 10))
+        .max(MIN_COLOR_AREA_WIDTH);
+    let narrow = area.width < BRUSH_AREA_WIDTH + color_width + MIN_INLINE_STATUS_WIDTH;
+    let preferred_height = if narrow {
+        NARROW_TOOLBAR_HEIGHT
+    } else {
+        TOOLBAR_HEIGHT
+    };
+
     let toolbar_height = if area.height >= 4 {
-        TOOLBAR_HEIGHT.min(area.height.saturating_sub(1))
+        preferred_height.min(area.height.saturating_sub(1))
     } else {
         area.height.saturating_sub(1).max(1)
     };
@@ -113,7 +222,14 @@ pub fn build_ui_state(area: Rect) -> UiState {
     ui.toolbar_inner = inner_with_borders(ui.toolbar_outer);
     ui.canvas_inner = inner_with_borders(ui.canvas_outer);
 
-    let row_constraints = if ui.toolbar_inner.height >= 2 {
+    let status_row_separate = narrow && ui.toolbar_inner.height >= 3;
+    let row_constraints = if status_row_separate {
+        vec![
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ]
+    } else if ui.toolbar_inner.height >= 2 {
         vec![Constraint::Length(1), Constraint::Length(1)]
     } else {
         vec![Constraint::Length(1)]
@@ -130,20 +246,24 @@ pub fn build_ui_state(area: Rect) -> UiState {
     let bottom_chunks = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([
-            Constraint::Length(30),
-            Constraint::Length(34),
+            Constraint::Length(BRUSH_AREA_WIDTH),
+            Constraint::Length(color_width),
             Constraint::Min(10),
         ])
         .split(ui.bottom_row);
 
     ui.brush_area = bottom_chunks[0];
     ui.color_area = bottom_chunks[1];
-    ui.status_area = bottom_chunks[2];
+    ui.status_area = if status_row_separate {
+        rows[2]
+    } else {
+        bottom_chunks[2]
+    };
 
     ui.tool_hits = build_tool_hits(ui.tool_row);
     ui.fill_toggle_hit = build_fill_toggle_hit(ui.tool_row);
     ui.brush_hits = build_brush_hits(ui.brush_area);
-    ui.color_hits = build_color_hits(ui.color_area);
+    ui.color_hits = build_color_hits(ui.color_area, palette);
 
     ui
 }
@@ -176,15 +296,80 @@ pub fn toolbar_action_at(ui: &UiState, column: u16, row: u16) -> Option<ToolbarA
     None
 }
 
-pub fn mouse_to_canvas(ui: &UiState, column: u16, row: u16) -> Option<Point> {
+/// How many terminal columns and rows of canvas fit in the view when every one is drawn as a
+/// `zoom`x`zoom` block.
+pub fn canvas_view_size(ui: &UiState, zoom: u16) -> (u16, u16) {
+    let zoom = zoom.max(1);
+    (
+        (ui.canvas_inner.width / zoom).max(1),
+        (ui.canvas_inner.height / zoom).max(1),
+    )
+}
+
+/// The terminal position of the canvas's top-left drawn cell. A canvas smaller than the view is
+/// centered in it; a larger one starts at the view's top-left corner.
+pub fn canvas_origin(
+    ui: &UiState,
+    canvas_size: (u16, u16),
+    half_block: bool,
+    zoom: u16,
+) -> (u16, u16) {
+    let rows_per_cell = if half_block { 2 } else { 1 };
+    let zoom = zoom.max(1);
+    let inner = ui.canvas_inner;
+    let width = canvas_size.0.saturating_mul(zoom);
+    let height = canvas_size.1.div_ceil(rows_per_cell).saturating_mul(zoom);
+    (
+        inner.x + inner.width.saturating_sub(width) / 2,
+        inner.y + inner.height.saturating_sub(height) / 2,
+    )
+}
+
+/// Maps a terminal position to canvas coordinates, offset by the current scroll position.
+pub fn mouse_to_canvas(
+    ui: &UiState,
+    canvas_size: (u16, u16),
+    column: u16,
+    row: u16,
+    half_block: bool,
+    zoom: u16,
+    scroll: (u16, u16),
+) -> Option<Point> {
     if !rect_contains(ui.canvas_inner, column, row) {
         return None;
     }
 
-    Some(Point {
-        x: (column - ui.canvas_inner.x) as i32,
-        y: (row - ui.canvas_inner.y) as i32,
-    })
+    Some(mouse_to_canvas_unclamped(
+        ui,
+        canvas_size,
+        column,
+        row,
+        half_block,
+        zoom,
+        scroll,
+    ))
+}
+
+/// Like `mouse_to_canvas`, but positions outside the canvas area map to points beyond its edges.
+pub fn mouse_to_canvas_unclamped(
+    ui: &UiState,
+    canvas_size: (u16, u16),
+    column: u16,
+    row: u16,
+    half_block: bool,
+    zoom: u16,
+    scroll: (u16, u16),
+) -> Point {
+    let rows_per_cell = if half_block { 2 } else { 1 };
+    let (origin_x, origin_y) = canvas_origin(ui, canvas_size, half_block, zoom);
+    // Euclidean division keeps positions left of or above the view on the far side of zero.
+    let zoom = zoom.max(1) as i32;
+    let dx = (column as i32 - origin_x as i32).div_euclid(zoom);
+    let dy = (row as i32 - origin_y as i32).div_euclid(zoom);
+    Point {
+        x: dx + scroll.0 as i32,
+        y: dy * rows_per_cell + scroll.1 as i32,
+    }
 }
 
 pub fn render(f: &mut Frame, ui: &UiState, ctx: &RenderContext<'_>) {
@@ -197,10 +382,32 @@ pub fn render(f: &mut Frame, ui: &UiState, ctx: &RenderContext<'_>) {
     render_status(f, ui, ctx);
 
     render_canvas(f, ui, ctx);
+    render_resize_handle(f, ui, ctx);
+    render_minimap(f, ui, ctx);
 
     if let Some(prompt) = &ctx.prompt {
         render_prompt(f, ui.terminal, prompt);
     }
+
+    if let Some(list) = &ctx.list {
+        render_list(f, ui.terminal, list);
+    }
+
+    if let Some(log) = &ctx.message_log {
+        render_message_log(f, ui.terminal, log);
+    }
+
+    if let Some(scroll) = ctx.help {
+        render_help(f, ui.terminal, scroll, ctx.keymap);
+    }
+
+    if let Some(selected) = ctx.color_picker {
+        render_color_picker(f, ui.terminal, selected);
+    }
+
+    if let Some(stats) = ctx.stats {
+        render_stats(f, ui.terminal, stats);
+    }
 }
 
 fn render_tool_row(f: &mut Frame, ui: &UiState, ctx: &RenderContext<'_>) {
@@ -217,7 +424,9 @@ fn render_tool_row(f: &mut Frame, ui: &UiState, ctx: &RenderContext<'_>) {
     }
 
     let fill_label = fill_toggle_label(ctx.filled_shapes);
-    let fill_style = if ctx.filled_shapes {
+    let fill_style = if !ctx.current_tool.supports_fill() {
+        Style::default().fg(Color::DarkGray)
+    } else if ctx.filled_shapes {
         Style::default().add_modifier(Modifier::BOLD)
     } else {
         Style::default().add_modifier(Modifier::DIM)
@@ -244,7 +453,11 @@ fn render_brush_row(f: &mut Frame, ui: &UiState, ctx: &RenderContext<'_>) {
         spans.push(Span::raw(" "));
     }
 
-    spans.push(Span::raw(format!("Size:{}", ctx.brush_size)));
+    spans.push(Span::raw(format!(
+        "Size:{}{}",
+        ctx.brush_size,
+        ctx.brush_shape.glyph()
+    )));
 
     f.render_widget(Paragraph::new(Line::from(spans)), ui.brush_area);
 }
@@ -263,7 +476,7 @@ fn render_color_row(f: &mut Frame, ui: &UiState, ctx: &RenderContext<'_>) {
     spans.push(Span::styled(color_button_label_default(), default_style));
     spans.push(Span::raw(" "));
 
-    for (idx, color) in PaintColor::quick_palette().iter().copied().enumerate() {
+    for (idx, color) in ctx.palette.iter().copied().enumerate() {
         let mut style = Style::default().fg(color.to_ratatui());
         if color == ctx.color {
             style = style.add_modifier(Modifier::REVERSED | Modifier::BOLD);
@@ -281,32 +494,163 @@ fn render_status(f: &mut Frame, ui: &UiState, ctx: &RenderContext<'_>) {
         .map(|p| format!("{},{}", p.x, p.y))
         .unwrap_or_else(|| "-".to_string());
 
+    let dirty_marker = if ctx.dirty { "*" } else { "" };
     let file_part = ctx
         .file_name
-        .map(|f| format!("File:{} ", f))
-        .unwrap_or_default();
+        .map(|f| format!("File:{}{}", dirty_marker, f))
+        .unwrap_or_else(|| format!("{}[unsaved]", dirty_marker));
+
+    let attrs_part = if ctx.attrs.is_empty() {
+        String::new()
+    } else {
+        format!(" Attr:{}", ctx.attrs.letters())
+    };
+
+    let layer_part = match ctx.layer {
+        Some((active, count, layer)) => format!(
+            " Layer:{}/{}{}{}{}{}",
+            active,
+            count,
+            if layer.name.is_empty() { "" } else { " " },
+            layer.name,
+            if layer.opacity < FULL_OPACITY {
+                format!(" {}%", layer.opacity)
+            } else {
+                String::new()
+            },
+            if layer.visible { "" } else { "(hidden)" }
+        ),
+        None => String::new(),
+    };
+
+    let brush_part = match ctx.pattern {
+        Some((width, height)) if ctx.current_tool == Tool::Pencil => {
+            format!("[{}x{}]", width, height)
+        }
+        _ => format!("'{}'", printable_char(ctx.brush_char)),
+    };
+
+    let spacing_part = if ctx.brush_spacing > 1 {
+        format!(" Spacing:{}", ctx.brush_spacing)
+    } else {
+        String::new()
+    };
 
-    let status = format!(
-        "{}Tool:{} Brush:'{}' Size:{} Color:{} Pos:{} | q quit u/y or Ctrl/Cmd+Z undo, Ctrl+Y/Cmd+Shift+Z redo, Ctrl+S/Ctrl+O",
-        file_part,
+    // Essential state comes first so it survives truncation; the file name and
+    // shortcut hints are only added while they still fit.
+    let mut full = format!(
+        "Tool:{} Brush:{} Size:{}{}{} Color:{}{}{} 2nd:{}{}{} Pos:{}",
         ctx.current_tool.name(),
-        printable_char(ctx.brush_char),
+        brush_part,
         ctx.brush_size,
-        ctx.color.name(),
+        ctx.brush_shape.glyph(),
+        spacing_part,
+        if ctx.rainbow {
+            "Rainbow".to_string()
+        } else if ctx.current_tool == Tool::Gradient {
+            format!("{}->{}", ctx.color.name(), ctx.gradient_end.name())
+        } else {
+            ctx.color.name()
+        },
+        match ctx.bg {
+            Some(bg) if !ctx.background_mode => format!(" on {}", bg.name()),
+            _ => String::new(),
+        },
+        if ctx.background_mode { "(BG)" } else { "" },
+        ctx.secondary_color.name(),
+        attrs_part,
+        layer_part,
         position
     );
 
-    let full = if ctx.status.is_empty() {
-        status
-    } else {
-        format!("{} | {}", status, ctx.status)
-    };
+    if let Some(count) = ctx.fill_warning {
+        full = format!("{} | ! Fill covers {} cells", full, count);
+    }
+
+    if !ctx.status.is_empty() {
+        full = format!("{} | {}", full, ctx.status);
+    }
+
+    let undo_part = format!(
+        "Undo:{}/{} Redo:{}",
+        ctx.undo_len, ctx.undo_limit, ctx.redo_len
+    );
+
+    let cursor = tool_cursor_span(ctx);
+    let available = (ui.status_area.width as usize).saturating_sub(cursor.width() + 1);
+    let hints = status_hints(ctx.keymap);
+    for part in [file_part.as_str(), undo_part.as_str(), hints.as_str()] {
+        let candidate = format!("{} | {}", full, part);
+        if candidate.chars().count() <= available {
+            full = candidate;
+        }
+    }
+
+    let line = Line::from(vec![cursor, Span::raw(" "), Span::raw(full)]);
+    f.render_widget(Paragraph::new(line), ui.status_area);
+}
+
+/// The status line's shortcut hints under `keymap`, leaving out unbound actions.
+fn status_hints(keymap: &KeyMap) -> String {
+    STATUS_HINTS
+        .iter()
+        .filter_map(|(action, label)| {
+            let keys = keymap.keys_for(*action);
+            (!keys.is_empty()).then(|| format!("{} {}", keys.join(" or "), label))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-    f.render_widget(Paragraph::new(full), ui.status_area);
+fn tool_cursor_span(ctx: &RenderContext<'_>) -> Span<'static> {
+    let color_style = Style::default().fg(ctx.color.to_ratatui());
+    let (glyph, style) = match ctx.current_tool {
+        Tool::Pencil if ctx.half_block => ('█', color_style),
+        Tool::Pencil => (ctx.brush_char, color_style),
+        Tool::Eraser => ('×', Style::default()),
+        Tool::Line => ('╱', color_style),
+        Tool::Rectangle if ctx.filled_shapes => ('■', color_style),
+        Tool::Rectangle => ('□', color_style),
+        Tool::Circle if ctx.filled_shapes => ('●', color_style),
+        Tool::Circle => ('○', color_style),
+        Tool::Fill => ('▓', color_style),
+        Tool::Spray => ('∴', color_style),
+        Tool::Text => ('I', color_style),
+        Tool::Select => ('⬚', Style::default()),
+        Tool::BoxLine => ('┼', color_style),
+        Tool::Gradient => ('▤', color_style),
+        Tool::ReplaceAll => ('⁂', color_style),
+        Tool::Arrow => ('→', color_style),
+        Tool::Polygon => ('⬠', color_style),
+        Tool::Measure => ('↔', Style::default()),
+    };
+    Span::styled(
+        format!("[{}]", printable_char(glyph)),
+        style.add_modifier(Modifier::BOLD),
+    )
 }
 
 fn render_canvas(f: &mut Frame, ui: &UiState, ctx: &RenderContext<'_>) {
-    let canvas_title = " Canvas ";
+    let rows_per_cell = if ctx.half_block { 2 } else { 1 };
+    let fits_view = ctx.zoom <= 1
+        && ctx.canvas.width() == ui.canvas_inner.width
+        && ctx.canvas.height() == ui.canvas_inner.height * rows_per_cell;
+    let canvas_title = if fits_view {
+        " Canvas ".to_string()
+    } else {
+        format!(
+            " Canvas {}x{} @ {},{}{} ",
+            ctx.canvas.width(),
+            ctx.canvas.height(),
+            ctx.scroll.0,
+            ctx.scroll.1,
+            if ctx.zoom > 1 {
+                format!(" zoom {}x", ctx.zoom)
+            } else {
+                String::new()
+            }
+        )
+    };
     let canvas_block = Block::default().title(canvas_title).borders(Borders::ALL);
     f.render_widget(canvas_block, ui.canvas_outer);
 
@@ -321,28 +665,53 @@ fn render_canvas(f: &mut Frame, ui: &UiState, ctx: &RenderContext<'_>) {
         }
     }
 
-    let mut lines = Vec::with_capacity(ctx.canvas.height() as usize);
+    let selection_set: HashSet<(u16, u16)> = ctx
+        .selection
+        .map(|rect| {
+            let start = Point::new(rect.x as i32, rect.y as i32);
+            let end = Point::new(rect.right() as i32 - 1, rect.bottom() as i32 - 1);
+            rectangle_points(start, end, false)
+                .into_iter()
+                .map(|p| (p.x as u16, p.y as u16))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if ctx.half_block {
+        render_half_block_canvas(f, ui, ctx, &preview_set, &selection_set);
+        return;
+    }
+
+    let zoom = ctx.zoom.max(1);
+    let (view_width, view_height) = canvas_view_size(ui, zoom);
+    let (scroll_x, scroll_y) = ctx.scroll;
+    let visible_x = scroll_x..ctx.canvas.width().min(scroll_x + view_width);
+    let visible_y = scroll_y..ctx.canvas.height().min(scroll_y + view_height);
+    let mut lines = Vec::with_capacity(visible_y.len() * zoom as usize);
 
-    for y in 0..ctx.canvas.height() {
-        let mut spans = Vec::with_capacity(ctx.canvas.width() as usize);
+    for y in visible_y {
+        let mut spans = Vec::with_capacity(visible_x.len());
 
-        for x in 0..ctx.canvas.width() {
-            let mut cell = ctx.canvas.get(x, y);
-            let is_preview = preview_set.contains(&(x, y));
+        for x in visible_x.clone() {
+            let (cell, is_preview) = displayed_cell(ctx, &preview_set, x, y);
 
+            let mut style = cell.style();
+            let mut glyph = cell.ch;
             if is_preview {
-                if let Some(preview_style) = ctx.preview_style {
-                    if preview_style.erase {
-                        cell = PaintCell::blank();
-                    } else {
-                        cell = PaintCell::new(preview_style.ch, preview_style.fg);
-                    }
+                style = preview_modifier(ctx, style);
+            } else {
+                if let Some(traced) = reference_cell(ctx, x, y).filter(|_| cell.is_blank()) {
+                    glyph = traced.ch;
+                    style = traced.style().add_modifier(Modifier::DIM);
+                } else if cell.is_blank() && ctx.blank_glyph != ' ' {
+                    glyph = ctx.blank_glyph;
+                    style = style.add_modifier(Modifier::DIM);
                 }
+                style = guide_modifier(ctx, style, x, y..y + 1);
             }
 
-            let mut style = cell.style();
-            if is_preview {
-                style = style.add_modifier(Modifier::UNDERLINED);
+            if selection_set.contains(&(x, y)) {
+                style = style.add_modifier(Modifier::REVERSED);
             }
 
             if let Some(hover) = ctx.hover {
@@ -351,17 +720,364 @@ fn render_canvas(f: &mut Frame, ui: &UiState, ctx: &RenderContext<'_>) {
                 }
             }
 
-            spans.push(Span::styled(cell.ch.to_string(), style));
+            if ctx.cursor == Some(Point::new(x as i32, y as i32)) {
+                style = cursor_style(style);
+            }
+
+            spans.push(Span::styled(glyph.to_string().repeat(zoom.into()), style));
+        }
+
+        push_zoomed_line(&mut lines, spans, zoom);
+    }
+
+    f.render_widget(Paragraph::new(lines), canvas_area(ui, ctx));
+}
+
+fn render_half_block_canvas(
+    f: &mut Frame,
+    ui: &UiState,
+    ctx: &RenderContext<'_>,
+    preview_set: &HashSet<(u16, u16)>,
+    selection_set: &HashSet<(u16, u16)>,
+) {
+    let zoom = ctx.zoom.max(1);
+    let (view_width, view_height) = canvas_view_size(ui, zoom);
+    let (scroll_x, scroll_y) = ctx.scroll;
+    let visible_x = scroll_x..ctx.canvas.width().min(scroll_x + view_width);
+    let first_row = scroll_y / 2;
+    let rows = ctx.canvas.height().div_ceil(2).min(first_row + view_height);
+    let mut lines = Vec::with_capacity((rows.saturating_sub(first_row) * zoom) as usize);
+
+    for row in first_row..rows {
+        let top_y = row * 2;
+        let bottom_y = top_y + 1;
+        let mut spans = Vec::with_capacity(visible_x.len());
+
+        for x in visible_x.clone() {
+            let (top, top_preview) = displayed_cell(ctx, preview_set, x, top_y);
+            let (bottom, bottom_preview) = if bottom_y < ctx.canvas.height() {
+                displayed_cell(ctx, preview_set, x, bottom_y)
+            } else {
+                (PaintCell::blank(), false)
+            };
+
+            // The reference only shows through where both halves are blank.
+            let traced = (
+                reference_cell(ctx, x, top_y),
+                reference_cell(ctx, x, bottom_y),
+            );
+            let (glyph, mut style) = match (pixel_color(top), pixel_color(bottom)) {
+                (None, None) if top_preview || bottom_preview => (' ', Style::default()),
+                (None, None) if traced.0.is_some() || traced.1.is_some() => {
+                    let colors = (
+                        traced.0.and_then(pixel_color),
+                        traced.1.and_then(pixel_color),
+                    );
+                    let (glyph, style) = match colors {
+                        (Some(t), None) => ('▀', Style::default().fg(t)),
+                        (None, Some(b)) => ('▄', Style::default().fg(b)),
+                        (Some(t), Some(b)) if t == b => ('█', Style::default().fg(t)),
+                        (Some(t), Some(b)) => ('▀', Style::default().fg(t).bg(b)),
+                        (None, None) => (ctx.blank_glyph, Style::default()),
+                    };
+                    (glyph, style.add_modifier(Modifier::DIM))
+                }
+                (None, None) => (
+                    ctx.blank_glyph,
+                    Style::default().add_modifier(Modifier::DIM),
+                ),
+                (Some(t), None) => ('▀', Style::default().fg(t)),
+                (None, Some(b)) => ('▄', Style::default().fg(b)),
+                (Some(t), Some(b)) if t == b => ('█', Style::default().fg(t)),
+                (Some(t), Some(b)) => ('▀', Style::default().fg(t).bg(b)),
+            };
+
+            if top_preview || bottom_preview {
+                style = preview_modifier(ctx, style);
+            } else {
+                style = guide_modifier(ctx, style, x, top_y..bottom_y + 1);
+            }
+
+            if selection_set.contains(&(x, top_y)) || selection_set.contains(&(x, bottom_y)) {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+
+            if let Some(hover) = ctx.hover {
+                if hover.x == x as i32 && hover.y / 2 == row as i32 {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+            }
+
+            if ctx
+                .cursor
+                .is_some_and(|c| c.x == x as i32 && c.y / 2 == row as i32)
+            {
+                style = cursor_style(style);
+            }
+
+            spans.push(Span::styled(glyph.to_string().repeat(zoom.into()), style));
+        }
+
+        push_zoomed_line(&mut lines, spans, zoom);
+    }
+
+    f.render_widget(Paragraph::new(lines), canvas_area(ui, ctx));
+}
+
+/// The part of the canvas view the canvas is drawn into, starting at its centered origin.
+fn canvas_area(ui: &UiState, ctx: &RenderContext<'_>) -> Rect {
+    let size = (ctx.canvas.width(), ctx.canvas.height());
+    let (x, y) = canvas_origin(ui, size, ctx.half_block, ctx.zoom);
+    let inner = ui.canvas_inner;
+    Rect::new(x, y, inner.right() - x, inner.bottom() - y)
+}
+
+/// The minimap's bordered area in the bottom-right corner of the canvas and how many canvas
+/// cells each of its cells covers per side, or `None` when the canvas area is too small for it.
+pub fn minimap_layout(ui: &UiState, canvas_width: u16, canvas_height: u16) -> Option<(Rect, u16)> {
+    let scale = canvas_width
+        .div_ceil(MINIMAP_MAX_WIDTH)
+        .max(canvas_height.div_ceil(MINIMAP_MAX_HEIGHT))
+        .max(1);
+    let width = canvas_width.div_ceil(scale) + 2;
+    let height = canvas_height.div_ceil(scale) + 2;
+    let inner = ui.canvas_inner;
+    // Leave most of the view free for drawing.
+    if width * 2 > inner.width || height * 2 > inner.height {
+        return None;
+    }
+    let area = Rect::new(
+        inner.right() - width,
+        inner.bottom() - height,
+        width,
+        height,
+    );
+    Some((area, scale))
+}
+
+/// The canvas cell at the middle of the minimap cell under a terminal position, if the position
+/// is on the minimap; its border counts as the nearest cell inside.
+pub fn minimap_hit(
+    ui: &UiState,
+    canvas_width: u16,
+    canvas_height: u16,
+    column: u16,
+    row: u16,
+) -> Option<Point> {
+    let (area, scale) = minimap_layout(ui, canvas_width, canvas_height)?;
+    if !rect_contains(area, column, row) {
+        return None;
+    }
+    let inner = inner_with_borders(area);
+    let mx = column.clamp(inner.x, inner.right() - 1) - inner.x;
+    let my = row.clamp(inner.y, inner.bottom() - 1) - inner.y;
+    Some(Point::new(
+        (mx * scale + scale / 2).min(canvas_width - 1) as i32,
+        (my * scale + scale / 2).min(canvas_height - 1) as i32,
+    ))
+}
+
+/// Draws the whole canvas shrunk into the corner, shading each cell by how much of its block is
+/// painted, with the visible part highlighted.
+fn render_minimap(f: &mut Frame, ui: &UiState, ctx: &RenderContext<'_>) {
+    let rows_per_cell = if ctx.half_block { 2 } else { 1 };
+    let (view_width, view_rows) = canvas_view_size(ui, ctx.zoom);
+    let view = Rect::new(
+        ctx.scroll.0,
+        ctx.scroll.1,
+        view_width,
+        view_rows * rows_per_cell,
+    );
+    let (width, height) = (ctx.canvas.width(), ctx.canvas.height());
+    if !ctx.minimap || (width <= view.width && height <= view.height) {
+        return;
+    }
+    let Some((area, scale)) = minimap_layout(ui, width, height) else {
+        return;
+    };
+
+    f.render_widget(Clear, area);
+    f.render_widget(Block::default().title("Map").borders(Borders::ALL), area);
+
+    let inner = inner_with_borders(area);
+    let canvas_rect = Rect::new(0, 0, width, height);
+    let buf = f.buffer_mut();
+    for my in 0..inner.height {
+        for mx in 0..inner.width {
+            let block = Rect::new(mx * scale, my * scale, scale, scale).intersection(canvas_rect);
+            let mut painted = 0;
+            let mut color = None;
+            for y in block.top()..block.bottom() {
+                for x in block.left()..block.right() {
+                    let cell = ctx.canvas.get(x, y);
+                    if !cell.is_blank() {
+                        painted += 1;
+                        color = color.or(cell.bg.or(Some(cell.fg)));
+                    }
+                }
+            }
+
+            let total = block.area().max(1) as usize;
+            let glyph = match painted {
+                0 => ' ',
+                _ => SHADES[(painted * SHADES.len() / total).min(SHADES.len() - 1)],
+            };
+            let mut style = Style::default().fg(color.unwrap_or(PaintColor::Default).to_ratatui());
+            if block.intersects(view) {
+                style = style.bg(Color::Indexed(238));
+            }
+            buf.set_string(inner.x + mx, inner.y + my, glyph.to_string(), style);
         }
+    }
+}
 
-        lines.push(Line::from(spans));
+/// The screen cell of the canvas resize handle, just past the canvas's bottom-right corner; it may
+/// sit on the canvas border. `None` while that corner is scrolled out of view.
+pub fn resize_handle_pos(
+    ui: &UiState,
+    canvas_size: (u16, u16),
+    half_block: bool,
+    zoom: u16,
+    scroll: (u16, u16),
+) -> Option<(u16, u16)> {
+    let rows_per_cell = if half_block { 2 } else { 1 };
+    let zoom = zoom.max(1) as i32;
+    let columns = canvas_size.0 as i32 - scroll.0 as i32;
+    let rows = (canvas_size.1 as i32 - scroll.1 as i32 + rows_per_cell - 1) / rows_per_cell;
+    let (origin_x, origin_y) = canvas_origin(ui, canvas_size, half_block, zoom as u16);
+    let x = origin_x as i32 + columns * zoom;
+    let y = origin_y as i32 + rows * zoom;
+    let inner = ui.canvas_inner;
+    let visible = columns > 0
+        && rows > 0
+        && x <= inner.right() as i32
+        && y <= inner.bottom() as i32
+        && inner.width > 0
+        && inner.height > 0;
+    visible.then_some((x as u16, y as u16))
+}
+
+fn render_resize_handle(f: &mut Frame, ui: &UiState, ctx: &RenderContext<'_>) {
+    if !ctx.resize_handle {
+        return;
+    }
+    let size = (ctx.canvas.width(), ctx.canvas.height());
+    if let Some((x, y)) = resize_handle_pos(ui, size, ctx.half_block, ctx.zoom, ctx.scroll) {
+        let style = Style::default()
+            .fg(Color::Yellow)
+            .add_modifier(Modifier::BOLD);
+        f.buffer_mut().set_string(x, y, "◢", style);
     }
+}
 
-    f.render_widget(Paragraph::new(lines), ui.canvas_inner);
+/// Adds one row of the view, repeated so it is `zoom` terminal rows tall.
+fn push_zoomed_line<'a>(lines: &mut Vec<Line<'a>>, spans: Vec<Span<'a>>, zoom: u16) {
+    for _ in 1..zoom {
+        lines.push(Line::from(spans.clone()));
+    }
+    lines.push(Line::from(spans));
+}
+
+// The keyboard cursor stays visible on top of the hover highlight and any preview.
+fn cursor_style(style: Style) -> Style {
+    style
+        .bg(Color::Yellow)
+        .fg(Color::Black)
+        .remove_modifier(Modifier::REVERSED)
+        .add_modifier(Modifier::BOLD)
+}
+
+// Crosshair, mirror-axis and grid lines only tint cells that have no background of their own,
+// so painted colors stay readable. The crosshair wins where they cross, then the axes.
+fn guide_modifier(ctx: &RenderContext<'_>, style: Style, x: u16, rows: Range<u16>) -> Style {
+    if style.bg.is_some() {
+        return style;
+    }
+
+    let on_crosshair = ctx.crosshair
+        && ctx
+            .hover
+            .is_some_and(|hover| hover.x == x as i32 || rows.clone().any(|y| hover.y == y as i32));
+    let on_grid = ctx.grid.is_some_and(|spacing| {
+        x.is_multiple_of(spacing) || rows.clone().any(|y| y.is_multiple_of(spacing))
+    });
+    // An axis between two cells shades both of them.
+    let near_axis = |pos: u16, axis: i32| (pos as i32 * 2 - axis).abs() <= 1;
+    let on_mirror = ctx.mirror.is_some_and(|(symmetry, axis)| {
+        (symmetry.mirrors_x() && near_axis(x, axis.x))
+            || (symmetry.mirrors_y() && rows.clone().any(|y| near_axis(y, axis.y)))
+    });
+
+    if on_crosshair {
+        style.bg(Color::Indexed(238))
+    } else if on_mirror {
+        style.bg(Color::Indexed(235))
+    } else if on_grid {
+        style.bg(Color::Indexed(236))
+    } else {
+        style
+    }
+}
+
+fn preview_modifier(ctx: &RenderContext<'_>, style: Style) -> Style {
+    let style = style.add_modifier(Modifier::UNDERLINED);
+    if ctx.fill_warning.is_some() {
+        style.bg(Color::Red)
+    } else {
+        style
+    }
+}
+
+/// The reference drawing's cell at a canvas position, when there is one and it isn't blank.
+fn reference_cell(ctx: &RenderContext<'_>, x: u16, y: u16) -> Option<PaintCell> {
+    ctx.reference?
+        .get_i32(x as i32, y as i32)
+        .filter(|cell| !cell.is_blank())
+}
+
+fn displayed_cell(
+    ctx: &RenderContext<'_>,
+    preview_set: &HashSet<(u16, u16)>,
+    x: u16,
+    y: u16,
+) -> (PaintCell, bool) {
+    let mut cell = ctx.canvas.get(x, y);
+    let is_preview = preview_set.contains(&(x, y));
+
+    if is_preview {
+        if let Some(preview_style) = ctx.preview_style {
+            if preview_style.background {
+                cell = cell.with_bg((!preview_style.erase).then_some(preview_style.fg));
+            } else if preview_style.erase {
+                cell = PaintCell::blank();
+            } else {
+                cell = PaintCell::brush(
+                    preview_style.ch,
+                    preview_style.fg,
+                    preview_style.bg,
+                    preview_style.literal_space,
+                );
+            }
+        }
+    }
+
+    (cell, is_preview)
+}
+
+// In half-block mode every logical cell is one pixel: its background if it only
+// carries a bg, otherwise its foreground.
+fn pixel_color(cell: PaintCell) -> Option<Color> {
+    if cell.is_blank() {
+        None
+    } else if cell.ch == ' ' {
+        cell.bg.map(|bg| bg.to_ratatui())
+    } else {
+        Some(cell.fg.to_ratatui())
+    }
 }
 
 fn render_prompt(f: &mut Frame, area: Rect, prompt: &PromptView<'_>) {
-    let width = area.width.min(70).max(20);
+    let width = area.width.clamp(20, 70);
     let popup = centered_rect(width, 5, area);
 
     f.render_widget(Clear, popup);
@@ -378,6 +1094,627 @@ fn render_prompt(f: &mut Frame, area: Rect, prompt: &PromptView<'_>) {
     f.render_widget(Paragraph::new(help), inner);
 }
 
+/// What the key column of a help row shows.
+enum HelpKeys {
+    /// Whatever keys the key map currently gives these actions.
+    Actions(&'static [Action]),
+    /// Keys and mouse gestures the key map doesn't cover.
+    Fixed(&'static str),
+}
+
+use HelpKeys::{Actions, Fixed};
+
+/// Every binding shown by the help overlay, grouped by section. Rows name actions rather than
+/// keys, so the overlay follows `keybindings.toml`; update this and the README when actions
+/// change.
+const HELP: &[(&str, &[(HelpKeys, &str)])] = &[
+    (
+        "Tools",
+        &[
+            (Actions(&[Action::Pencil]), "Pencil"),
+            (Actions(&[Action::Eraser]), "Eraser"),
+            (Actions(&[Action::Line]), "Line"),
+            (
+                Actions(&[Action::Rectangle]),
+                "Rectangle (Shift+drag: square)",
+            ),
+            (
+                Actions(&[Action::Circle]),
+                "Circle / ellipse (Shift+drag: circle)",
+            ),
+            (Actions(&[Action::Fill]), "Fill (bucket)"),
+            (Actions(&[Action::Spray]), "Spray"),
+            (Actions(&[Action::Text]), "Text"),
+            (
+                Actions(&[Action::Select]),
+                "Select (drag inside the selection to move it)",
+            ),
+            (
+                Actions(&[Action::BoxLine]),
+                "Box line (joins into corners and junctions)",
+            ),
+            (Actions(&[Action::Gradient]), "Gradient (drag a rectangle)"),
+            (
+                Actions(&[Action::ReplaceAll]),
+                "Replace all cells matching the clicked one",
+            ),
+            (
+                Actions(&[Action::Arrow]),
+                "Arrow (head at the release point)",
+            ),
+            (
+                Actions(&[Action::Polygon]),
+                "Polygon (Enter closes, double-click ends)",
+            ),
+            (
+                Actions(&[Action::Measure]),
+                "Measure distance and size (draws nothing)",
+            ),
+            (
+                Actions(&[Action::NextTool, Action::PrevTool]),
+                "Next / previous tool",
+            ),
+        ],
+    ),
+    (
+        "Brush",
+        &[
+            (
+                Actions(&[Action::SizeDown, Action::SizeUp, Action::BrushSize]),
+                "Brush size down / up, or type it (the Eraser keeps its own)",
+            ),
+            (
+                Actions(&[Action::BrushShape]),
+                "Toggle square / round brush",
+            ),
+            (
+                Actions(&[Action::SpacingDown, Action::SpacingUp]),
+                "Brush spacing down / up",
+            ),
+            (
+                Actions(&[Action::PatternBrush]),
+                "Pattern brush: Pencil stamps the clipboard",
+            ),
+            (Actions(&[Action::NextBrushChar]), "Cycle brush character"),
+            (Actions(&[Action::BrushChar]), "Type any brush character"),
+            (Actions(&[Action::FilledShapes]), "Toggle filled shapes"),
+            (
+                Actions(&[Action::CornerRadius]),
+                "Cycle rectangle corner radius (0-4)",
+            ),
+            (
+                Actions(&[Action::FromCenter]),
+                "Toggle rect / ellipse from center (Alt+drag)",
+            ),
+            (
+                Actions(&[Action::ShadedLines]),
+                "Toggle shaded (smoothed) lines",
+            ),
+            (
+                Actions(&[Action::LineStyle]),
+                "Cycle line style (solid / dashed / dotted)",
+            ),
+            (Actions(&[Action::Rainbow]), "Toggle rainbow brush"),
+            (
+                Actions(&[Action::LiteralSpaces]),
+                "Toggle space brush: color blocks / literal spaces",
+            ),
+            (Actions(&[Action::HalfBlock]), "Toggle half-block mode"),
+            (
+                Actions(&[Action::Symmetry]),
+                "Cycle symmetry (off / vertical / horizontal / both axes)",
+            ),
+            (
+                Actions(&[Action::MirrorAxis]),
+                "Move the symmetry axes to the pointer, or back to center",
+            ),
+            (Actions(&[Action::BackgroundMode]), "Toggle background mode"),
+            (
+                Fixed("Alt+B / I / U / R"),
+                "Toggle bold / italic / underline / reversed",
+            ),
+        ],
+    ),
+    (
+        "Color",
+        &[
+            (Fixed("1..9"), "Palette color"),
+            (
+                Actions(&[Action::DefaultColor]),
+                "Default color, no background",
+            ),
+            (
+                Actions(&[Action::SwapColors]),
+                "Swap with the secondary color (right-drag paints it)",
+            ),
+            (
+                Actions(&[Action::PaletteAdd, Action::PaletteRemove]),
+                "Add / remove the current color in the palette",
+            ),
+            (Actions(&[Action::ColorPrompt]), "Exact color from hex"),
+            (
+                Actions(&[Action::ColorPicker]),
+                "Pick from the 256-color palette",
+            ),
+            (
+                Actions(&[Action::Lighten, Action::Darken]),
+                "Lighten / darken",
+            ),
+            (Actions(&[Action::GradientEnd]), "Gradient end color"),
+            (
+                Fixed("Right click"),
+                "Sample char, color and attributes from the canvas",
+            ),
+            (Fixed("Scroll"), "Cycle colors"),
+        ],
+    ),
+    (
+        "Fill",
+        &[
+            (
+                Actions(&[Action::FillConnectivity]),
+                "Toggle 4-way / 8-way fill",
+            ),
+            (Actions(&[Action::FillMatch]), "Cycle what fill matches"),
+            (Actions(&[Action::FillPattern]), "Cycle fill pattern"),
+            (
+                Actions(&[Action::MultiFill]),
+                "Multi-seed fill (Enter fills all seeds)",
+            ),
+            (
+                Actions(&[Action::FillWarnThreshold]),
+                "Fill size warning threshold",
+            ),
+        ],
+    ),
+    (
+        "Edit",
+        &[
+            (Actions(&[Action::Undo]), "Undo"),
+            (Actions(&[Action::Redo]), "Redo"),
+            (Actions(&[Action::History]), "Undo history"),
+            (
+                Actions(&[Action::Copy, Action::Cut, Action::Paste]),
+                "Copy / cut / paste",
+            ),
+            (
+                Fixed("Esc"),
+                "Cancel shape, move or fill seeds; clear selection",
+            ),
+            (
+                Actions(&[
+                    Action::CursorLeft,
+                    Action::CursorRight,
+                    Action::CursorUp,
+                    Action::CursorDown,
+                ]),
+                "Move the keyboard cursor, or nudge a selection one cell",
+            ),
+            (
+                Actions(&[Action::Press]),
+                "Press at the keyboard cursor (Enter works too)",
+            ),
+        ],
+    ),
+    (
+        "Canvas",
+        &[
+            (
+                Actions(&[Action::FlipHorizontal, Action::FlipVertical]),
+                "Flip the layer horizontally / vertically",
+            ),
+            (
+                Actions(&[Action::RotateRight, Action::RotateLeft]),
+                "Rotate clockwise / counter-clockwise",
+            ),
+            (Actions(&[Action::Trim]), "Trim blank margins"),
+            (Actions(&[Action::Clear]), "Clear the active layer"),
+            (
+                Actions(&[Action::NewLayer, Action::DeleteLayer]),
+                "Add / delete a layer",
+            ),
+            (
+                Actions(&[Action::LayerUp, Action::LayerDown]),
+                "Layer above / below",
+            ),
+            (
+                Actions(&[Action::ToggleLayer]),
+                "Hide / show the active layer",
+            ),
+            (
+                Actions(&[Action::FlattenLayers]),
+                "Flatten the visible layers into one",
+            ),
+            (
+                Actions(&[Action::MoveLayerUp, Action::MoveLayerDown]),
+                "Move the active layer up / down",
+            ),
+            (Actions(&[Action::RenameLayer]), "Rename the active layer"),
+            (
+                Actions(&[Action::LayerOpacityDown, Action::LayerOpacityUp]),
+                "Active layer opacity down / up (dithered)",
+            ),
+            (Actions(&[Action::CanvasSize]), "Set a fixed canvas size"),
+            (Fixed("Drag ◢"), "Resize a fixed-size canvas"),
+            (
+                Actions(&[Action::LuminanceBands]),
+                "Recolor by brightness bands",
+            ),
+            (
+                Actions(&[Action::Backdrop]),
+                "Backdrop: color the background of every empty cell",
+            ),
+            (
+                Actions(&[Action::ConfirmDestructive]),
+                "Toggle confirmation before whole-canvas actions",
+            ),
+        ],
+    ),
+    (
+        "View",
+        &[
+            (
+                Actions(&[
+                    Action::ScrollLeft,
+                    Action::ScrollRight,
+                    Action::ScrollUp,
+                    Action::ScrollDown,
+                ]),
+                "Scroll the canvas (middle drag works too)",
+            ),
+            (
+                Actions(&[Action::ZoomIn, Action::ZoomOut]),
+                "Zoom in / out (1x-4x)",
+            ),
+            (
+                Actions(&[Action::ResetView]),
+                "Reset the view: scroll to the top-left, zoom 1x",
+            ),
+            (
+                Actions(&[Action::Minimap]),
+                "Toggle the minimap (click it to jump)",
+            ),
+            (
+                Actions(&[Action::Grid, Action::GridSpacing]),
+                "Toggle grid / set its spacing",
+            ),
+            (Actions(&[Action::Crosshair]), "Toggle crosshair guides"),
+            (
+                Actions(&[Action::BlankGlyph]),
+                "Cycle how blank cells are shown",
+            ),
+            (Actions(&[Action::MessageLog]), "Message log"),
+            (
+                Actions(&[Action::Stats]),
+                "Canvas stats (size, colors, memory)",
+            ),
+            (Actions(&[Action::Help]), "This help"),
+        ],
+    ),
+    (
+        "Files",
+        &[
+            (
+                Actions(&[Action::Save]),
+                "Save (format follows the extension)",
+            ),
+            (
+                Actions(&[Action::ExportView]),
+                "Export just the visible part",
+            ),
+            (Actions(&[Action::Load]), "Load"),
+            (Actions(&[Action::Import]), "Import a file at the pointer"),
+            (
+                Actions(&[Action::Reference]),
+                "Trace over a reference drawing",
+            ),
+            (Actions(&[Action::RecentFiles]), "Recent files"),
+            (Actions(&[Action::ExportCellSize]), "Image export cell size"),
+            (
+                Actions(&[Action::SvgFill]),
+                "SVG color for default-colored text",
+            ),
+            (
+                Actions(&[Action::ImageColors]),
+                "PPM/PNG default foreground and background",
+            ),
+            (Actions(&[Action::AsciiHeader]), "Toggle ASCII size header"),
+            (Actions(&[Action::Quit]), "Quit"),
+            (
+                Fixed("keybindings.toml"),
+                "Change any of these keys (see README)",
+            ),
+        ],
+    ),
+];
+
+/// The key column of a help row under `keymap`: each action's keys joined by "or", actions
+/// joined by "/", and four arrow keys that share their modifiers shortened to `Arrows`.
+fn help_keys(keys: &HelpKeys, keymap: &KeyMap) -> String {
+    let actions = match keys {
+        Fixed(text) => return text.to_string(),
+        Actions(actions) => actions,
+    };
+    let per_action: Vec<Vec<String>> = actions.iter().map(|a| keymap.keys_for(*a)).collect();
+
+    if per_action.len() == 4 && per_action.iter().all(|keys| keys.len() == 1) {
+        let prefix = per_action[0][0].strip_suffix("Left");
+        let arrows = ["Right", "Up", "Down"];
+        if let Some(prefix) = prefix.filter(|prefix| {
+            arrows
+                .iter()
+                .zip(&per_action[1..])
+                .all(|(arrow, keys)| keys[0].strip_suffix(arrow) == Some(*prefix))
+        }) {
+            return format!("{prefix}Arrows");
+        }
+    }
+
+    per_action
+        .iter()
+        .map(|keys| match keys.as_slice() {
+            [] => "(unbound)".to_string(),
+            keys => keys.join(" or "),
+        })
+        .collect::<Vec<_>>()
+        .join(" / ")
+}
+
+/// Number of lines in the help overlay, for clamping its scroll offset.
+pub fn help_line_count() -> usize {
+    HELP.iter().map(|(_, rows)| rows.len() + 2).sum()
+}
+
+fn render_help(f: &mut Frame, area: Rect, scroll: u16, keymap: &KeyMap) {
+    f.render_widget(Clear, area);
+    f.render_widget(
+        Block::default()
+            .title(" Help - Up/Down/PgUp/PgDn to scroll, Esc or ? to close ")
+            .borders(Borders::ALL),
+        area,
+    );
+
+    let sections: Vec<(&str, Vec<(String, &str)>)> = HELP
+        .iter()
+        .map(|(section, rows)| {
+            let rows = rows
+                .iter()
+                .map(|(keys, action)| (help_keys(keys, keymap), *action))
+                .collect();
+            (*section, rows)
+        })
+        .collect();
+    let key_width = sections
+        .iter()
+        .flat_map(|(_, rows)| rows.iter())
+        .map(|(keys, _)| keys.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut lines = Vec::with_capacity(help_line_count());
+    for (section, rows) in sections {
+        lines.push(Line::from(Span::styled(
+            section,
+            Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+        )));
+        for (keys, action) in rows {
+            lines.push(Line::from(vec![
+                Span::styled(
+                    format!("  {keys:<key_width$}  "),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::raw(action),
+            ]));
+        }
+        lines.push(Line::default());
+    }
+
+    f.render_widget(
+        Paragraph::new(lines).scroll((scroll, 0)),
+        inner_with_borders(area),
+    );
+}
+
+fn render_message_log(f: &mut Frame, area: Rect, log: &MessageLogView<'_>) {
+    let popup = centered_rect(
+        area.width.saturating_sub(4),
+        area.height.saturating_sub(4),
+        area,
+    );
+
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Block::default()
+            .title(" Messages - Up/Down/PgUp/PgDn to scroll, Esc to close ")
+            .borders(Borders::ALL),
+        popup,
+    );
+
+    // Timestamps are time since startup; long messages wrap instead of being cut off.
+    let lines: Vec<Line> = log
+        .entries
+        .iter()
+        .map(|(at, message)| {
+            let secs = at.as_secs();
+            Line::from(vec![
+                Span::styled(
+                    format!(
+                        "[{:02}:{:02}:{:02}] ",
+                        secs / 3600,
+                        secs / 60 % 60,
+                        secs % 60
+                    ),
+                    Style::default().add_modifier(Modifier::DIM),
+                ),
+                Span::raw(message.as_str()),
+            ])
+        })
+        .collect();
+
+    f.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .scroll((log.scroll, 0)),
+        inner_with_borders(popup),
+    );
+}
+
+/// The color picker lays the 256 xterm colors out as 16 rows of 16 swatches.
+const PICKER_COLUMNS: u16 = 16;
+const PICKER_SWATCH_WIDTH: u16 = 3;
+
+/// The color picker popup and the palette index of each swatch that fits inside it.
+fn color_picker_swatches(area: Rect) -> (Rect, Vec<(Rect, u8)>) {
+    let popup = centered_rect(
+        PICKER_COLUMNS * PICKER_SWATCH_WIDTH + 2,
+        256 / PICKER_COLUMNS + 2,
+        area,
+    );
+    let inner = inner_with_borders(popup);
+    let swatches = (0..=u8::MAX)
+        .filter_map(|index| {
+            let x = inner.x + u16::from(index) % PICKER_COLUMNS * PICKER_SWATCH_WIDTH;
+            let y = inner.y + u16::from(index) / PICKER_COLUMNS;
+            (x + PICKER_SWATCH_WIDTH <= inner.right() && y < inner.bottom())
+                .then(|| (Rect::new(x, y, PICKER_SWATCH_WIDTH, 1), index))
+        })
+        .collect();
+    (popup, swatches)
+}
+
+/// The palette index of the color picker swatch under a terminal position.
+pub fn color_picker_swatch_at(ui: &UiState, column: u16, row: u16) -> Option<u8> {
+    let (_, swatches) = color_picker_swatches(ui.terminal);
+    swatches
+        .into_iter()
+        .find(|(rect, _)| rect_contains(*rect, column, row))
+        .map(|(_, index)| index)
+}
+
+fn render_color_picker(f: &mut Frame, area: Rect, selected: u8) {
+    let (popup, swatches) = color_picker_swatches(area);
+    let color = PaintColor::xterm(selected);
+
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Block::default()
+            .title(" Colors - click or arrows + Enter, Esc closes ")
+            .title_bottom(format!(" {selected}: {} ", color.name()))
+            .borders(Borders::ALL),
+        popup,
+    );
+
+    // The highlighted swatch is bracketed in black or white, whichever stands out.
+    let buf = f.buffer_mut();
+    for (rect, index) in swatches {
+        let swatch = PaintColor::xterm(index);
+        let mut style = Style::default().bg(swatch.to_ratatui());
+        let label = if index == selected {
+            let marker = if swatch.luminance() > 128 {
+                Color::Black
+            } else {
+                Color::White
+            };
+            style = style.fg(marker).add_modifier(Modifier::BOLD);
+            "[ ]"
+        } else {
+            "   "
+        };
+        buf.set_string(rect.x, rect.y, label, style);
+    }
+}
+
+fn render_stats(f: &mut Frame, area: Rect, stats: &StatsView) {
+    let canvas = &stats.canvas;
+    let percent = canvas.painted as f64 * 100.0 / canvas.cells.max(1) as f64;
+    let rows = [
+        ("Size", format!("{} x {}", canvas.width, canvas.height)),
+        ("Cells", canvas.cells.to_string()),
+        ("Painted", format!("{} ({percent:.1}%)", canvas.painted)),
+        ("Colors", canvas.colors.to_string()),
+        ("Canvas memory", format_bytes(canvas.bytes)),
+        (
+            "Undo history",
+            format!(
+                "{} undo / {} redo steps, {}",
+                stats.undo_len,
+                stats.redo_len,
+                format_bytes(stats.history_bytes)
+            ),
+        ),
+    ];
+
+    let popup = centered_rect(56, rows.len() as u16 + 2, area);
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Block::default()
+            .title(" Canvas stats - any key closes ")
+            .borders(Borders::ALL),
+        popup,
+    );
+
+    let lines: Vec<Line> = rows
+        .into_iter()
+        .map(|(label, value)| {
+            Line::from(vec![
+                Span::styled(format!("{label:<14}"), Style::default().fg(Color::Yellow)),
+                Span::raw(value),
+            ])
+        })
+        .collect();
+    f.render_widget(Paragraph::new(lines), inner_with_borders(popup));
+}
+
+/// A byte count in the largest unit that keeps it at or above 1.
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+fn render_list(f: &mut Frame, area: Rect, list: &ListView) {
+    let width = area.width.clamp(20, 80);
+    let height = (list.items.len() as u16 + 2).min(area.height.max(3));
+    let popup = centered_rect(width, height, area);
+
+    f.render_widget(Clear, popup);
+    f.render_widget(
+        Block::default().title(list.title).borders(Borders::ALL),
+        popup,
+    );
+
+    // Scroll just far enough to keep the highlighted entry on screen.
+    let inner = inner_with_borders(popup);
+    let visible = inner.height.max(1) as usize;
+    let first = list.selected.saturating_sub(visible - 1);
+    let lines: Vec<Line> = list
+        .items
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(visible)
+        .map(|(index, item)| {
+            let style = if index == list.selected {
+                Style::default().add_modifier(Modifier::REVERSED | Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+            Line::from(Span::styled(item.as_str(), style))
+        })
+        .collect();
+
+    f.render_widget(Paragraph::new(lines), inner);
+}
+
 fn build_tool_hits(area: Rect) -> Vec<(Rect, Tool)> {
     let mut hits = Vec::new();
     let mut x = area.x;
@@ -434,7 +1771,15 @@ fn build_brush_hits(area: Rect) -> Vec<(Rect, char)> {
     hits
 }
 
-fn build_color_hits(area: Rect) -> Vec<(Rect, PaintColor)> {
+/// The width the color row needs to show the default color and every palette entry.
+fn color_area_width(palette: &[PaintColor]) -> u16 {
+    let labels = std::iter::once(color_button_label_default())
+        .chain((1..=palette.len()).map(color_button_label_index));
+    let width: usize = "Color ".len() + labels.map(|label| label.len() + 1).sum::<usize>();
+    u16::try_from(width).unwrap_or(u16::MAX)
+}
+
+fn build_color_hits(area: Rect, palette: &[PaintColor]) -> Vec<(Rect, PaintColor)> {
     let mut hits = Vec::new();
     let mut x = area.x.saturating_add("Color ".chars().count() as u16);
     let y = area.y;
@@ -447,7 +1792,7 @@ fn build_color_hits(area: Rect) -> Vec<(Rect, PaintColor)> {
         x = x.saturating_add(default_w + 1);
     }
 
-    for (idx, color) in PaintColor::quick_palette().iter().copied().enumerate() {
+    for (idx, color) in palette.iter().copied().enumerate() {
         let label = color_button_label_index(idx + 1);
         let w = label.chars().count() as u16;
         if x.saturating_add(w) > right {
@@ -514,3 +1859,63 @@ fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
     let y = area.y + area.height.saturating_sub(h) / 2;
     Rect::new(x, y, w, h)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn help_lists_every_action() {
+        let listed: Vec<Action> = HELP
+            .iter()
+            .flat_map(|(_, rows)| rows.iter())
+            .filter_map(|(keys, _)| match keys {
+                Actions(actions) => Some(*actions),
+                Fixed(_) => None,
+            })
+            .flatten()
+            .copied()
+            .collect();
+        for action in crate::keymap::all_actions() {
+            assert!(
+                listed.contains(&action),
+                "{} has no help row",
+                action.name()
+            );
+        }
+    }
+
+    #[test]
+    fn help_keys_follow_the_key_map() {
+        let defaults = KeyMap::default();
+        let undo = Actions(&[Action::Undo]);
+        assert_eq!(help_keys(&undo, &defaults), "u or Ctrl+Z");
+        let cursor = Actions(&[
+            Action::CursorLeft,
+            Action::CursorRight,
+            Action::CursorUp,
+            Action::CursorDown,
+        ]);
+        assert_eq!(help_keys(&cursor, &defaults), "Arrows");
+        let zoom = Actions(&[Action::ZoomIn, Action::ZoomOut]);
+        assert_eq!(help_keys(&zoom, &defaults), "+ or = / -");
+
+        let custom = KeyMap::from_toml("undo = \"F5\"\nzoom_out = []\n").unwrap();
+        assert_eq!(help_keys(&undo, &custom), "F5");
+        assert_eq!(help_keys(&zoom, &custom), "+ or = / (unbound)");
+    }
+
+    #[test]
+    fn status_hints_follow_the_key_map() {
+        let defaults = status_hints(&KeyMap::default());
+        assert!(
+            defaults.starts_with("q quit, u or Ctrl+Z undo"),
+            "{defaults}"
+        );
+
+        let custom = KeyMap::from_toml("undo = \"F5\"\nsave = []\n").unwrap();
+        let hints = status_hints(&custom);
+        assert!(hints.contains("F5 undo"), "{hints}");
+        assert!(!hints.contains("save"), "{hints}");
+    }
+}